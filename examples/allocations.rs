@@ -0,0 +1,75 @@
+//! Counts the heap allocations made while tokenizing and parsing
+//!
+//! Run with `cargo run --release --example allocations`.
+//!
+//! The lexer borrows identifiers from the input and the parser pulls the tokens from it one at a time,
+//! so tokenizing allocates nothing. Collecting the tokens into a vector, like the parser did before,
+//! is shown for comparison.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use pemel::prelude::*;
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const INPUT: &str = "sin(alpha)^2 + cos(alpha)^2 * (radius - offset_x) / sqrt(width * height) + log(2, value) - gamma(n)";
+const ITERATIONS: u32 = 10_000;
+
+/// Returns the number of allocations made by one run and the average time of a run
+fn measure<T>(f: impl Fn() -> T) -> (usize, f64) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    black_box(f());
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(f());
+    }
+    let micros = start.elapsed().as_secs_f64() * 1e6 / ITERATIONS as f64;
+
+    (allocations, micros)
+}
+
+fn main() {
+    let cases: [(&str, &dyn Fn()); 4] = [
+        ("lex lazily", &|| {
+            black_box(Lexer::new(black_box(INPUT)).count());
+        }),
+        ("collect tokens", &|| {
+            black_box(Lexer::new(black_box(INPUT)).collect::<Result<Vec<_>, _>>().ok());
+        }),
+        ("parse", &|| {
+            black_box(Expr::parse(black_box(INPUT), false).ok());
+        }),
+        ("parse and evaluate", &|| {
+            black_box(Expr::parse(black_box(INPUT), true).ok());
+        }),
+    ];
+
+    println!("input: {}\n", INPUT);
+    println!("{:<20} {:>12} {:>12}", "", "allocations", "time (µs)");
+
+    for (name, f) in cases {
+        let (allocations, micros) = measure(f);
+        println!("{:<20} {:>12} {:>12.2}", name, allocations, micros);
+    }
+}
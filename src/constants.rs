@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::f32::consts;
+
+use crate::names::CaseIndex;
+
+/// Named constants recognized by the parser
+///
+/// The default table contains the built-in constants `pi`, `e`, `tau`, `phi` and `inf`,
+/// you can register your own on top of them:
+///
+/// ```
+/// use pemel::prelude::*;
+///
+/// let mut options = ParseOptions::default();
+/// options.constants.register("g", 9.81);
+///
+/// let expr = Expr::parse_with("g * t^2 / 2", &options).unwrap();
+/// assert_eq!(expr.to_string(), "g * t^2 / 2");
+/// assert_eq!(expr.eval_with_var("t", 2.0), Ok(19.62));
+/// ```
+///
+/// Constants are parsed as `Expr::Const`, which keeps the name for printing,
+/// and they are treated as constant by the implicit evaluation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constants {
+    table: HashMap<String, f32>,
+    names: CaseIndex,
+}
+
+impl Constants {
+    pub const BUILTIN: [(&'static str, f32); 5] = [
+        ("pi", consts::PI),
+        ("e", consts::E),
+        ("tau", consts::TAU),
+        ("phi", 1.618_034),
+        ("inf", f32::INFINITY),
+    ];
+
+    /// Table without any constants, not even the built-in ones
+    pub fn empty() -> Self {
+        Constants {
+            table: HashMap::new(),
+            names: CaseIndex::default(),
+        }
+    }
+
+    /// Registers the constant, replacing the previous one with the same name
+    pub fn register(&mut self, name: impl Into<String>, value: f32) {
+        let name = name.into();
+        self.names.insert(&name);
+        self.table.insert(name, value);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<f32> {
+        self.names.remove(name);
+        self.table.remove(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<f32> {
+        self.table.get(name).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, f32)> {
+        self.table.iter().map(|(name, value)| (name.as_str(), *value))
+    }
+
+    /// Returns the name of the constant with the given lowercase name, for the case insensitive parsing
+    pub(crate) fn get_ignore_case(&self, lowercase: &str) -> Option<&str> {
+        self.names.get(lowercase)
+    }
+}
+
+impl Default for Constants {
+    fn default() -> Self {
+        let mut constants = Self::empty();
+
+        for (name, value) in Self::BUILTIN {
+            constants.register(name, value);
+        }
+
+        constants
+    }
+}
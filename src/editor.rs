@@ -0,0 +1,443 @@
+use crate::function::Arity;
+use crate::parser::{builtin_function, ParseOptions, Span, SyntaxTree, Token, BUILTIN_FUNCTIONS};
+
+/// Most evaluation steps `Document::hover` takes to compute the value of the hovered expression
+const HOVER_EVAL_BUDGET: usize = 10_000;
+
+/// Source text of a formula editor, answering the queries the editor makes while the text is typed
+///
+/// The text is tokenized once, the queries take byte offsets into it, like `Span`.
+/// Unlike parsing, the queries work with incomplete or erroneous text, tokens that can't be read are skipped.
+/// The text can be a single expression or a `Program`, names bound by its statements are known to the queries.
+///
+/// ```
+/// use pemel::prelude::*;
+///
+/// let options = ParseOptions::default();
+/// let document = Document::new("log(2, 8) * x", &options);
+///
+/// let kinds: Vec<_> = document.semantic_tokens().iter().map(|token| token.kind).collect();
+/// assert_eq!(kinds[0], SemanticKind::Function);
+/// assert_eq!(kinds[1], SemanticKind::Paren { depth: 0 });
+///
+/// let completions = document.completions(1);
+/// assert_eq!(completions[0].label, "lgamma");
+/// assert_eq!(completions[1].label, "ln");
+/// assert_eq!(completions[2].kind, CompletionKind::Function(Arity::Range { min: 1, max: 2 }));
+///
+/// let hover = document.hover(1).unwrap();
+/// assert_eq!(hover.signature.as_deref(), Some("log(x), log(base, x)"));
+/// assert_eq!(hover.value, Some(3.0));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Document<'a> {
+    input: &'a str,
+    options: &'a ParseOptions,
+    tokens: Vec<(Token<'a>, Span)>,
+    bindings: Vec<Binding>,
+}
+
+/// Name bound by a statement of the document, `a = 2` or `f(x) = x^2`
+#[derive(Debug, Clone)]
+struct Binding {
+    name: String,
+    /// Parameters of a function definition, `None` for a bound value
+    params: Option<Vec<String>>,
+    /// Part of the statement where the parameters can be used, from the parameter list to the end of the body
+    body: Span,
+}
+
+/// Classification of a token for highlighting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SemanticKind {
+    Number,
+    /// Variable, parameter of a function definition or a name bound in a program
+    Variable,
+    Constant,
+    /// Name of a built-in, registered or defined function
+    Function,
+    /// Operators, bars of the absolute value and the separators `,`, `;`, `=`, `?` and `:`
+    Operator,
+    /// Parenthesis with the number of parentheses around it, the outermost pair is at depth 0
+    Paren { depth: usize },
+}
+
+/// Token of the document with its classification
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SemanticToken {
+    pub span: Span,
+    pub kind: SemanticKind,
+}
+
+/// What the completed name is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompletionKind {
+    Function(Arity),
+    Variable,
+    Constant(f32),
+}
+
+/// Name that can be written at the cursor
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+    /// Signature of the function or value of the constant, empty for variables
+    pub detail: String,
+    /// Part of the input replaced by the label, the identifier up to the cursor
+    pub span: Span,
+}
+
+/// Information about the token under the cursor
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hover {
+    /// The hovered token, or the expression it belongs to if the expression is constant
+    pub span: Span,
+    /// Signature of the hovered function, like `log(x), log(base, x)`
+    pub signature: Option<String>,
+    /// Value of the hovered number or constant, or of the constant expression around the hovered operator
+    pub value: Option<f32>,
+}
+
+impl<'a> Document<'a> {
+    pub fn new(input: &'a str, options: &'a ParseOptions) -> Self {
+        let tokens: Vec<_> = options
+            .lexer(input)
+            .filter_map(Result::ok)
+            .filter(|(token, _)| *token != Token::EOF)
+            .collect();
+
+        let bindings = statements(&tokens)
+            .filter_map(|statement| binding(statement, options))
+            .collect();
+
+        Document {
+            input,
+            options,
+            tokens,
+            bindings,
+        }
+    }
+
+    /// Returns the classified tokens in the order they are written
+    ///
+    /// The `^` of a superscript exponent has no text, so it has no semantic token.
+    pub fn semantic_tokens(&self) -> Vec<SemanticToken> {
+        let mut depth = 0usize;
+        let mut tokens = vec![];
+
+        for (index, &(token, span)) in self.tokens.iter().enumerate() {
+            let kind = match token {
+                Token::Newline => continue,
+                _ if span.is_empty() => continue,
+                Token::Number(_) => SemanticKind::Number,
+                Token::Ident(name) => self.name_kind(index, name, span),
+                Token::LParen => {
+                    depth += 1;
+                    SemanticKind::Paren { depth: depth - 1 }
+                }
+                Token::RParen => {
+                    depth = depth.saturating_sub(1);
+                    SemanticKind::Paren { depth }
+                }
+                _ => SemanticKind::Operator,
+            };
+
+            tokens.push(SemanticToken { span, kind });
+        }
+
+        tokens
+    }
+
+    /// Returns the names that can be written at the cursor, sorted by the label
+    ///
+    /// The names start with the part of the identifier before the cursor, all names are returned if
+    /// the cursor is not at an identifier. The candidates are the functions and constants known to
+    /// the options, the allowed variables, the names bound before the cursor, the parameters of
+    /// the definition the cursor is in and the variables used elsewhere in the document.
+    pub fn completions(&self, offset: usize) -> Vec<Completion> {
+        let span = self
+            .tokens
+            .iter()
+            .find(|(token, span)| matches!(token, Token::Ident(_)) && span.start < offset && offset <= span.end)
+            .map_or(Span::new(offset, offset), |(_, span)| Span::new(span.start, offset));
+
+        let options = self.options;
+        let mut candidates = vec![];
+        let mut push = |label: &str, kind, detail| candidates.push((label.to_string(), kind, detail));
+
+        for binding in &self.bindings {
+            match &binding.params {
+                Some(params) if contains(binding.body, span) => {
+                    params.iter().for_each(|param| push(param, CompletionKind::Variable, String::new()));
+                }
+                Some(params) if binding.body.end <= span.start => {
+                    let arity = CompletionKind::Function(Arity::Fixed(params.len()));
+                    push(&binding.name, arity, definition_signature(&binding.name, params));
+                }
+                None if binding.body.end <= span.start => push(&binding.name, CompletionKind::Variable, String::new()),
+                _ => {}
+            }
+        }
+
+        for &(name, arity, signature) in &BUILTIN_FUNCTIONS {
+            push(name, CompletionKind::Function(arity), signature.to_string());
+        }
+
+        for definition in options.scope.iter() {
+            let arity = CompletionKind::Function(Arity::Fixed(definition.params.len()));
+            push(&definition.name, arity, definition_signature(&definition.name, &definition.params));
+        }
+
+        for function in options.functions.iter() {
+            let arity = function.arity();
+            push(function.name(), CompletionKind::Function(arity), signature(function.name(), arity));
+        }
+
+        for (name, value) in options.constants.iter() {
+            push(name, CompletionKind::Constant(value), value.to_string());
+        }
+
+        for name in options.allowed_variables.iter().flatten() {
+            push(name, CompletionKind::Variable, String::new());
+        }
+
+        for (index, &(token, token_span)) in self.tokens.iter().enumerate() {
+            let Token::Ident(name) = token else {
+                continue;
+            };
+
+            let name = options.canonical_name(name);
+            let is_variable = self.name_kind(index, &name, token_span) == SemanticKind::Variable;
+
+            // Parameters are offered only in their own definition, the identifier being typed is not offered
+            if is_variable
+                && options.is_variable_allowed(&name)
+                && !self.is_param(&name, token_span)
+                && token_span.start != span.start
+            {
+                push(&name, CompletionKind::Variable, String::new());
+            }
+        }
+
+        let prefix = self.input.get(span.start..span.end).unwrap_or_default();
+        let prefix = options.canonical_name(prefix);
+        let lowercase = |s: &str| s.chars().flat_map(char::to_lowercase).collect::<String>();
+
+        candidates.retain(|(label, kind, _)| {
+            let allowed = match kind {
+                CompletionKind::Function(_) => options.is_function_allowed(label),
+                _ => true,
+            };
+
+            let matches = match options.case_insensitive {
+                true => lowercase(label).starts_with(&lowercase(&prefix)),
+                false => label.starts_with(prefix.as_ref()),
+            };
+
+            allowed && matches
+        });
+
+        // The sort is stable, so of the candidates with the same label the one found first is kept
+        candidates.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+        candidates.dedup_by(|(a, ..), (b, ..)| a == b);
+
+        candidates
+            .into_iter()
+            .map(|(label, kind, detail)| Completion {
+                label,
+                kind,
+                detail,
+                span,
+            })
+            .collect()
+    }
+
+    /// Returns the signature of the function or the value of the constant under the cursor
+    ///
+    /// The token under the cursor is the one containing the offset, or the one ending at it.
+    /// If the token is part of a constant expression, like the `+` in `2 + 3` or the name in `sqrt(4)`,
+    /// the value is the value of the innermost such expression. Values are evaluated only if the document
+    /// is a single valid expression, otherwise only the values of numbers and constants are known.
+    /// Expressions calling registered functions are not evaluated, the functions can be slow or impure,
+    /// and the evaluation stops after 10 000 steps.
+    pub fn hover(&self, offset: usize) -> Option<Hover> {
+        let visible = |&(token, span): &(Token, Span)| token != Token::Newline && !span.is_empty();
+        let index = self
+            .tokens
+            .iter()
+            .position(|token| visible(token) && token.1.start <= offset && offset < token.1.end)
+            .or_else(|| self.tokens.iter().position(|token| visible(token) && token.1.end == offset))?;
+
+        let (token, span) = self.tokens[index];
+        let kind = match token {
+            Token::Ident(name) => Some(self.name_kind(index, name, span)),
+            _ => None,
+        };
+
+        let signature = match token {
+            Token::Ident(name) if kind == Some(SemanticKind::Function) => self.signature(name),
+            _ => None,
+        };
+
+        let tree = SyntaxTree::parse(self.input, self.options).ok();
+        let node = tree.as_ref().and_then(|tree| tree.root.find(span));
+        let value = node.and_then(|node| {
+            let expr = node.to_expr(self.options).ok()?;
+            match expr.calls_only_builtins() {
+                true => expr.eval_with_budget(&[], HOVER_EVAL_BUDGET).ok(),
+                false => None,
+            }
+        });
+
+        let (span, value) = match (node, value, token) {
+            (Some(node), Some(value), _) => (node.span(), Some(value)),
+            (_, _, Token::Number(value)) => (span, Some(value)),
+            (_, _, Token::Ident(name)) if kind == Some(SemanticKind::Constant) => {
+                (span, self.options.constants.get(&self.options.canonical_name(name)))
+            }
+            _ => (span, None),
+        };
+
+        if signature.is_none() && value.is_none() {
+            return None;
+        }
+
+        Some(Hover { span, signature, value })
+    }
+
+    /// Classifies the identifier the same way the parser resolves it
+    fn name_kind(&self, index: usize, name: &str, span: Span) -> SemanticKind {
+        let options = self.options;
+        let name = options.canonical_name(name);
+        let is_call = matches!(self.tokens.get(index + 1), Some((Token::LParen, _)));
+
+        let is_function = options.is_function(&name)
+            || self.bindings.iter().any(|binding| binding.name == name && binding.params.is_some());
+
+        if self.is_param(&name, span) {
+            SemanticKind::Variable
+        } else if is_function && (is_call || options.implicit_multiplication) {
+            SemanticKind::Function
+        } else if options.constants.get(&name).is_some() {
+            SemanticKind::Constant
+        } else {
+            SemanticKind::Variable
+        }
+    }
+
+    /// Returns true if the name at the span is a parameter of the definition it is in
+    fn is_param(&self, name: &str, span: Span) -> bool {
+        let name = self.options.canonical_name(name);
+
+        self.bindings.iter().any(|binding| match &binding.params {
+            Some(params) => contains(binding.body, span) && params.iter().any(|param| *param == name),
+            None => false,
+        })
+    }
+
+    /// Returns the signature of the function, built-in functions can't be redefined and definitions take
+    /// precedence over the registered functions
+    fn signature(&self, name: &str) -> Option<String> {
+        let options = self.options;
+        let name = options.canonical_name(name);
+
+        if let Some((_, signature)) = builtin_function(&name) {
+            return Some(signature.to_string());
+        }
+
+        let defined = self
+            .bindings
+            .iter()
+            .find_map(|binding| binding.params.as_ref().filter(|_| binding.name == name))
+            .or_else(|| options.scope.get(&name).map(|definition| &definition.params));
+
+        if let Some(params) = defined {
+            return Some(definition_signature(&name, params));
+        }
+
+        options
+            .functions
+            .get(&name)
+            .map(|function| signature(&name, function.arity()))
+    }
+}
+
+/// Splits the tokens into the statements of a program, new lines inside parentheses don't end a statement
+fn statements<'t, 'a>(tokens: &'t [(Token<'a>, Span)]) -> impl Iterator<Item = &'t [(Token<'a>, Span)]> {
+    let mut depth = 0usize;
+
+    tokens.split(move |(token, _)| {
+        match token {
+            Token::LParen => depth += 1,
+            Token::RParen => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+
+        *token == Token::Semicolon || (*token == Token::Newline && depth == 0)
+    })
+}
+
+/// Returns the name bound by the statement, `name = expr` or `name(param, ...) = expr`
+fn binding(statement: &[(Token, Span)], options: &ParseOptions) -> Option<Binding> {
+    let statement: Vec<_> = statement.iter().filter(|(token, _)| *token != Token::Newline).collect();
+    let equals = statement.iter().position(|(token, _)| *token == Token::Equals)?;
+    let head: Vec<_> = statement[..equals].iter().map(|(token, _)| *token).collect();
+    let end = statement.last()?.1.end;
+
+    let (name, params) = match head.as_slice() {
+        [Token::Ident(name)] => (name, None),
+        [Token::Ident(name), Token::LParen, Token::RParen] => (name, Some(vec![])),
+        [Token::Ident(name), Token::LParen, params @ .., Token::RParen] => {
+            let params = params
+                .split(|token| *token == Token::Comma)
+                .map(|param| match param {
+                    [Token::Ident(param)] => Some(options.canonical_name(param).into_owned()),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+
+            (name, Some(params))
+        }
+        _ => return None,
+    };
+
+    // A bound value has no parameters, its body is empty so the name is visible from the end of the statement
+    let start = match params {
+        Some(_) => statement[1].1.start,
+        None => end,
+    };
+
+    Some(Binding {
+        name: options.canonical_name(name).into_owned(),
+        params,
+        body: Span::new(start, end),
+    })
+}
+
+fn contains(outer: Span, inner: Span) -> bool {
+    outer.start <= inner.start && inner.end <= outer.end
+}
+
+fn definition_signature(name: &str, params: &[String]) -> String {
+    format!("{}({})", name, params.join(", "))
+}
+
+/// Returns the signature of a registered function, its parameters have no names
+fn signature(name: &str, arity: Arity) -> String {
+    let params = |count: usize| match count {
+        1 => "x".to_string(),
+        _ => (1..=count).map(|i| format!("x{}", i)).collect::<Vec<_>>().join(", "),
+    };
+
+    match arity {
+        Arity::Fixed(count) => format!("{}({})", name, params(count)),
+        Arity::Variadic { min: 0 } => format!("{}(...)", name),
+        Arity::Variadic { min } => format!("{}({}, ...)", name, params(min)),
+        Arity::Range { min, max } if max.saturating_sub(min) > 2 => format!("{}({}, ...)", name, params(min.max(1))),
+        Arity::Range { min, max } => (min..=max)
+            .map(|count| format!("{}({})", name, params(count)))
+            .collect::<Vec<_>>()
+            .join(", "),
+    }
+}
@@ -1,3 +1,5 @@
+use std::fmt::Display;
+
 /// This represents an error that occurs during evaluation of an expression
 /// 
 /// `ParseError` can contain this error if the parser tries to evaluate an expression during parsing.
@@ -7,4 +9,34 @@ pub enum EvalError {
     DivisionByZero,
     InvalidExponentiation,
     InvalidLogarithm,
+    /// Factorial of a negative integer, or gamma of a non-positive integer
+    InvalidFactorial,
+    /// `nCr` or `nPr` of a negative or non-integer number
+    InvalidCombination,
+    /// The expression contains `Expr::Error` placeholder left by the recovering parser
+    ErrorNode,
+    /// Function was called with wrong number of arguments, this can happen only if the call wasn't parsed
+    WrongNumberOfArgs(String, usize),
+    /// Evaluation took more steps than the budget given to `Expr::eval_with_budget`
+    BudgetExceeded,
 }
+
+impl Display for EvalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EvalError::VariableNotDefined(var) => write!(f, "variable `{}` is not defined", var),
+            EvalError::DivisionByZero => write!(f, "division by zero"),
+            EvalError::InvalidExponentiation => write!(f, "invalid exponentiation"),
+            EvalError::InvalidLogarithm => write!(f, "invalid logarithm"),
+            EvalError::InvalidFactorial => write!(f, "factorial of a negative integer"),
+            EvalError::InvalidCombination => write!(f, "combinations of negative or non-integer numbers"),
+            EvalError::ErrorNode => write!(f, "expression contains a syntax error"),
+            EvalError::WrongNumberOfArgs(name, n) => {
+                write!(f, "function `{}` can't be called with {} arguments", name, n)
+            }
+            EvalError::BudgetExceeded => write!(f, "evaluation budget exceeded"),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
@@ -1,8 +1,15 @@
 use std::fmt::Display;
+use std::sync::Arc;
 
 use crate::eval_error::EvalError;
+use crate::function::Function;
 use crate::macros::expr_pat;
 use crate::parser;
+use crate::printer::PrintOptions;
+use crate::special;
+
+/// Precedence of the prefix operators `-` and `!`, see `Expr::precedence`
+pub(crate) const PREFIX_PRECEDENCE: u8 = 13;
 
 /// Represensts a mathematical expression
 ///
@@ -22,6 +29,8 @@ use crate::parser;
 ///
 /// This behavior can be unexpected, so it can be disabled by setting the `implicit_evaluation` parameter to `false`.
 ///
+/// Other parser features, like implicit multiplication, are enabled through `ParseOptions` passed to `parse_with`.
+///
 /// ## Evaluation
 ///
 /// You can evaluate the expression with 0 or 1 variable using the `eval_const` or `eval_with_variable` method.
@@ -45,23 +54,80 @@ use crate::parser;
 /// If you try to substitute into the variable that is being derivated, it will use 'delayed' substitution.
 /// 
 /// 'delayed' substitution is evaluated only when the derivative is evaluated.
+///
+/// ## Truth values
+///
+/// Comparisons and logical operators evaluate to `1` for true and `0` for false,
+/// any value other than `0` is true when used as a condition.
+///
+/// `&&`, `||` and conditionals evaluate their operands lazily,
+/// so `x != 0 ? 1 / x : 0` is not a division by zero for `x = 0`.
+/// Implicit evaluation reports errors of the constant parts only if they are evaluated,
+/// so `x > 0 ? 1 : 1 / 0` and `0 && 1 / 0` parse with it, but `1 > 0 ? 1 / 0 : 1` doesn't.
+///
+/// Equality is exact, `0.1 + 0.2 == 0.3` depends on the rounding of `f32`.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Num(f32),
     Var(String),
+    /// Named constant like `pi`, it is evaluated as the value but printed as the name
+    Const(String, f32),
     Add(Box<Expr>, Box<Expr>),
     Sub(Box<Expr>, Box<Expr>),
     Mul(Box<Expr>, Box<Expr>),
     Div(Box<Expr>, Box<Expr>),
+    /// Remainder of the floor division, it has the sign of the divisor, so `-7 % 3` is `2`
+    Mod(Box<Expr>, Box<Expr>),
+    /// Division rounded towards negative infinity, `a // b * b + a % b` is `a`
+    FloorDiv(Box<Expr>, Box<Expr>),
     Pow(Box<Expr>, Box<Expr>),
     Log(Box<Expr>, Box<Expr>),
     Sin(Box<Expr>),
     Cos(Box<Expr>),
     Tan(Box<Expr>),
     Cot(Box<Expr>),
+    /// Angle in degrees, evaluated in radians, the argument of `sin`, `cos`, `tan` and `cot` with `AngleUnit::Degrees`
+    ///
+    /// It is printed as the angle alone, so the output parses back to the same expression with the same options.
+    Degrees(Box<Expr>),
     Abs(Box<Expr>),
+    Floor(Box<Expr>),
+    Ceil(Box<Expr>),
+    /// Rounds half away from zero, so `round(-2.5)` is `-3`
+    Round(Box<Expr>),
+    Trunc(Box<Expr>),
+    /// Fractional part with the sign of the argument, `x - trunc(x)`
+    Frac(Box<Expr>),
+    /// `-1`, `0` or `1`
+    Sign(Box<Expr>),
+    /// Postfix `x!`, defined for all reals except negative integers as `gamma(x + 1)`
+    Factorial(Box<Expr>),
+    Gamma(Box<Expr>),
+    /// Natural logarithm of the absolute value of gamma, `lgamma(x)`
+    LnGamma(Box<Expr>),
+    /// Combinations `nCr(n, k)`, also written as `binomial(n, k)`
+    Comb(Box<Expr>, Box<Expr>),
+    /// Permutations `nPr(n, k)`
+    Perm(Box<Expr>, Box<Expr>),
+    Lt(Box<Expr>, Box<Expr>),
+    Le(Box<Expr>, Box<Expr>),
+    Eq(Box<Expr>, Box<Expr>),
+    Ne(Box<Expr>, Box<Expr>),
+    Gt(Box<Expr>, Box<Expr>),
+    Ge(Box<Expr>, Box<Expr>),
+    /// The right side is evaluated only if the left one is true
+    And(Box<Expr>, Box<Expr>),
+    /// The right side is evaluated only if the left one is false
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    /// Condition, value if true and value if false, only one of the branches is evaluated
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
     // The last argument is possible substitute for the variable
     Derivative(Box<Expr>, String, Option<Box<Expr>>),
+    /// Call of a user defined function, see `FunctionRegistry`
+    Call(Arc<Function>, Vec<Expr>),
+    /// Placeholder for the part of the input that failed to parse, see `Expr::parse_recovering`
+    Error,
 }
 
 impl Default for Expr {
@@ -73,9 +139,74 @@ impl Default for Expr {
 impl Expr {
     const DX: f32 = 0.001;
 
+    /// Parse the expression from a string
+    ///
+    /// Any invalid input is reported as a `ParseError`. The parser is recursive, so without a limit
+    /// deeply nested input like `((((...))))` can overflow the stack, untrusted input should be parsed
+    /// with `parse_with` and `ParseOptions::max_depth` set.
     pub fn parse(input: &str, implicit_evaluation: bool) -> Result<Expr, parser::ParseError> {
-        let tokens = parser::tokenize(input)?;
-        parser::parse(tokens, implicit_evaluation)
+        let options = parser::ParseOptions {
+            implicit_evaluation,
+            ..Default::default()
+        };
+
+        Self::parse_with(input, &options)
+    }
+
+    /// Parse the expression from a string with the given options
+    ///
+    /// Like `parse`, it can overflow the stack on deeply nested input unless `options.max_depth` is set.
+    pub fn parse_with(input: &str, options: &parser::ParseOptions) -> Result<Expr, parser::ParseError> {
+        options.check_length(input)?;
+        parser::parse(options.lexer(input), options)
+    }
+
+    /// Parse the expression written in LaTeX, like `\frac{1}{2} \cdot x^{2}`
+    ///
+    /// The result is the same as for the equivalent text input, so `\sqrt{x}` is parsed like `sqrt(x)`.
+    /// Each letter is a separate variable, so `2xy` needs implicit multiplication enabled in the options.
+    ///
+    /// Supported are fractions, powers, `\sqrt` with optional index, `\sin`, `\cos`, `\tan`, `\cot`, `\ln`, `\exp`,
+    /// `\log` with optional base `\log_{b}`, `\left( \right)`, `\left| \right|`, `\cdot`, `\times`, `\div`, `\bmod`,
+    /// `\lfloor \rfloor`, `\lceil \rceil`, `\binom{n}{k}`, `{}_{n}P_{k}`, `\Gamma(x)`, `\ln\Gamma(x)`,
+    /// `\operatorname{sgn}`, comparisons, `\land`, `\lor`, `\lnot`, `\begin{cases}`, `\pi`, `\tau`, `\varphi`,
+    /// `\infty`, Greek letters and derivatives `\frac{d}{dx}`, also at a point `\left. ... \right|_{x=a}`.
+    /// The floor of one fraction, `\lfloor \frac{a}{b} \rfloor`, is the floor division `a // b`.
+    ///
+    /// Commands, scripts and groups nested more than 128 levels deep are `ParseError::TooDeep`
+    /// even without `options.max_depth`, so the translation doesn't overflow the stack.
+    pub fn parse_latex(input: &str, options: &parser::ParseOptions) -> Result<Expr, parser::ParseError> {
+        options.check_length(input)?;
+        let tokens = parser::tokenize_latex(input, options)?;
+        parser::parse(tokens.into_iter().map(Ok), options)
+    }
+
+    /// Parse the expression written in content MathML, like `<apply><plus/><ci>x</ci><cn>1</cn></apply>`
+    ///
+    /// Supported are the arithmetic, relational and logical operators, `<root/>` with `<degree>`,
+    /// `<log/>` with `<logbase>`, `<ln/>`, trigonometric functions, `<abs/>`, `<floor/>`, `<ceiling/>`,
+    /// `<factorial/>`, `<piecewise>`, `<diff/>` with `<bvar>` and the constants `<pi/>`, `<exponentiale/>`
+    /// and `<infinity/>`. Functions named by `<ci>` or `<csymbol>` are resolved like in the text syntax.
+    ///
+    /// The derivative at a point is written as the derivative of `<lambda>` applied to the point,
+    /// see `to_content_mathml`.
+    pub fn parse_content_mathml(input: &str, options: &parser::ParseOptions) -> Result<Expr, parser::ParseError> {
+        options.check_length(input)?;
+        parser::parse_content_mathml(input, options)
+    }
+
+    /// Parse the expression from a string, reporting all syntax errors instead of just the first one
+    ///
+    /// Parts of the input that failed to parse are replaced with `Expr::Error` placeholders,
+    /// so the valid parts can still be inspected or evaluated.
+    ///
+    /// The expression is `None` only if nothing could be parsed at all.
+    pub fn parse_recovering(input: &str, options: &parser::ParseOptions) -> (Option<Expr>, Vec<parser::ParseError>) {
+        if let Err(err) = options.check_length(input) {
+            return (None, vec![err]);
+        }
+
+        parser::parse_recovering(options.lexer(input), options)
     }
 
     /// Evaluate the expression with the given value for the variable
@@ -101,7 +232,8 @@ impl Expr {
                     Err(EvalError::VariableNotDefined(d_var.clone()))
                 }
             }
-            Expr::Num(n) => Ok(*n),
+            Expr::Num(n) | Expr::Const(_, n) => Ok(*n),
+            Expr::Error => Err(EvalError::ErrorNode),
             Expr::Var(s) => {
                 if s == var {
                     Ok(value)
@@ -120,6 +252,25 @@ impl Expr {
                 let inner = inner.eval_with_var(var, value)?;
                 self.un_op_unchecked(inner)
             }
+
+            Expr::And(lhs, rhs) => Ok(from_bool(is_true(lhs.eval_with_var(var, value)?) && is_true(rhs.eval_with_var(var, value)?))),
+            Expr::Or(lhs, rhs) => Ok(from_bool(is_true(lhs.eval_with_var(var, value)?) || is_true(rhs.eval_with_var(var, value)?))),
+            Expr::Cond(cond, then, otherwise) => {
+                if is_true(cond.eval_with_var(var, value)?) {
+                    then.eval_with_var(var, value)
+                } else {
+                    otherwise.eval_with_var(var, value)
+                }
+            }
+
+            Expr::Call(func, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval_with_var(var, value))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                func.call(&args)
+            }
         }
     }
 
@@ -131,8 +282,34 @@ impl Expr {
     ///
     /// You need to provide a value for variable that you use for derivative, even if the derivative is constant
     pub fn eval_with(&self, values: &[(&str, f32)]) -> Result<f32, EvalError> {
+        self.eval_with_budget(values, usize::MAX)
+    }
+
+    /// Evaluate the expression like `eval_with`, but fail with `EvalError::BudgetExceeded` after `budget` steps
+    ///
+    /// Each evaluated node is one step, so a derivative costs its inner expression twice
+    /// and a conditional costs only the chosen branch.
+    /// Together with the limits in `ParseOptions` this bounds the time spent on an untrusted expression.
+    ///
+    /// ```
+    /// use pemel::prelude::*;
+    ///
+    /// let expr = Expr::parse("D(x, D(x, D(x, x^4)))", false).unwrap();
+    /// assert_eq!(expr.eval_with_budget(&[("x", 1.0)], 10), Err(EvalError::BudgetExceeded));
+    /// assert!(expr.eval_with_budget(&[("x", 1.0)], 100).is_ok());
+    /// ```
+    pub fn eval_with_budget(&self, values: &[(&str, f32)], budget: usize) -> Result<f32, EvalError> {
+        let mut steps = budget;
+        self.eval_steps(values, &mut steps)
+    }
+
+    /// Evaluates the expression, `steps` is the remaining budget and each node takes one step of it
+    pub(crate) fn eval_steps(&self, values: &[(&str, f32)], steps: &mut usize) -> Result<f32, EvalError> {
+        *steps = steps.checked_sub(1).ok_or(EvalError::BudgetExceeded)?;
+
         match self {
-            Expr::Num(n) => Ok(*n),
+            Expr::Num(n) | Expr::Const(_, n) => Ok(*n),
+            Expr::Error => Err(EvalError::ErrorNode),
             Expr::Var(s) => {
                 for (var, value) in values {
                     if s == var {
@@ -155,25 +332,44 @@ impl Expr {
                 }
 
                 if let Some(sub) = sub {
-                    let sub_value = sub.eval_with(values)?;
-                    inner.approx_derivative(d_var, sub_value, Self::DX)
+                    let sub_value = sub.eval_steps(values, steps)?;
+                    inner.approx_derivative_steps(d_var, sub_value, Self::DX, steps)
                 } else if let Some(d_val) = d_val {
-                    inner.approx_derivative(d_var, d_val, Self::DX)
+                    inner.approx_derivative_steps(d_var, d_val, Self::DX, steps)
                 } else {
                     Err(EvalError::VariableNotDefined(d_var.clone()))
                 }
             }
 
             expr_pat!(BINOP: lhs, rhs) => {
-                let lhs = lhs.eval_with(values)?;
-                let rhs = rhs.eval_with(values)?;
+                let lhs = lhs.eval_steps(values, steps)?;
+                let rhs = rhs.eval_steps(values, steps)?;
                 self.bin_op_unchecked(lhs, rhs)
             }
 
             expr_pat!(UNOP: inner) => {
-                let inner = inner.eval_with(values)?;
+                let inner = inner.eval_steps(values, steps)?;
                 self.un_op_unchecked(inner)
             }
+
+            Expr::And(lhs, rhs) => Ok(from_bool(is_true(lhs.eval_steps(values, steps)?) && is_true(rhs.eval_steps(values, steps)?))),
+            Expr::Or(lhs, rhs) => Ok(from_bool(is_true(lhs.eval_steps(values, steps)?) || is_true(rhs.eval_steps(values, steps)?))),
+            Expr::Cond(cond, then, otherwise) => {
+                if is_true(cond.eval_steps(values, steps)?) {
+                    then.eval_steps(values, steps)
+                } else {
+                    otherwise.eval_steps(values, steps)
+                }
+            }
+
+            Expr::Call(func, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval_steps(values, steps))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                func.call(&args)
+            }
         }
     }
 
@@ -193,10 +389,11 @@ impl Expr {
                 }
 
                 let _ = expr.eval_const();
-                return Ok(0.0);
+                Ok(0.0)
             }
-            Expr::Num(n) => Ok(*n),
-            Expr::Var(s) => return Err(EvalError::VariableNotDefined(s.clone())),
+            Expr::Num(n) | Expr::Const(_, n) => Ok(*n),
+            Expr::Error => Err(EvalError::ErrorNode),
+            Expr::Var(s) => Err(EvalError::VariableNotDefined(s.clone())),
 
             expr_pat!(BINOP: lhs, rhs) => {
                 let lhs = lhs.eval_const()?;
@@ -208,6 +405,25 @@ impl Expr {
                 let inner = inner.eval_const()?;
                 self.un_op_unchecked(inner)
             }
+
+            Expr::And(lhs, rhs) => Ok(from_bool(is_true(lhs.eval_const()?) && is_true(rhs.eval_const()?))),
+            Expr::Or(lhs, rhs) => Ok(from_bool(is_true(lhs.eval_const()?) || is_true(rhs.eval_const()?))),
+            Expr::Cond(cond, then, otherwise) => {
+                if is_true(cond.eval_const()?) {
+                    then.eval_const()
+                } else {
+                    otherwise.eval_const()
+                }
+            }
+
+            Expr::Call(func, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval_const())
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                func.call(&args)
+            }
         }
     }
 
@@ -227,6 +443,22 @@ impl Expr {
                 lhs / rhs
             }
 
+            Expr::Mod(_, _) => {
+                if rhs == 0.0 {
+                    return Err(EvalError::DivisionByZero);
+                }
+
+                lhs - rhs * (lhs / rhs).floor()
+            }
+
+            Expr::FloorDiv(_, _) => {
+                if rhs == 0.0 {
+                    return Err(EvalError::DivisionByZero);
+                }
+
+                (lhs / rhs).floor()
+            }
+
             Expr::Pow(_, _) => {
                 if lhs == 0.0 && rhs <= 0.0 {
                     return Err(EvalError::InvalidExponentiation);
@@ -243,6 +475,15 @@ impl Expr {
                 rhs.log(lhs)
             }
 
+            Expr::Comb(_, _) => special::combinations(lhs, rhs)?,
+            Expr::Perm(_, _) => special::permutations(lhs, rhs)?,
+            Expr::Lt(_, _) => from_bool(lhs < rhs),
+            Expr::Le(_, _) => from_bool(lhs <= rhs),
+            Expr::Eq(_, _) => from_bool(lhs == rhs),
+            Expr::Ne(_, _) => from_bool(lhs != rhs),
+            Expr::Gt(_, _) => from_bool(lhs > rhs),
+            Expr::Ge(_, _) => from_bool(lhs >= rhs),
+
             // Panic is safe because we know it's binop
             _ => panic!("Not a binary operation: {:?}", self),
         })
@@ -251,6 +492,23 @@ impl Expr {
     fn un_op_unchecked(&self, inner: f32) -> Result<f32, EvalError> {
         Ok(match self {
             Expr::Abs(_) => inner.abs(),
+            Expr::Floor(_) => inner.floor(),
+            Expr::Ceil(_) => inner.ceil(),
+            Expr::Round(_) => inner.round(),
+            Expr::Trunc(_) => inner.trunc(),
+            Expr::Frac(_) => inner.fract(),
+            Expr::Sign(_) => {
+                if inner == 0.0 {
+                    0.0
+                } else {
+                    inner.signum()
+                }
+            }
+            Expr::Not(_) => from_bool(!is_true(inner)),
+            Expr::Factorial(_) => special::factorial(inner)?,
+            Expr::Gamma(_) => special::gamma(inner)?,
+            Expr::LnGamma(_) => special::ln_gamma(inner)?,
+            Expr::Degrees(_) => inner.to_radians(),
             Expr::Sin(_) => inner.sin(),
             Expr::Cos(_) => inner.cos(),
             Expr::Tan(_) => inner.tan(),
@@ -296,15 +554,31 @@ impl Expr {
                 }
             }
 
-            expr_pat!(BINOP: lhs, rhs) => {
+            expr_pat!(BINOP: lhs, rhs) | Expr::And(lhs, rhs) | Expr::Or(lhs, rhs) => {
                 let value = value.into();
                 lhs.substitute(var, value.clone());
                 rhs.substitute(var, value);
             }
 
+            Expr::Cond(cond, then, otherwise) => {
+                let value = value.into();
+                cond.substitute(var, value.clone());
+                then.substitute(var, value.clone());
+                otherwise.substitute(var, value);
+            }
+
             expr_pat!(UNOP: inner) => inner.substitute(var, value),
 
+            Expr::Call(_, args) => {
+                let value = value.into();
+                for arg in args {
+                    arg.substitute(var, value.clone());
+                }
+            }
+
             Expr::Num(_) => (),
+            Expr::Const(_, _) => (),
+            Expr::Error => (),
             Expr::Var(_) => (), // I don't want to have the wild card here, because I want to be explicit
         }
     }
@@ -315,15 +589,117 @@ impl Expr {
         }
     }
 
+    /// Returns true if the expression doesn't depend on any variable and can be evaluated at parse time
+    pub(crate) fn is_constant(&self) -> bool {
+        match self {
+            Expr::Num(_) | Expr::Const(_, _) => true,
+            Expr::Var(_) | Expr::Error | Expr::Derivative(_, _, _) => false,
+            Expr::Call(func, args) => func.is_pure() && args.iter().all(Expr::is_constant),
+            expr_pat!(BINOP: lhs, rhs) | Expr::And(lhs, rhs) | Expr::Or(lhs, rhs) => lhs.is_constant() && rhs.is_constant(),
+            Expr::Cond(cond, then, otherwise) => cond.is_constant() && then.is_constant() && otherwise.is_constant(),
+            expr_pat!(UNOP: inner) => inner.is_constant(),
+        }
+    }
+
+    /// Returns true if the expression calls no registered function, only the built-in ones
+    pub(crate) fn calls_only_builtins(&self) -> bool {
+        match self {
+            Expr::Num(_) | Expr::Const(_, _) | Expr::Var(_) | Expr::Error => true,
+            Expr::Call(_, _) => false,
+            Expr::Derivative(inner, _, sub) => inner.calls_only_builtins() && sub.as_deref().is_none_or(Expr::calls_only_builtins),
+            expr_pat!(BINOP: lhs, rhs) | Expr::And(lhs, rhs) | Expr::Or(lhs, rhs) => {
+                lhs.calls_only_builtins() && rhs.calls_only_builtins()
+            }
+            Expr::Cond(cond, then, otherwise) => {
+                cond.calls_only_builtins() && then.calls_only_builtins() && otherwise.calls_only_builtins()
+            }
+            expr_pat!(UNOP: inner) => inner.calls_only_builtins(),
+        }
+    }
+
+    /// Returns how tightly the expression binds, for deciding where printers need parentheses
+    ///
+    /// The levels are the binding powers of the parser, see the operator table in the README.
+    /// Negation `-1 * x` and negative numbers have the level of the prefix `-`.
+    pub(crate) fn precedence(&self) -> u8 {
+        match self {
+            Expr::Cond(_, _, _) => 1,
+            Expr::Or(_, _) => 3,
+            Expr::And(_, _) => 5,
+            Expr::Lt(_, _) | Expr::Le(_, _) | Expr::Eq(_, _) | Expr::Ne(_, _) | Expr::Gt(_, _) | Expr::Ge(_, _) => 7,
+            Expr::Add(_, _) | Expr::Sub(_, _) => 9,
+            Expr::Mul(lhs, _) if **lhs == Expr::Num(-1.0) => PREFIX_PRECEDENCE,
+            Expr::Mul(_, _) | Expr::Div(_, _) | Expr::Mod(_, _) | Expr::FloorDiv(_, _) => 11,
+            Expr::Num(n) if n.is_sign_negative() => PREFIX_PRECEDENCE,
+            Expr::Not(_) => PREFIX_PRECEDENCE,
+            // Square root is printed as a function
+            Expr::Pow(_, exp) if **exp == Expr::Num(0.5) => 20,
+            Expr::Pow(_, _) => 15,
+            Expr::Factorial(_) => 17,
+            Expr::Degrees(inner) => inner.precedence(),
+            _ => 20,
+        }
+    }
+
     /// Approximate the derivative of the expression with respect to a given variable
     ///
     /// Only works for expressions with one variable
+    ///
+    /// If the expression is a call of a function with registered derivative,
+    /// the chain rule is used, so only the arguments are approximated.
     pub fn approx_derivative(&self, var: &str, value: f32, dx: f32) -> Result<f32, EvalError> {
-        let f1 = self.eval_with_var(var, value - dx)?;
-        let f2 = self.eval_with_var(var, value + dx)?;
+        let mut steps = usize::MAX;
+        self.approx_derivative_steps(var, value, dx, &mut steps)
+    }
+
+    /// Approximates the derivative like `approx_derivative`, taking the evaluation steps from the budget
+    fn approx_derivative_steps(&self, var: &str, value: f32, dx: f32, steps: &mut usize) -> Result<f32, EvalError> {
+        if let Expr::Call(func, args) = self {
+            if func.has_derivative() {
+                let values = args
+                    .iter()
+                    .map(|arg| arg.eval_steps(&[(var, value)], steps))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let mut result = 0.0;
+                for (i, arg) in args.iter().enumerate() {
+                    if let Some(partial) = func.call_derivative(&values, i) {
+                        result += partial? * arg.approx_derivative_steps(var, value, dx, steps)?;
+                    }
+                }
+
+                return Ok(result);
+            }
+        }
+
+        let f1 = self.eval_steps(&[(var, value - dx)], steps)?;
+        let f2 = self.eval_steps(&[(var, value + dx)], steps)?;
 
         Ok((f2 - f1) / (2.0 * dx))
     }
+
+    /// Returns the number of nodes in the tree, every operation, number, variable and constant is one node
+    ///
+    /// The tree is walked without recursion, so even a very deep tree can be measured.
+    pub fn node_count(&self) -> usize {
+        let mut count = 0;
+        let mut stack = vec![self];
+
+        while let Some(expr) = stack.pop() {
+            count += 1;
+
+            match expr {
+                expr_pat!(BINOP: lhs, rhs) | Expr::And(lhs, rhs) | Expr::Or(lhs, rhs) => stack.extend([&**lhs, &**rhs]),
+                expr_pat!(UNOP: inner) => stack.push(inner),
+                Expr::Cond(cond, then, otherwise) => stack.extend([&**cond, &**then, &**otherwise]),
+                Expr::Derivative(inner, _, sub) => stack.extend(std::iter::once(&**inner).chain(sub.as_deref())),
+                Expr::Call(_, args) => stack.extend(args),
+                Expr::Num(_) | Expr::Var(_) | Expr::Const(_, _) | Expr::Error => (),
+            }
+        }
+
+        count
+    }
 }
 
 // CONSTRUCTORS
@@ -344,6 +720,14 @@ impl Expr {
         Expr::Div(Box::new(lhs.into()), Box::new(rhs.into()))
     }
 
+    pub fn new_mod(lhs: impl Into<Self>, rhs: impl Into<Self>) -> Self {
+        Self::Mod(Box::new(lhs.into()), Box::new(rhs.into()))
+    }
+
+    pub fn new_floor_div(lhs: impl Into<Self>, rhs: impl Into<Self>) -> Self {
+        Self::FloorDiv(Box::new(lhs.into()), Box::new(rhs.into()))
+    }
+
     pub fn new_pow(lhs: impl Into<Self>, rhs: impl Into<Self>) -> Self {
         Expr::Pow(Box::new(lhs.into()), Box::new(rhs.into()))
     }
@@ -364,6 +748,10 @@ impl Expr {
         Expr::Tan(Box::new(inner.into()))
     }
 
+    pub fn new_degrees(inner: impl Into<Self>) -> Self {
+        Expr::Degrees(Box::new(inner.into()))
+    }
+
     pub fn new_cot(inner: impl Into<Self>) -> Self {
         Expr::Cot(Box::new(inner.into()))
     }
@@ -372,66 +760,128 @@ impl Expr {
         Expr::Abs(Box::new(inner.into()))
     }
 
+    pub fn new_floor(inner: impl Into<Self>) -> Self {
+        Self::Floor(Box::new(inner.into()))
+    }
+
+    pub fn new_ceil(inner: impl Into<Self>) -> Self {
+        Self::Ceil(Box::new(inner.into()))
+    }
+
+    pub fn new_round(inner: impl Into<Self>) -> Self {
+        Self::Round(Box::new(inner.into()))
+    }
+
+    pub fn new_trunc(inner: impl Into<Self>) -> Self {
+        Self::Trunc(Box::new(inner.into()))
+    }
+
+    pub fn new_frac(inner: impl Into<Self>) -> Self {
+        Self::Frac(Box::new(inner.into()))
+    }
+
+    pub fn new_sign(inner: impl Into<Self>) -> Self {
+        Self::Sign(Box::new(inner.into()))
+    }
+
+    pub fn new_factorial(inner: impl Into<Self>) -> Self {
+        Self::Factorial(Box::new(inner.into()))
+    }
+
+    pub fn new_gamma(inner: impl Into<Self>) -> Self {
+        Self::Gamma(Box::new(inner.into()))
+    }
+
+    pub fn new_ln_gamma(inner: impl Into<Self>) -> Self {
+        Self::LnGamma(Box::new(inner.into()))
+    }
+
+    pub fn new_comb(n: impl Into<Self>, k: impl Into<Self>) -> Self {
+        Self::Comb(Box::new(n.into()), Box::new(k.into()))
+    }
+
+    pub fn new_perm(n: impl Into<Self>, k: impl Into<Self>) -> Self {
+        Self::Perm(Box::new(n.into()), Box::new(k.into()))
+    }
+
+    pub fn new_lt(lhs: impl Into<Self>, rhs: impl Into<Self>) -> Self {
+        Self::Lt(Box::new(lhs.into()), Box::new(rhs.into()))
+    }
+
+    pub fn new_le(lhs: impl Into<Self>, rhs: impl Into<Self>) -> Self {
+        Self::Le(Box::new(lhs.into()), Box::new(rhs.into()))
+    }
+
+    pub fn new_eq(lhs: impl Into<Self>, rhs: impl Into<Self>) -> Self {
+        Self::Eq(Box::new(lhs.into()), Box::new(rhs.into()))
+    }
+
+    pub fn new_ne(lhs: impl Into<Self>, rhs: impl Into<Self>) -> Self {
+        Self::Ne(Box::new(lhs.into()), Box::new(rhs.into()))
+    }
+
+    pub fn new_gt(lhs: impl Into<Self>, rhs: impl Into<Self>) -> Self {
+        Self::Gt(Box::new(lhs.into()), Box::new(rhs.into()))
+    }
+
+    pub fn new_ge(lhs: impl Into<Self>, rhs: impl Into<Self>) -> Self {
+        Self::Ge(Box::new(lhs.into()), Box::new(rhs.into()))
+    }
+
+    pub fn new_and(lhs: impl Into<Self>, rhs: impl Into<Self>) -> Self {
+        Self::And(Box::new(lhs.into()), Box::new(rhs.into()))
+    }
+
+    pub fn new_or(lhs: impl Into<Self>, rhs: impl Into<Self>) -> Self {
+        Self::Or(Box::new(lhs.into()), Box::new(rhs.into()))
+    }
+
+    pub fn new_not(inner: impl Into<Self>) -> Self {
+        Self::Not(Box::new(inner.into()))
+    }
+
+    pub fn new_cond(cond: impl Into<Self>, then: impl Into<Self>, otherwise: impl Into<Self>) -> Self {
+        Self::Cond(Box::new(cond.into()), Box::new(then.into()), Box::new(otherwise.into()))
+    }
+
+    pub fn new_const(name: impl Into<String>, value: f32) -> Self {
+        Expr::Const(name.into(), value)
+    }
+
+    pub fn new_call(func: Arc<Function>, args: Vec<Expr>) -> Self {
+        Expr::Call(func, args)
+    }
+
     pub fn new_derivative(var: impl Into<String>, expr: impl Into<Self>) -> Self {
         Expr::Derivative(Box::new(expr.into()), var.into(), None)
     }
 }
 
+/// Prints the expression with only the necessary parentheses, see `Expr::to_string_with`
+///
+/// The alternate form, `format!("{:#}", expr)`, prints `abs(x)` as `|x|`.
 impl Display for Expr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Expr::Num(n) => write!(f, "{}", n),
-            Expr::Var(s) => write!(f, "{}", s),
-            Expr::Log(base, arg) => write!(f, "log({}, {})", base.to_string(), arg.to_string()),
-            Expr::Derivative(expr, var, None) => write!(f, "D({}, {})", var, expr.to_string()),
-            Expr::Derivative(expr, var, Some(sub)) => write!(
-                f,
-                "D({}, {})[{} = {}]",
-                var,
-                expr.to_string(),
-                var,
-                sub.to_string()
-            ),
-
-            expr_pat!(BINOP: lhs, rhs) => write!(
-                f,
-                "({} {} {})",
-                lhs.to_string(),
-                binop_to_string_unchecked(self),
-                rhs.to_string()
-            ),
-
-            expr_pat!(UNOP: inner) => write!(
-                f,
-                "{}({})",
-                unop_to_string_unchecked(self),
-                inner.to_string(),
-            ),
-        }
+        let options = PrintOptions {
+            abs_bars: f.alternate(),
+            ..Default::default()
+        };
+
+        f.write_str(&self.to_string_with(&options))
     }
 }
 
-fn binop_to_string_unchecked(expr: &Expr) -> char {
-    match expr {
-        Expr::Add(_, _) => '+',
-        Expr::Sub(_, _) => '-',
-        Expr::Mul(_, _) => '*',
-        Expr::Div(_, _) => '/',
-        Expr::Pow(_, _) => '^',
-        _ => panic!("Not a binary op"),
-    }
+/// Any value other than `0` is true
+pub(crate) fn is_true(value: f32) -> bool {
+    value != 0.0
 }
 
-fn unop_to_string_unchecked(expr: &Expr) -> String {
-    match expr {
-        Expr::Sin(_) => "sin",
-        Expr::Cos(_) => "cos",
-        Expr::Tan(_) => "tan",
-        Expr::Cot(_) => "cot",
-        Expr::Abs(_) => "abs",
-        _ => panic!("Not a unary op"),
+fn from_bool(value: bool) -> f32 {
+    if value {
+        1.0
+    } else {
+        0.0
     }
-    .to_string()
 }
 
 mod froms {
@@ -1,7 +1,12 @@
 use crate::eval_error::EvalError;
 use crate::expr::Expr;
+use crate::function::{Arity, Function};
 use crate::parser::ParseError;
-use crate::parser::Token;
+use crate::parser::ParseOptions;
+use crate::parser::{AngleUnit, NumberLiterals};
+use crate::parser::Span;
+use crate::printer::PrintOptions;
+use crate::program::Program;
 
 #[test]
 fn bad_syntax() {
@@ -11,10 +16,22 @@ fn bad_syntax() {
     let e1 = Expr::parse(i1, false);
     let e2 = Expr::parse(i2, false);
 
-    let token_x = Token::Ident("x".to_string());
+    let token_x = "x".to_string();
 
-    assert_eq!(e1, Err(ParseError::UnexpectedToken(token_x)));
-    assert_eq!(e2, Err(ParseError::UnexpectedToken(Token::EOF)));
+    assert_eq!(e1, Err(ParseError::UnexpectedToken(token_x, Span::new(6, 7))));
+    assert_eq!(e2, Err(ParseError::UnexpectedEof(Span::new(23, 23))));
+}
+
+#[test]
+fn error_render() {
+    let input = "1 + sinc(x) * 2";
+    let err = Expr::parse(input, false).unwrap_err();
+
+    assert_eq!(err.span(), Span::new(4, 8));
+    assert_eq!(
+        err.render(input),
+        "error: function `sinc` is not recognized\n  |\n1 | 1 + sinc(x) * 2\n  |     ^^^^"
+    );
 }
 
 #[test]
@@ -22,7 +39,7 @@ fn unrecognized_function() {
     let input = "sinc(3 * 8)";
     let expr = Expr::parse(input, false);
 
-    assert!(matches!(expr, Err(ParseError::FunctionNotRecognized(_))));
+    assert!(matches!(expr, Err(ParseError::FunctionNotRecognized(_, _))));
 }
 
 #[test]
@@ -30,18 +47,16 @@ fn eval_error_in_parse() {
     let input = "2 + 3 / 0";
     let expr = Expr::parse(input, true);
 
-    assert_eq!(expr, Err(ParseError::EvalError(EvalError::DivisionByZero)));
+    assert_eq!(expr, Err(ParseError::EvalError(EvalError::DivisionByZero, Span::new(4, 9))));
 }
 
-// This test is not supported for now
-
-// #[test]
-// fn wrong_number_of_args() {
-//     let input = "cos(2, 4)";
-//     let expr = Expr::parse(input, false);
+#[test]
+fn wrong_number_of_args() {
+    let input = "cos(2, 4)";
+    let expr = Expr::parse(input, false);
 
-//     assert!(matches!(expr, Err(ParseError::WrongNumberOfArgs(_))));
-// }
+    assert_eq!(expr, Err(ParseError::WrongNumberOfArgs("cos".to_string(), 2, Span::new(0, 9))));
+}
 
 #[test]
 fn const_expr_eval() {
@@ -67,7 +82,7 @@ fn power() {
     let input = "2*x^3 - 3*x^2/2 + 1*x^(2/1) - 5";
     let expr = Expr::parse(input, false).unwrap();
 
-    println!("{}", expr.to_string());
+    println!("{}", expr);
 
     let zero = expr.eval_with_var("x", 0.0).unwrap();
     let two = expr.eval_with_var("x", 2.0).unwrap();
@@ -131,6 +146,35 @@ fn abs() {
     assert_eq!(result, 2.0);
 }
 
+#[test]
+fn abs_bars() {
+    let expr = Expr::parse("||x| - 1|", false).unwrap();
+    assert_eq!(expr.eval_with_var("x", -0.5), Ok(0.5));
+    assert_eq!(Expr::parse("|2 - 5| + |-1|", true), Ok(Expr::Num(4.0)));
+
+    assert_eq!(format!("{:#}", expr), "||x| - 1|");
+    assert_eq!(format!("{:#}", Expr::parse("sin(|x|) * abs(y)", false).unwrap()), "sin(|x|) * |y|");
+
+    assert_eq!(Expr::parse("|x", false), Err(ParseError::UnexpectedEof(Span::new(2, 2))));
+    assert_eq!(Expr::parse("x|", false), Err(ParseError::UnexpectedToken("|".to_string(), Span::new(1, 2))));
+    assert_eq!(Expr::parse("||x|", false), Err(ParseError::UnexpectedEof(Span::new(4, 4))));
+    assert_eq!(Expr::parse("|(x|)", false), Err(ParseError::UnexpectedToken("|".to_string(), Span::new(3, 4))));
+}
+
+#[test]
+fn unicode_input() {
+    assert_eq!(Expr::parse("2² × 3 ÷ 4 − 1", true), Ok(Expr::Num(2.0)));
+    assert_eq!(Expr::parse("√16 · 10⁻¹", true), Ok(Expr::Num(0.4)));
+    assert_eq!(Expr::parse("π", false), Ok(Expr::new_const("pi", std::f32::consts::PI)));
+
+    let expr = Expr::parse("θ² + λ", false).unwrap();
+    assert_eq!(expr.eval_with(&[("θ", 3.0), ("λ", 1.0)]), Ok(10.0));
+
+    // Spans are byte ranges, `²` is two bytes long
+    assert_eq!(Expr::parse("x⁻", false), Err(ParseError::UnexpectedEof(Span::new(4, 4))));
+    assert_eq!(Expr::parse("x ∑", false), Err(ParseError::UnexpectedChar('∑', Span::new(2, 5))));
+}
+
 #[test]
 fn derivative_function() {
     let input = "D(x, x^2 + 2*x + 1)";
@@ -153,6 +197,547 @@ fn derivative_multiple_vars() {
     assert!((result - 4.0).abs() <= 0.0002);
 }
 
+#[test]
+fn builtin_constants() {
+    let input = "sin(pi/2) + ln(e) + tau / pi + phi - 1/phi";
+    let expr = Expr::parse(input, true).unwrap();
+
+    assert!((expr.eval_const().unwrap() - 5.0).abs() <= 0.0001);
+    assert_eq!(Expr::parse("-inf", true), Ok(Expr::Num(f32::NEG_INFINITY)));
+}
+
+#[test]
+fn user_constants() {
+    let mut options = ParseOptions::default();
+    options.constants.register("g", 9.81);
+    options.constants.remove("e");
+
+    let expr = Expr::parse_with("g * e", &options).unwrap();
+
+    assert_eq!(expr, Expr::new_mul(Expr::new_const("g", 9.81), "e"));
+    assert_eq!(expr.to_string(), "g * e");
+    assert_eq!(expr.eval_with_var("e", 2.0), Ok(19.62));
+}
+
+#[test]
+fn constants_implicit_evaluation() {
+    let options = ParseOptions {
+        implicit_evaluation: true,
+        ..Default::default()
+    };
+
+    assert_eq!(Expr::parse_with("2 * pi", &options), Ok(Expr::Num(2.0 * PI)));
+    assert_eq!(Expr::parse_with("pi", &options), Ok(Expr::new_const("pi", PI)));
+    assert_eq!(Expr::parse_with("pi * x", &options).unwrap().to_string(), "pi * x");
+}
+
+fn options_with_functions() -> ParseOptions {
+    let mut options = ParseOptions {
+        implicit_evaluation: true,
+        ..Default::default()
+    };
+
+    options.functions.register(Function::new("clamp", Arity::Fixed(3), |args| {
+        Ok(args[0].clamp(args[1], args[2]))
+    }));
+    options.functions.register(Function::new("max", Arity::Variadic { min: 1 }, |args| {
+        Ok(args.iter().copied().fold(f32::NEG_INFINITY, f32::max))
+    }));
+    options.functions.register(Function::new("counter", Arity::Fixed(0), |_| Ok(1.0)).impure());
+    options.functions.register(
+        Function::new("square", Arity::Fixed(1), |args| Ok(args[0] * args[0]))
+            .with_derivative(|args, _| Ok(2.0 * args[0])),
+    );
+
+    options
+}
+
+#[test]
+fn registered_functions() {
+    let options = options_with_functions();
+
+    let expr = Expr::parse_with("clamp(x, 0, 1) + max(1, 5, x)", &options).unwrap();
+    assert_eq!(expr.to_string(), "clamp(x, 0, 1) + max(1, 5, x)");
+    assert_eq!(expr.eval_with_var("x", 7.0), Ok(8.0));
+
+    // Pure functions are evaluated during parsing, impure are not
+    assert_eq!(Expr::parse_with("max(1, 2) * 2", &options), Ok(Expr::Num(4.0)));
+    assert_eq!(Expr::parse_with("counter() * 2", &options).unwrap().to_string(), "counter() * 2");
+}
+
+#[test]
+fn registered_function_errors() {
+    let options = options_with_functions();
+
+    assert_eq!(
+        Expr::parse_with("clamp(x, 1)", &options),
+        Err(ParseError::WrongNumberOfArgs("clamp".to_string(), 2, Span::new(0, 11)))
+    );
+    assert_eq!(
+        Expr::parse_with("lerp(x, 1, 2)", &options),
+        Err(ParseError::FunctionNotRecognized("lerp".to_string(), Span::new(0, 4)))
+    );
+
+    let mut options = options;
+    options.functions.register(Function::new("args", Arity::Range { min: 1, max: 2 }, |args| Ok(args.len() as f32)));
+
+    assert_eq!(Expr::parse_with("args(x) + args(x, x)", &options).unwrap().eval_with_var("x", 0.0), Ok(3.0));
+    assert_eq!(
+        Expr::parse_with("args(x, x, x)", &options),
+        Err(ParseError::WrongNumberOfArgs("args".to_string(), 3, Span::new(0, 13)))
+    );
+}
+
+#[test]
+fn definitions() {
+    let mut options = ParseOptions::default();
+    options.define("f(x) = x^2 + 1").unwrap();
+    options.define("g(x, y) = f(x) * y").unwrap();
+    // Parameters shadow the constants and are substituted simultaneously
+    options.define("h(e, x) = e - x").unwrap();
+
+    let expr = Expr::parse_with("g(2, y)", &options).unwrap();
+    assert_eq!(expr.eval_with_var("y", 3.0), Ok(15.0));
+
+    let expr = Expr::parse_with("h(x, e)", &options).unwrap();
+    assert_eq!(expr.eval_with_var("x", 3.0), Ok(3.0 - std::f32::consts::E));
+
+    // Constant calls are evaluated at parse time
+    options.implicit_evaluation = true;
+    assert_eq!(Expr::parse_with("f(3)", &options), Ok(Expr::Num(10.0)));
+}
+
+#[test]
+fn definition_errors() {
+    let mut options = ParseOptions::default();
+    options.define("f(x) = x^2").unwrap();
+
+    assert_eq!(
+        Expr::parse_with("f(1, 2)", &options),
+        Err(ParseError::WrongNumberOfArgs("f".to_string(), 2, Span::new(0, 7)))
+    );
+    assert_eq!(
+        options.define("r(x) = r(x - 1)"),
+        Err(ParseError::RecursiveDefinition("r".to_string(), Span::new(7, 8)))
+    );
+    assert_eq!(
+        options.define("d(x, x) = x"),
+        Err(ParseError::DuplicateParameter("x".to_string(), Span::new(5, 6)))
+    );
+    assert!(matches!(options.define("f = 2"), Err(ParseError::UnexpectedToken(token, _)) if token == "="));
+}
+
+#[test]
+fn registered_function_derivative() {
+    let options = options_with_functions();
+    let expr = Expr::parse_with("D(x, square(3 * x))", &options).unwrap();
+
+    // chain rule: 2 * (3x) * 3
+    let result = expr.eval_with_var("x", 2.0).unwrap();
+    assert!((result - 36.0).abs() <= 0.01);
+}
+
+#[test]
+fn options_builder() {
+    let options = ParseOptions::new()
+        .with_implicit_evaluation(true)
+        .with_implicit_multiplication(true);
+
+    assert!(options.implicit_evaluation && options.implicit_multiplication);
+    assert_eq!(Expr::parse_with("2(3 + 1)x", &options), Ok(Expr::new_mul(8.0, "x")));
+}
+
+#[test]
+fn allowed_names() {
+    let mut options = ParseOptions::new()
+        .with_allowed_functions(["sin", "f"])
+        .with_allowed_variables(["x"]);
+    options.define("f(t) = t^2").unwrap();
+
+    assert!(Expr::parse_with("sin(x) + f(x) * pi", &options).is_ok());
+    assert_eq!(
+        Expr::parse_with("x + cos(x)", &options),
+        Err(ParseError::FunctionNotRecognized("cos".to_string(), Span::new(4, 7)))
+    );
+    assert_eq!(
+        Expr::parse_with("x + y", &options),
+        Err(ParseError::UnknownVariable("y".to_string(), Span::new(4, 5)))
+    );
+    assert_eq!(
+        Expr::parse_with("D(y, x^2)", &options),
+        Err(ParseError::UnknownVariable("y".to_string(), Span::new(2, 3)))
+    );
+
+    // Bound names are not variables
+    assert!(Program::parse_with("a = 2; a * x", &options).is_ok());
+}
+
+#[test]
+fn case_insensitive_names() {
+    let mut options = ParseOptions::new().case_insensitive();
+    options.define("F(X) = X^2").unwrap();
+
+    let expr = Expr::parse_with("SIN(X) + Pi * x + NCR(x, 2) + f(2)", &options).unwrap();
+    assert_eq!(expr.to_string(), "sin(x) + pi * x + nCr(x, 2) + 2^2");
+
+    let sensitive = Expr::parse("SIN(X)", false);
+    assert_eq!(sensitive, Err(ParseError::FunctionNotRecognized("SIN".to_string(), Span::new(0, 3))));
+
+    // Functions registered after the options were made case insensitive are found too
+    options.functions.register(Function::new("Half", Arity::Fixed(1), |args| Ok(args[0] / 2.0)));
+    assert_eq!(Expr::parse_with("HALF(x)", &options).map(|expr| expr.to_string()), Ok("Half(x)".to_string()));
+    options.functions.remove("Half");
+    assert!(Expr::parse_with("HALF(x)", &options).is_err());
+
+    let options = ParseOptions::new().with_allowed_variables(["X"]).case_insensitive().with_allowed_functions(["SIN"]);
+    assert!(Expr::parse_with("Sin(x) + X", &options).is_ok());
+    assert_eq!(Expr::parse_with("y", &options), Err(ParseError::UnknownVariable("y".to_string(), Span::new(0, 1))));
+    assert_eq!(Expr::parse_with("cos(x)", &options), Err(ParseError::FunctionNotRecognized("cos".to_string(), Span::new(0, 3))));
+}
+
+#[test]
+fn angle_unit() {
+    let options = ParseOptions::new()
+        .with_implicit_evaluation(true)
+        .with_angle_unit(AngleUnit::Degrees);
+
+    let approx = |expr: Result<Expr, ParseError>, x: f32, expected: f32| {
+        let value = expr.unwrap().eval_with_var("x", x).unwrap();
+        assert!((value - expected).abs() < 1e-5, "{} != {}", value, expected);
+    };
+
+    approx(Expr::parse_with("sin(90)", &options), 0.0, 1.0);
+    approx(Expr::parse_with("cos(x)", &options), 180.0, -1.0);
+    approx(Expr::parse_with("tan(x)", &options), 45.0, 1.0);
+    approx(Expr::parse_latex(r"\sin{30}", &options), 0.0, 0.5);
+    approx(Expr::parse_content_mathml("<apply><cos/><ci>x</ci></apply>", &options), 60.0, 0.5);
+
+    let expr = Expr::parse_with("sin(x)", &options).unwrap();
+    assert_eq!(expr, Expr::new_sin(Expr::new_degrees("x")));
+    assert_eq!(expr.to_string(), "sin(x)");
+}
+
+#[test]
+fn angle_unit_round_trip() {
+    // LaTeX writes `2 * x` as `2x`
+    let options = ParseOptions::new()
+        .with_implicit_multiplication(true)
+        .with_angle_unit(AngleUnit::Degrees);
+    let expr = Expr::parse_with("sin(x + 30) * cos(2 * x) - tan(-x) / cot(x^2)", &options).unwrap();
+
+    assert_eq!(Expr::parse_with(&expr.to_string(), &options), Ok(expr.clone()));
+    assert_eq!(Expr::parse_latex(&expr.to_latex(), &options), Ok(expr.clone()));
+    assert_eq!(Expr::parse_content_mathml(&expr.to_content_mathml(), &options), Ok(expr.clone()));
+}
+
+#[test]
+fn number_literal_rules() {
+    let options = ParseOptions::new().with_number_literals(NumberLiterals::DECIMAL);
+
+    assert_eq!(Expr::parse_with("12.5", &options), Ok(Expr::Num(12.5)));
+
+    for input in ["1e5", ".5", "1_000", "0x1F", "0b101"] {
+        let expected = ParseError::InvalidNumber(input.to_string(), Span::new(0, input.len()));
+        assert_eq!(Expr::parse_with(input, &options), Err(expected), "{}", input);
+    }
+
+    let options = ParseOptions::new().with_number_literals(NumberLiterals {
+        hexadecimal: true,
+        ..NumberLiterals::DECIMAL
+    });
+    assert_eq!(Expr::parse_with("0x1F", &options), Ok(Expr::Num(31.0)));
+    assert!(Expr::parse_with("0x1_F", &options).is_err());
+}
+
+#[test]
+fn max_input_length() {
+    let options = ParseOptions::new().with_max_input_length(5);
+
+    assert!(Expr::parse_with("1 + 2", &options).is_ok());
+    assert_eq!(Expr::parse_with("1 + 23", &options), Err(ParseError::InputTooLong(5, Span::new(5, 6))));
+    assert_eq!(Expr::parse_recovering("1 + 23", &options), (None, vec![ParseError::InputTooLong(5, Span::new(5, 6))]));
+    assert!(Program::parse_with("a = 1; a", &options).is_err());
+    assert!(Expr::parse_latex(r"\frac{1}{2}", &options).is_err());
+}
+
+#[test]
+fn max_tokens() {
+    let options = ParseOptions::new().with_max_tokens(3);
+
+    assert!(Expr::parse_with("1 + 2\n", &options).is_ok());
+    assert_eq!(Expr::parse_with("1 + 2 * x", &options), Err(ParseError::TooManyTokens(3, Span::new(6, 9))));
+    assert_eq!(
+        Expr::parse_recovering("1 + 2 * x", &options),
+        (Some(Expr::new_add(1.0, 2.0)), vec![ParseError::TooManyTokens(3, Span::new(6, 9))])
+    );
+    assert!(Program::parse_with("a = 1; a", &options).is_err());
+    assert!(Expr::parse_latex(r"\frac{1}{2}", &options).is_err());
+}
+
+#[test]
+fn max_depth() {
+    let options = ParseOptions::new().with_max_depth(5);
+
+    assert!(Expr::parse_with("(x + 1) * 2", &options).is_ok());
+    assert!(Expr::parse_with("((((x))))", &options).is_ok());
+    assert_eq!(Expr::parse_with("(((((x)))))", &options), Err(ParseError::TooDeep(5, Span::new(5, 6))));
+    assert!(Expr::parse_with("sin(cos(-(x)))", &options).is_ok());
+    assert!(matches!(Expr::parse_with("sin(cos(-(-x)))", &options), Err(ParseError::TooDeep(5, _))));
+    assert!(Expr::parse_with("a + b + c + d", &options).is_ok());
+    assert!(matches!(Expr::parse_with("a + b + c + d + e", &options), Err(ParseError::TooDeep(5, _))));
+
+    assert!(Expr::parse_latex(r"\frac{x}{2}", &options).is_ok());
+    assert!(matches!(Expr::parse_latex(r"{{{{{x}}}}}", &options), Err(ParseError::TooDeep(5, _))));
+
+    let nested = |n| format!("{}<ci>x</ci>{}", "<apply><abs/>".repeat(n), "</apply>".repeat(n));
+    assert!(Expr::parse_content_mathml(&nested(4), &options).is_ok());
+    assert!(matches!(Expr::parse_content_mathml(&nested(5), &options), Err(ParseError::TooDeep(5, _))));
+}
+
+#[test]
+fn max_nodes() {
+    let mut options = ParseOptions::new().with_max_nodes(20);
+    options.define("f(x) = x * x").unwrap();
+
+    assert_eq!(Expr::parse_with("f(f(x))", &options).map(|expr| expr.node_count()), Ok(7));
+    assert_eq!(Expr::parse_with("f(f(f(f(x))))", &options), Err(ParseError::TooManyNodes(20, Span::new(0, 13))));
+    assert!(Expr::parse_with("f(f(f(f(f(f(f(f(f(f(x))))))))))", &options).is_err());
+    assert!(Program::parse_with("a = f(f(x)); f(f(a))", &options).is_ok());
+    assert!(Program::parse_with("a = f(f(f(f(x)))); a", &options).is_err());
+
+    // Every inlined call is checked, so the nodes don't double 40 times before the limit is found
+    let mathml = format!("{}<ci>x</ci>{}", "<apply><ci>f</ci>".repeat(40), "</apply>".repeat(40));
+    assert!(matches!(Expr::parse_content_mathml(&mathml, &options), Err(ParseError::TooManyNodes(20, _))));
+
+    let options = ParseOptions::new().with_max_nodes(2);
+    assert!(Expr::parse_with("x + 1", &options).is_err());
+    assert!(Expr::parse_with("1 + 1", &options.clone().with_implicit_evaluation(true)).is_ok());
+}
+
+#[test]
+fn eval_budget() {
+    let expr = Expr::parse("x * 2 + 1", false).unwrap();
+    assert_eq!(expr.eval_with_budget(&[("x", 3.0)], 5), Ok(7.0));
+    assert_eq!(expr.eval_with_budget(&[("x", 3.0)], 4), Err(EvalError::BudgetExceeded));
+
+    // Only the chosen branch is evaluated
+    let expr = Expr::parse("x > 0 ? 1 : x * x * x * x", false).unwrap();
+    assert_eq!(expr.eval_with_budget(&[("x", 1.0)], 5), Ok(1.0));
+    assert_eq!(expr.eval_with_budget(&[("x", -1.0)], 5), Err(EvalError::BudgetExceeded));
+
+    // Each nested derivative doubles the work
+    let expr = Expr::parse("D(x, D(x, D(x, x^3)))", false).unwrap();
+    assert!(expr.eval_with_budget(&[("x", 1.0)], 100).is_ok());
+    assert_eq!(expr.eval_with_budget(&[("x", 1.0)], 30), Err(EvalError::BudgetExceeded));
+
+    let program = Program::parse("a = x * x; a * a", false).unwrap();
+    assert_eq!(program.eval_with_budget(&[("x", 2.0)], 6), Ok(16.0));
+    assert_eq!(program.eval_with_budget(&[("x", 2.0)], 5), Err(EvalError::BudgetExceeded));
+}
+
+#[test]
+fn program() {
+    let program = Program::parse("a = 2; b = a * x; b^2 + a", false).unwrap();
+    assert_eq!(program.bindings.len(), 2);
+    assert_eq!(program.eval_with(&[("x", 3.0)]), Ok(38.0));
+    assert_eq!(program.inline().eval_with(&[("x", 3.0)]), Ok(38.0));
+
+    // New lines separate statements, except inside parentheses
+    let program = Program::parse("a = (1 +\n 2)\r\n\nx = x + a\n\nx^2\n", false).unwrap();
+    assert_eq!(program.eval_with(&[("x", 1.0)]), Ok(16.0));
+    assert_eq!(program.inline().eval_with(&[("x", 1.0)]), Ok(16.0));
+
+    // Bound names shadow constants, functions see the bindings from where they are defined
+    let program = Program::parse("e = 2; f(x) = e * x; e = 10; f(e)", false).unwrap();
+    assert_eq!(program.eval_with(&[]), Ok(20.0));
+    assert_eq!(program.inline().eval_const(), Ok(20.0));
+
+    // A program with a single expression is the same as the expression
+    let program = Program::parse("2 * x", true).unwrap();
+    assert_eq!(program.result, Expr::parse("2 * x", true).unwrap());
+}
+
+#[test]
+fn program_errors() {
+    assert_eq!(Program::parse("a = 2;", false), Err(ParseError::UnexpectedEof(Span::new(6, 6))));
+    assert_eq!(
+        Program::parse("a = 2; a + 1; a", false),
+        Err(ParseError::UnexpectedToken(";".to_string(), Span::new(12, 13)))
+    );
+    assert_eq!(
+        Program::parse("(a; 1)", false),
+        Err(ParseError::UnexpectedToken(";".to_string(), Span::new(2, 3)))
+    );
+    assert_eq!(Program::parse("", false), Err(ParseError::UnexpectedEof(Span::new(0, 0))));
+
+    let program = Program::parse("y = 2; x + y", false).unwrap();
+    assert_eq!(program.eval_with(&[]), Err(EvalError::VariableNotDefined("x".to_string())));
+}
+
+#[test]
+fn newlines_in_expression() {
+    assert_eq!(Expr::parse("1 +\n2", true), Ok(Expr::Num(3.0)));
+}
+
+#[test]
+fn comparisons() {
+    let cases = [
+        ("1 < 2", 1.0),
+        ("2 <= 1", 0.0),
+        ("2 == 2", 1.0),
+        ("2 != 2", 0.0),
+        ("3 > 2", 1.0),
+        ("2 >= 3", 0.0),
+        ("0 < 0.5 < 1", 1.0),
+        ("0 < 1.5 < 1", 0.0),
+        ("1 && 0", 0.0),
+        ("1 || 0", 1.0),
+        ("!0", 1.0),
+        ("!2", 0.0),
+    ];
+
+    for (input, expected) in cases {
+        assert_eq!(Expr::parse(input, true), Ok(Expr::Num(expected)), "{}", input);
+        assert_eq!(Expr::parse(input, false).unwrap().eval_const(), Ok(expected), "{}", input);
+    }
+}
+
+#[test]
+fn conditionals() {
+    let expr = Expr::parse("x < 0 ? -x : x^2", false).unwrap();
+    assert_eq!(expr.eval_with_var("x", -3.0), Ok(3.0));
+    assert_eq!(expr.eval_with_var("x", 3.0), Ok(9.0));
+
+    let expr = Expr::parse("if(0 <= x && x <= 1, x, 0)", true).unwrap();
+    assert_eq!(expr.eval_with(&[("x", 0.5)]), Ok(0.5));
+    assert_eq!(expr.eval_with(&[("x", 2.0)]), Ok(0.0));
+
+    // Branches and the right side of `&&` and `||` are evaluated lazily
+    let expr = Expr::parse("x != 0 ? 1 / x : 0", false).unwrap();
+    assert_eq!(expr.eval_with_var("x", 0.0), Ok(0.0));
+    let expr = Expr::parse("x == 0 || 1 / x > 1", false).unwrap();
+    assert_eq!(expr.eval_with_var("x", 0.0), Ok(1.0));
+    assert_eq!(Expr::parse("0 && 1 / 0", false).unwrap().eval_const(), Ok(0.0));
+
+    // With a constant condition only the chosen branch is kept
+    assert_eq!(Expr::parse("1 < 2 ? x : y", true), Ok(Expr::Var("x".to_string())));
+    assert_eq!(Expr::parse("if(pi > 3, 1, x)", true), Ok(Expr::Num(1.0)));
+
+    // Implicit evaluation fails only in the branches that are taken
+    let expr = Expr::parse("x > 0 ? 1 : 1/0", true).unwrap();
+    assert_eq!(expr.eval_with_var("x", 1.0), Ok(1.0));
+    assert_eq!(expr.eval_with_var("x", -1.0), Err(EvalError::DivisionByZero));
+    let expr = Expr::parse("if(x > 0, 1, ln(0))", true).unwrap();
+    assert_eq!(expr.eval_with_var("x", 1.0), Ok(1.0));
+    let expr = Expr::parse("x > 0 && 1/0 > 1", true).unwrap();
+    assert_eq!(expr.eval_with_var("x", -1.0), Ok(0.0));
+    assert_eq!(Expr::parse("0 && 1 / 0", true), Ok(Expr::Num(0.0)));
+    assert_eq!(Expr::parse("1 < 2 ? 1 : 1/0", true), Ok(Expr::Num(1.0)));
+    assert!(matches!(Expr::parse("1 > 2 ? 1 : 1/0", true), Err(ParseError::EvalError(EvalError::DivisionByZero, _))));
+    assert!(matches!(Expr::parse("1 || 0 ? 1/0 : 1", true), Err(ParseError::EvalError(..))));
+    assert!(matches!(Expr::parse("1 && 1/0", true), Err(ParseError::EvalError(..))));
+    assert!(Expr::parse("x ? 1 : 1/0 + ln(0)", true).is_ok());
+
+    assert!(matches!(Expr::parse("x ? 1", false), Err(ParseError::UnexpectedEof(_))));
+    assert_eq!(
+        Expr::parse("if(x, 1)", false),
+        Err(ParseError::WrongNumberOfArgs("if".to_string(), 2, Span::new(0, 8)))
+    );
+    assert_eq!(Expr::parse("x & y", false), Err(ParseError::UnexpectedChar('&', Span::new(2, 3))));
+}
+
+#[test]
+fn factorial_and_combinatorics() {
+    let cases = [
+        ("0!", 1.0),
+        ("5!", 120.0),
+        ("3!^2", 36.0),
+        ("2^3!", 64.0),
+        ("-3!", -6.0),
+        ("gamma(5)", 24.0),
+        ("nCr(5, 2)", 10.0),
+        ("binomial(52, 5)", 2_598_960.0),
+        ("nCr(2, 5)", 0.0),
+        ("nPr(5, 2)", 20.0),
+        ("nPr(5, 0)", 1.0),
+    ];
+
+    for (input, expected) in cases {
+        assert_eq!(Expr::parse(input, true), Ok(Expr::Num(expected)), "{}", input);
+    }
+
+    let approx = |input: &str, expected: f32| {
+        let result = Expr::parse(input, false).unwrap().eval_const().unwrap();
+        assert!((result - expected).abs() <= 1e-4 * expected.abs().max(1.0), "{} = {}", input, result);
+    };
+
+    approx("0.5!", 0.886_226_9);
+    approx("gamma(0.5)", std::f32::consts::PI.sqrt());
+    approx("gamma(-0.5)", -2.0 * std::f32::consts::PI.sqrt());
+    approx("lgamma(10)", 12.801_827);
+    approx("lgamma(100)", 359.134_2);
+    approx("lgamma(-0.5)", 1.265_512_1);
+
+    // Huge arguments stop as soon as the result overflows, they don't loop `n` times
+    assert_eq!(Expr::parse("nPr(1e12, 1e12)", true), Ok(Expr::Num(f32::INFINITY)));
+    assert_eq!(Expr::parse("nCr(1e12, 5e11)", true), Ok(Expr::Num(f32::INFINITY)));
+    assert_eq!(Expr::parse("nCr(1e12, 1e12)", true), Ok(Expr::Num(1.0)));
+    assert_eq!(Expr::parse("nCr(1e12, 1)", true), Ok(Expr::Num(1e12)));
+}
+
+#[test]
+fn factorial_domain_errors() {
+    let expr = Expr::parse("x!", false).unwrap();
+    assert_eq!(expr.eval_with_var("x", -2.0), Err(EvalError::InvalidFactorial));
+
+    assert_eq!(Expr::parse("gamma(0)", false).unwrap().eval_const(), Err(EvalError::InvalidFactorial));
+    assert_eq!(Expr::parse("lgamma(-3)", false).unwrap().eval_const(), Err(EvalError::InvalidFactorial));
+    assert_eq!(Expr::parse("nCr(-1, 2)", false).unwrap().eval_const(), Err(EvalError::InvalidCombination));
+    assert_eq!(Expr::parse("nPr(4, 1.5)", false).unwrap().eval_const(), Err(EvalError::InvalidCombination));
+    assert_eq!(
+        Expr::parse("(-1)!", true),
+        Err(ParseError::EvalError(EvalError::InvalidFactorial, Span::new(0, 5)))
+    );
+}
+
+#[test]
+fn modulo_and_rounding() {
+    let cases = [
+        ("7 % 3", 1.0),
+        ("-7 % 3", 2.0),
+        ("7 % -3", -2.0),
+        ("5.5 % 2", 1.5),
+        ("7 // 2", 3.0),
+        ("-7 // 2", -4.0),
+        ("-7 // 2 * 2 + -7 % 2", -7.0),
+        ("floor(-1.5)", -2.0),
+        ("ceil(-1.5)", -1.0),
+        ("round(2.5)", 3.0),
+        ("round(-2.5)", -3.0),
+        ("trunc(-1.5)", -1.0),
+        ("frac(-1.25)", -0.25),
+        ("sign(-3)", -1.0),
+        ("sign(0)", 0.0),
+        ("sign(2)", 1.0),
+    ];
+
+    for (input, expected) in cases {
+        assert_eq!(Expr::parse(input, true), Ok(Expr::Num(expected)), "{}", input);
+    }
+
+    let expr = Expr::parse("x % 0", false).unwrap();
+    assert_eq!(expr.eval_with_var("x", 1.0), Err(EvalError::DivisionByZero));
+    let expr = Expr::parse("x // y", false).unwrap();
+    assert_eq!(expr.eval_with(&[("x", 1.0), ("y", 0.0)]), Err(EvalError::DivisionByZero));
+    assert_eq!(
+        Expr::parse("1 % 0", true),
+        Err(ParseError::EvalError(EvalError::DivisionByZero, Span::new(0, 5)))
+    );
+
+    assert_eq!(Expr::parse("x % 2 + y // 3", false).unwrap().to_string(), "x % 2 + y // 3");
+    assert_eq!(Expr::parse("round(x) - sign(x)", false).unwrap().to_string(), "round(x) - sign(x)");
+}
+
 //////////////////////////////////////////////////////////////////////////////
 //  These test can't fail because they are just for testing functionality.  //
 //////////////////////////////////////////////////////////////////////////////
@@ -164,3 +749,70 @@ fn display() {
 
     print!("{}", expr);
 }
+
+#[test]
+fn minimal_parentheses() {
+    let cases = [
+        ("(a + b) + c", "a + b + c"),
+        ("a + (b + c)", "a + (b + c)"),
+        ("a - (-b)", "a - (-b)"),
+        ("-(a * b)", "-(a * b)"),
+        ("-a * b", "-a * b"),
+        ("(-2)^2", "(-2)^2"),
+        ("-2^2", "-2^2"),
+        ("2^-x", "2^(-x)"),
+        ("ln(x) + log(x) + log(2, x)", "ln(x) + log(x) + log(2, x)"),
+        ("sqrt(x)^2", "sqrt(x)^2"),
+        ("(x + 1)! * x!", "(x + 1)! * x!"),
+        ("(a < b) == c", "(a < b) == c"),
+        ("a && !b", "a && !b"),
+        ("a ? (b ? c : d) : e ? f : g", "a ? (b ? c : d) : e ? f : g"),
+        ("D(x, x^2, 3)", "D(x, x^2, 3)"),
+    ];
+
+    for (input, expected) in cases {
+        assert_eq!(Expr::parse(input, false).unwrap().to_string(), expected, "{}", input);
+    }
+
+    // Substitution into the derivative variable is delayed, it is printed as the third argument
+    let mut expr = Expr::parse("D(x, x^2)", false).unwrap();
+    expr.substitute("x", Expr::Num(3.0));
+    assert_eq!(expr.to_string(), "D(x, x^2, 3)");
+    assert_eq!(Expr::parse(&expr.to_string(), false), Ok(expr));
+
+    assert_eq!(Expr::parse("-2", false), Ok(Expr::Num(-2.0)));
+    assert_eq!(Expr::parse("-x", true).unwrap().to_string(), "-x");
+}
+
+#[test]
+fn print_options() {
+    let options = ParseOptions {
+        implicit_multiplication: true,
+        ..Default::default()
+    };
+    let expr = Expr::parse_with("2x * (y + 1) + pi * |x|", &options).unwrap();
+
+    let print = |compact, implicit_multiplication, constant_values, abs_bars| {
+        expr.to_string_with(&PrintOptions {
+            compact,
+            implicit_multiplication,
+            constant_values,
+            abs_bars,
+        })
+    };
+
+    assert_eq!(print(false, false, false, false), "2 * x * (y + 1) + pi * abs(x)");
+    assert_eq!(print(true, false, false, false), "2*x*(y+1)+pi*abs(x)");
+    assert_eq!(print(false, true, false, false), "2x(y + 1) + pi abs(x)");
+    assert_eq!(print(false, false, true, false), "2 * x * (y + 1) + 3.1415927 * abs(x)");
+    assert_eq!(print(false, true, false, true), "2x(y + 1) + pi * |x|");
+    assert_eq!(format!("{:#}", expr), "2 * x * (y + 1) + pi * |x|");
+
+    // Letters that would continue the number literal are separated
+    let expr = Expr::parse("2 * e + 0 * x", false).unwrap();
+    let implicit = PrintOptions {
+        implicit_multiplication: true,
+        ..Default::default()
+    };
+    assert_eq!(expr.to_string_with(&implicit), "2 e + 0 x");
+}
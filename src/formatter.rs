@@ -0,0 +1,232 @@
+use crate::parser::{Lexer, SyntaxNode, SyntaxToken, SyntaxTree, Token, PREFIX_BINDING_POWER};
+
+impl SyntaxTree<'_> {
+    /// Prints the expression in the canonical form, keeping the names, number literals and comments as written
+    ///
+    /// Binary operators are surrounded by single spaces except `^`, there is a space after commas and none
+    /// inside parentheses, after prefix or before postfix operators. Implicit multiplication is printed without
+    /// a space where the tokens don't merge, `2x`, `(a + b)(a - b)`. Parentheses the precedence doesn't need are removed.
+    ///
+    /// ```
+    /// use pemel::prelude::*;
+    ///
+    /// let tree = SyntaxTree::parse("((a+b))*c - (d^2) /* note */", &ParseOptions::default()).unwrap();
+    /// assert_eq!(tree.format(), "(a + b) * c - d^2 /* note */");
+    /// ```
+    ///
+    /// The formatted text is parsed to the same `Expr` as the input, with the same options.
+    pub fn format(&self) -> String {
+        let mut formatter = Formatter {
+            out: String::new(),
+            last: "",
+            after_comment: false,
+        };
+
+        formatter.node(&self.root, Position::Top, false);
+        formatter.comments(self.eof.leading_trivia);
+
+        // The new line ending the last line comment
+        let len = formatter.out.trim_end().len();
+        formatter.out.truncate(len);
+        formatter.out
+    }
+}
+
+/// Where the node is, it decides if the parentheses around it are needed
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Position {
+    /// Whole expression, or delimited by parentheses, bars, commas or `? :`
+    Top,
+    /// Left operand of the operator with the left binding power, comparisons are chained so they don't nest
+    Left { bp: u8, comparison: bool },
+    /// Right operand of the operator with the right binding power
+    Right { bp: u8, comparison: bool },
+    /// Operand of the prefix `-`, `-(2)` is a negation, not the literal `-2`
+    Negated,
+    /// Left operand of the implicit multiplication, `(x)(y)` would be a call without the parentheses
+    ImplicitLeft,
+    /// Right operand of the implicit multiplication and the left operands in it,
+    /// the product is implicit only if the operand starts with an identifier or `(`, so it is kept as written
+    ImplicitRight,
+}
+
+impl Position {
+    /// Returns the position of the left operand, the first token of the implicit right operand is kept
+    fn left(self, bp: u8, comparison: bool) -> Position {
+        match self {
+            Position::ImplicitRight => Position::ImplicitRight,
+            _ => Position::Left { bp, comparison },
+        }
+    }
+
+    fn needs_parens(self, inner: &SyntaxNode) -> bool {
+        let (l_bp, r_bp) = inner.binding_power();
+
+        match self {
+            Position::Top => false,
+            // The operator after the operand would be taken into its right side, unless it binds looser
+            Position::Left { bp, comparison } => bp >= r_bp || (comparison && inner.is_comparison()),
+            Position::Right { bp, comparison } => l_bp < bp || (comparison && inner.is_comparison()),
+            Position::Negated => l_bp < PREFIX_BINDING_POWER || matches!(inner, SyntaxNode::Number(_)),
+            Position::ImplicitLeft => Position::Left { bp: 11, comparison: false }.needs_parens(inner)
+                || matches!(inner, SyntaxNode::Name(_)),
+            Position::ImplicitRight => true,
+        }
+    }
+}
+
+struct Formatter<'a> {
+    out: String,
+    // Text of the last printed token, to check that the next one doesn't merge with it
+    last: &'a str,
+    // Block comment was printed, it is separated from the next token by a space
+    after_comment: bool,
+}
+
+impl<'a> Formatter<'a> {
+    /// Prints the node, `space` tells if its first token is separated by a space
+    fn node(&mut self, node: &SyntaxNode<'a>, position: Position, space: bool) {
+        match node {
+            SyntaxNode::Number(token) | SyntaxNode::Name(token) => self.token(token, space),
+
+            SyntaxNode::Parens { open, inner, close } if !position.needs_parens(inner) => {
+                self.comments(open.leading_trivia);
+                self.node(inner, position, space);
+                self.comments(close.leading_trivia);
+            }
+
+            SyntaxNode::Parens { open, inner, close } | SyntaxNode::Abs { open, inner, close } => {
+                self.token(open, space);
+                self.node(inner, Position::Top, false);
+                self.token(close, false);
+            }
+
+            SyntaxNode::Call { name, open, args, commas, close } => {
+                self.token(name, space);
+                self.token(open, false);
+
+                for (i, arg) in args.iter().enumerate() {
+                    if let Some(comma) = i.checked_sub(1).and_then(|i| commas.get(i)) {
+                        self.token(comma, false);
+                    }
+                    self.node(arg, Position::Top, i > 0);
+                }
+
+                self.token(close, false);
+            }
+
+            SyntaxNode::Prefix { op, operand } => {
+                self.token(op, space);
+                let position = match op.token {
+                    Token::Minus => Position::Negated,
+                    _ => Position::Right { bp: PREFIX_BINDING_POWER, comparison: false },
+                };
+                self.node(operand, position, false);
+            }
+
+            SyntaxNode::Postfix { operand, op } => {
+                let (l_bp, _) = node.binding_power();
+                self.node(operand, position.left(l_bp, false), space);
+                self.token(op, false);
+            }
+
+            SyntaxNode::Binary { lhs, op: None, rhs } => {
+                let position = match position {
+                    Position::ImplicitRight => Position::ImplicitRight,
+                    _ => Position::ImplicitLeft,
+                };
+                self.node(lhs, position, space);
+                self.node(rhs, Position::ImplicitRight, false);
+            }
+
+            SyntaxNode::Binary { lhs, op: Some(op), rhs } => {
+                let (l_bp, r_bp) = node.binding_power();
+                let comparison = node.is_comparison();
+                let spaced = op.token != Token::Caret;
+
+                self.node(lhs, position.left(l_bp, comparison), space);
+                self.token(op, spaced);
+                self.node(rhs, Position::Right { bp: r_bp, comparison }, spaced);
+            }
+
+            SyntaxNode::Conditional { cond, question, then, colon, otherwise } => {
+                let (l_bp, r_bp) = node.binding_power();
+
+                self.node(cond, position.left(l_bp, false), space);
+                self.token(question, true);
+                self.node(then, Position::Top, true);
+                self.token(colon, true);
+                self.node(otherwise, Position::Right { bp: r_bp, comparison: false }, true);
+            }
+        }
+    }
+
+    /// Prints the comments before the token and the token, with a space if it would merge with the previous one
+    fn token(&mut self, token: &SyntaxToken<'a>, space: bool) {
+        self.comments(token.leading_trivia);
+
+        // The `^` of a superscript
+        if token.text.is_empty() {
+            return;
+        }
+
+        if (space || self.after_comment || merges(self.last, token.text)) && !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push(' ');
+        }
+
+        self.out.push_str(token.text);
+        self.last = token.text;
+        self.after_comment = false;
+    }
+
+    /// Prints the comments in the trivia, each line comment ends with a new line
+    fn comments(&mut self, trivia: &'a str) {
+        let mut rest = trivia;
+
+        while let Some(start) = rest.find(['#', '/']) {
+            let comment = &rest[start..];
+            // Trivia has `//` only if it is a line comment, see `ParseOptions::slash_comments`
+            let is_line = comment.starts_with('#') || comment.starts_with("//");
+            let len = match is_line {
+                true => comment.find('\n').unwrap_or(comment.len()),
+                false => comment.find("*/").map_or(comment.len(), |i| i + 2),
+            };
+
+            if !self.out.is_empty() && !self.out.ends_with('\n') {
+                self.out.push(' ');
+            }
+
+            self.out.push_str(&comment[..len]);
+            self.last = "";
+
+            if is_line {
+                self.out.push('\n');
+            } else {
+                self.after_comment = true;
+            }
+
+            rest = &comment[len..];
+        }
+    }
+}
+
+/// Returns true if the tokens written next to each other would be read differently, like `2` and `e5` or `x` and `y`
+///
+/// Two bars are always read the same, the parser splits `||` where it closes or opens two absolute values.
+fn merges(last: &str, next: &str) -> bool {
+    if last.is_empty() || (last == "|" && next == "|") {
+        return false;
+    }
+
+    let joined = format!("{}{}", last, next);
+    let boundary = last.len();
+
+    Lexer::new(&joined).any(|result| {
+        let span = match result {
+            Ok((_, span)) => span,
+            Err(err) => err.span(),
+        };
+
+        span.start < boundary && boundary < span.end
+    })
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use crate::eval_error::EvalError;
+use crate::names::CaseIndex;
+
+type EvalFn = dyn Fn(&[f32]) -> Result<f32, EvalError> + Send + Sync;
+type DerivativeFn = dyn Fn(&[f32], usize) -> Result<f32, EvalError> + Send + Sync;
+
+/// Number of arguments a function accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Fixed(usize),
+    /// Any number of arguments, but at least `min`
+    Variadic { min: usize },
+    /// From `min` to `max` arguments, like `log(x)` and `log(base, x)`
+    Range { min: usize, max: usize },
+}
+
+impl Arity {
+    pub fn accepts(&self, count: usize) -> bool {
+        match *self {
+            Arity::Fixed(n) => count == n,
+            Arity::Variadic { min } => count >= min,
+            Arity::Range { min, max } => (min..=max).contains(&count),
+        }
+    }
+}
+
+/// Native function that can be called from expressions, see `FunctionRegistry`
+///
+/// Functions are pure by default, which means that calls with constant arguments
+/// are evaluated during parsing if the implicit evaluation is enabled.
+#[derive(Clone)]
+pub struct Function {
+    name: String,
+    arity: Arity,
+    pure: bool,
+    eval: Arc<EvalFn>,
+    derivative: Option<Arc<DerivativeFn>>,
+}
+
+impl Function {
+    pub fn new(
+        name: impl Into<String>,
+        arity: Arity,
+        eval: impl Fn(&[f32]) -> Result<f32, EvalError> + Send + Sync + 'static,
+    ) -> Self {
+        Function {
+            name: name.into(),
+            arity,
+            pure: true,
+            eval: Arc::new(eval),
+            derivative: None,
+        }
+    }
+
+    /// Marks the function as impure, so it is never evaluated during parsing
+    pub fn impure(mut self) -> Self {
+        self.pure = false;
+        self
+    }
+
+    /// Sets the partial derivative of the function
+    ///
+    /// The closure gets the argument values and the index of the argument to derivate by.
+    ///
+    /// It is used instead of the numeric approximation when the derivative of a call is evaluated.
+    pub fn with_derivative(
+        mut self,
+        derivative: impl Fn(&[f32], usize) -> Result<f32, EvalError> + Send + Sync + 'static,
+    ) -> Self {
+        self.derivative = Some(Arc::new(derivative));
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    pub fn is_pure(&self) -> bool {
+        self.pure
+    }
+
+    pub fn has_derivative(&self) -> bool {
+        self.derivative.is_some()
+    }
+
+    pub fn call(&self, args: &[f32]) -> Result<f32, EvalError> {
+        if !self.arity.accepts(args.len()) {
+            return Err(EvalError::WrongNumberOfArgs(self.name.clone(), args.len()));
+        }
+
+        (self.eval)(args)
+    }
+
+    /// Evaluates the partial derivative by the argument at `index`, returns `None` if the function has no derivative
+    pub fn call_derivative(&self, args: &[f32], index: usize) -> Option<Result<f32, EvalError>> {
+        self.derivative.as_ref().map(|derivative| derivative(args, index))
+    }
+}
+
+impl Debug for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Function")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .field("pure", &self.pure)
+            .finish_non_exhaustive()
+    }
+}
+
+// Closures can't be compared, so two functions are equal only if they share the same closure
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.arity == other.arity
+            && self.pure == other.pure
+            && Arc::ptr_eq(&self.eval, &other.eval)
+    }
+}
+
+/// User defined functions available to the parser
+///
+/// Names of the built-in functions (`sin`, `log`, `D`, ...) take precedence over the registered ones.
+///
+/// ```
+/// use pemel::prelude::*;
+///
+/// let mut options = ParseOptions::default();
+/// options.functions.register(Function::new("clamp", Arity::Fixed(3), |args| {
+///     Ok(args[0].clamp(args[1], args[2]))
+/// }));
+///
+/// let expr = Expr::parse_with("clamp(x, 0, 1)", &options).unwrap();
+/// assert_eq!(expr.eval_with_var("x", 1.5), Ok(1.0));
+/// ```
+///
+/// Calls are parsed as `Expr::Call`, which holds the function itself,
+/// so the registry is not needed for evaluation.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FunctionRegistry {
+    functions: HashMap<String, Arc<Function>>,
+    names: CaseIndex,
+}
+
+impl FunctionRegistry {
+    /// Registers the function, replacing the previous one with the same name
+    pub fn register(&mut self, function: Function) {
+        self.names.insert(&function.name);
+        self.functions.insert(function.name.clone(), Arc::new(function));
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Arc<Function>> {
+        self.names.remove(name);
+        self.functions.remove(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<Function>> {
+        self.functions.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Function>> {
+        self.functions.values()
+    }
+
+    /// Returns the name of the function with the given lowercase name, for the case insensitive parsing
+    pub(crate) fn get_ignore_case(&self, lowercase: &str) -> Option<&str> {
+        self.names.get(lowercase)
+    }
+}
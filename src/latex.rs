@@ -0,0 +1,182 @@
+use crate::expr::{Expr, PREFIX_PRECEDENCE};
+
+impl Expr {
+    /// Renders the expression as LaTeX, with only the parentheses the precedence requires
+    ///
+    /// ```
+    /// use pemel::prelude::*;
+    ///
+    /// let expr = Expr::parse("(x + 1) / 2 * sqrt(x)^2", false).unwrap();
+    /// assert_eq!(expr.to_latex(), r"\frac{x + 1}{2} \cdot \sqrt{x}^{2}");
+    /// ```
+    ///
+    /// The output parses back with `Expr::parse_latex` to the same expression, except `Expr::Error`,
+    /// and `floor(a / b)`, which is written like `a // b` and parses back as the floor division.
+    /// Functions called by name have to be registered in the options of the parser,
+    /// and angles of `Expr::Degrees` parse back only with `AngleUnit::Degrees`.
+    pub fn to_latex(&self) -> String {
+        match self {
+            Expr::Num(n) => n.to_string(),
+            Expr::Var(name) | Expr::Const(name, _) => latex_name(name),
+            Expr::Error => r"\text{error}".to_string(),
+
+            Expr::Add(lhs, rhs) => binop(lhs, "+", rhs, 9),
+            Expr::Sub(lhs, rhs) => binop(lhs, "-", rhs, 9),
+            Expr::Mul(lhs, rhs) if **lhs == Expr::Num(-1.0) => format!("-{}", operand(rhs, PREFIX_PRECEDENCE + 1)),
+            Expr::Mul(lhs, rhs) if is_juxtaposable(lhs, rhs) => format!("{}{}", lhs.to_latex(), rhs.to_latex()),
+            Expr::Mul(lhs, rhs) => binop(lhs, r"\cdot", rhs, 11),
+            Expr::Div(lhs, rhs) => format!(r"\frac{{{}}}{{{}}}", lhs.to_latex(), rhs.to_latex()),
+            Expr::Mod(lhs, rhs) => binop(lhs, r"\bmod", rhs, 11),
+            Expr::FloorDiv(lhs, rhs) => {
+                format!(r"\left\lfloor \frac{{{}}}{{{}}} \right\rfloor", lhs.to_latex(), rhs.to_latex())
+            }
+
+            Expr::Pow(base, exp) => match exp.as_ref() {
+                Expr::Num(n) if *n == 0.5 => format!(r"\sqrt{{{}}}", base.to_latex()),
+                Expr::Div(one, index) if **one == Expr::Num(1.0) => {
+                    format!(r"\sqrt[{}]{{{}}}", index.to_latex(), base.to_latex())
+                }
+                // Right associative, so a power in the base needs parentheses
+                _ => format!("{}^{{{}}}", operand(base, 16), exp.to_latex()),
+            },
+
+            Expr::Log(base, arg) => match base.as_ref() {
+                Expr::Const(name, _) if name == "e" => format!(r"\ln{}", parens(arg)),
+                Expr::Num(n) if *n == 10.0 => format!(r"\log{}", parens(arg)),
+                base => format!(r"\log_{{{}}}{}", base.to_latex(), parens(arg)),
+            },
+
+            Expr::Sin(inner) => format!(r"\sin{}", parens(inner)),
+            Expr::Cos(inner) => format!(r"\cos{}", parens(inner)),
+            Expr::Tan(inner) => format!(r"\tan{}", parens(inner)),
+            Expr::Cot(inner) => format!(r"\cot{}", parens(inner)),
+            // The angle is written in degrees, like in the input
+            Expr::Degrees(inner) => inner.to_latex(),
+            Expr::Abs(inner) => format!(r"\left|{}\right|", inner.to_latex()),
+            Expr::Floor(inner) => format!(r"\left\lfloor {} \right\rfloor", inner.to_latex()),
+            Expr::Ceil(inner) => format!(r"\left\lceil {} \right\rceil", inner.to_latex()),
+            Expr::Round(inner) => format!(r"\operatorname{{round}}{}", parens(inner)),
+            Expr::Trunc(inner) => format!(r"\operatorname{{trunc}}{}", parens(inner)),
+            Expr::Frac(inner) => format!(r"\operatorname{{frac}}{}", parens(inner)),
+            Expr::Sign(inner) => format!(r"\operatorname{{sgn}}{}", parens(inner)),
+            Expr::Gamma(inner) => format!(r"\Gamma{}", parens(inner)),
+            Expr::LnGamma(inner) => format!(r"\ln\Gamma{}", parens(inner)),
+            Expr::Factorial(inner) => format!("{}!", operand(inner, 18)),
+            Expr::Comb(n, k) => format!(r"\binom{{{}}}{{{}}}", n.to_latex(), k.to_latex()),
+            Expr::Perm(n, k) => format!("{{}}_{{{}}}P_{{{}}}", n.to_latex(), k.to_latex()),
+
+            Expr::Lt(lhs, rhs) => binop(lhs, "<", rhs, 8),
+            Expr::Le(lhs, rhs) => binop(lhs, r"\le", rhs, 8),
+            Expr::Eq(lhs, rhs) => binop(lhs, "=", rhs, 8),
+            Expr::Ne(lhs, rhs) => binop(lhs, r"\ne", rhs, 8),
+            Expr::Gt(lhs, rhs) => binop(lhs, ">", rhs, 8),
+            Expr::Ge(lhs, rhs) => binop(lhs, r"\ge", rhs, 8),
+            Expr::And(lhs, rhs) => binop(lhs, r"\land", rhs, 5),
+            Expr::Or(lhs, rhs) => binop(lhs, r"\lor", rhs, 3),
+            Expr::Not(inner) => format!(r"\lnot {}", operand(inner, PREFIX_PRECEDENCE + 1)),
+            Expr::Cond(cond, then, otherwise) => format!(
+                r"\begin{{cases}} {} & \text{{if }} {} \\ {} & \text{{otherwise}} \end{{cases}}",
+                then.to_latex(),
+                cond.to_latex(),
+                otherwise.to_latex()
+            ),
+
+            Expr::Derivative(expr, var, sub) => {
+                let derivative = format!(r"\frac{{d}}{{d{}}}{}", latex_name(var), parens(expr));
+
+                match sub {
+                    None => derivative,
+                    Some(sub) => format!(r"\left.{}\right|_{{{}={}}}", derivative, latex_name(var), sub.to_latex()),
+                }
+            }
+
+            Expr::Call(func, args) => {
+                let args: Vec<String> = args.iter().map(Expr::to_latex).collect();
+                format!(r"\operatorname{{{}}}\left({}\right)", func.name(), args.join(", "))
+            }
+        }
+    }
+}
+
+/// Left associative binary operator, `precedence` is the level of the operator
+///
+/// The right operand on the same level needs parentheses, so `a - (b - c)` keeps them.
+/// Negative right operand is always in parentheses, so `a - (-b)` is not printed as `a - -b`.
+fn binop(lhs: &Expr, op: &str, rhs: &Expr, precedence: u8) -> String {
+    let rhs = if rhs.precedence() == PREFIX_PRECEDENCE && !matches!(rhs, Expr::Not(_)) {
+        parens(rhs)
+    } else {
+        operand(rhs, precedence + 1)
+    };
+
+    format!("{} {} {}", operand(lhs, precedence), op, rhs)
+}
+
+/// Renders the operand, in parentheses if it binds looser than `min_precedence`
+fn operand(expr: &Expr, min_precedence: u8) -> String {
+    if expr.precedence() < min_precedence {
+        parens(expr)
+    } else {
+        expr.to_latex()
+    }
+}
+
+fn parens(expr: &Expr) -> String {
+    format!(r"\left({}\right)", expr.to_latex())
+}
+
+/// Returns true if the product can be written without the operator, like `2x` or `3\sin(x)`
+fn is_juxtaposable(lhs: &Expr, rhs: &Expr) -> bool {
+    let is_letter = |expr: &Expr| matches!(expr, Expr::Var(_) | Expr::Const(_, _));
+
+    let is_rhs_juxtaposable = match rhs {
+        Expr::Pow(base, _) => is_letter(base),
+        Expr::Sin(_) | Expr::Cos(_) | Expr::Tan(_) | Expr::Cot(_) | Expr::Log(_, _) | Expr::Call(_, _) => true,
+        rhs => is_letter(rhs),
+    };
+
+    matches!(lhs, Expr::Num(n) if n.is_sign_positive()) && is_rhs_juxtaposable
+}
+
+/// Names of Greek letters and known constants are commands, longer names are upright
+fn latex_name(name: &str) -> String {
+    let command = match name {
+        "pi" | "π" => "pi",
+        "tau" => "tau",
+        "phi" => "varphi",
+        "inf" => "infty",
+        "α" => "alpha",
+        "β" => "beta",
+        "γ" => "gamma",
+        "δ" => "delta",
+        "ε" => "varepsilon",
+        "ζ" => "zeta",
+        "η" => "eta",
+        "θ" => "theta",
+        "ι" => "iota",
+        "κ" => "kappa",
+        "λ" => "lambda",
+        "μ" => "mu",
+        "ν" => "nu",
+        "ξ" => "xi",
+        "ρ" => "rho",
+        "σ" => "sigma",
+        "υ" => "upsilon",
+        "χ" => "chi",
+        "ψ" => "psi",
+        "ω" => "omega",
+        "Γ" => "Gamma",
+        "Δ" => "Delta",
+        "Θ" => "Theta",
+        "Λ" => "Lambda",
+        "Ξ" => "Xi",
+        "Σ" => "Sigma",
+        "Φ" => "Phi",
+        "Ψ" => "Psi",
+        "Ω" => "Omega",
+        name if name.chars().count() == 1 => return name.to_string(),
+        name => return format!(r"\mathrm{{{}}}", name),
+    };
+
+    format!(r"\{}", command)
+}
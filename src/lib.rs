@@ -1,17 +1,40 @@
 // #![allow(unused)]
 // #![deny(warnings)]
 
+mod constants;
+mod editor;
 mod eval_error;
 mod expr;
+mod formatter;
+mod function;
+mod latex;
 mod macros;
+mod mathml;
+mod names;
 mod parser;
+mod printer;
+mod program;
+mod scope;
+mod special;
 
 #[cfg(test)]
 mod expr_test;
+#[cfg(test)]
+mod parser_test;
 
 pub mod prelude {
+    pub use crate::constants::Constants;
+    pub use crate::editor::{Completion, CompletionKind, Document, Hover, SemanticKind, SemanticToken};
     pub use crate::eval_error::EvalError;
     pub use crate::expr::Expr;
+    pub use crate::function::{Arity, Function, FunctionRegistry};
+    pub use crate::parser::Lexer;
     pub use crate::parser::ParseError;
+    pub use crate::parser::{AngleUnit, NumberLiterals, ParseOptions};
+    pub use crate::parser::{Location, Span};
+    pub use crate::parser::{SyntaxNode, SyntaxToken, SyntaxTree};
     pub use crate::parser::Token;
+    pub use crate::printer::PrintOptions;
+    pub use crate::program::Program;
+    pub use crate::scope::{Definition, Scope};
 }
@@ -4,8 +4,18 @@ macro_rules! expr_pat {
             | Expr::Sub($lhs, $rhs)
             | Expr::Mul($lhs, $rhs)
             | Expr::Div($lhs, $rhs)
+            | Expr::Mod($lhs, $rhs)
+            | Expr::FloorDiv($lhs, $rhs)
             | Expr::Pow($lhs, $rhs)
             | Expr::Log($lhs, $rhs)
+            | Expr::Lt($lhs, $rhs)
+            | Expr::Le($lhs, $rhs)
+            | Expr::Eq($lhs, $rhs)
+            | Expr::Ne($lhs, $rhs)
+            | Expr::Gt($lhs, $rhs)
+            | Expr::Ge($lhs, $rhs)
+            | Expr::Comb($lhs, $rhs)
+            | Expr::Perm($lhs, $rhs)
     };
 
     (UNOP: $inner:ident) => {
@@ -13,7 +23,18 @@ macro_rules! expr_pat {
             | Expr::Cos($inner)
             | Expr::Tan($inner)
             | Expr::Cot($inner)
+            | Expr::Degrees($inner)
             | Expr::Abs($inner)
+            | Expr::Floor($inner)
+            | Expr::Ceil($inner)
+            | Expr::Round($inner)
+            | Expr::Trunc($inner)
+            | Expr::Frac($inner)
+            | Expr::Sign($inner)
+            | Expr::Not($inner)
+            | Expr::Factorial($inner)
+            | Expr::Gamma($inner)
+            | Expr::LnGamma($inner)
     };
 }
 
@@ -0,0 +1,301 @@
+use crate::expr::{Expr, PREFIX_PRECEDENCE};
+
+const MATH_START: &str = r#"<math xmlns="http://www.w3.org/1998/Math/MathML">"#;
+const MATH_END: &str = "</math>";
+
+impl Expr {
+    /// Renders the expression as presentation MathML, with only the parentheses the precedence requires
+    ///
+    /// ```
+    /// use pemel::prelude::*;
+    ///
+    /// let expr = Expr::parse("sqrt(x) / 2", false).unwrap();
+    /// assert_eq!(
+    ///     expr.to_presentation_mathml(),
+    ///     r#"<math xmlns="http://www.w3.org/1998/Math/MathML"><mfrac><msqrt><mi>x</mi></msqrt><mn>2</mn></mfrac></math>"#
+    /// );
+    /// ```
+    pub fn to_presentation_mathml(&self) -> String {
+        format!("{}{}{}", MATH_START, self.presentation(), MATH_END)
+    }
+
+    /// Renders the expression as content MathML, like `<apply><plus/><ci>x</ci><cn>1</cn></apply>`
+    ///
+    /// Operators without a MathML element, like `round` or `lgamma`, are written as `<csymbol>`
+    /// with the name of the function in the text syntax, calls of registered functions as `<ci>` heads.
+    /// `%` and `//` are written as `<rem/>` and `<quotient/>`, they keep the floor semantics of the text syntax.
+    ///
+    /// The output can be parsed back with `Expr::parse_content_mathml`.
+    pub fn to_content_mathml(&self) -> String {
+        format!("{}{}{}", MATH_START, self.content(), MATH_END)
+    }
+
+    fn presentation(&self) -> String {
+        match self {
+            Expr::Num(n) if n.is_nan() => "<mi>NaN</mi>".to_string(),
+            Expr::Num(n) if n.is_infinite() && *n > 0.0 => "<mi>∞</mi>".to_string(),
+            Expr::Num(n) if n.is_sign_negative() => format!("<mrow><mo>-</mo>{}</mrow>", Expr::Num(-n).presentation()),
+            Expr::Num(n) => format!("<mn>{}</mn>", n),
+            Expr::Var(name) | Expr::Const(name, _) => format!("<mi>{}</mi>", identifier(name)),
+            Expr::Error => "<merror><mtext>error</mtext></merror>".to_string(),
+
+            Expr::Add(lhs, rhs) => binop(lhs, "+", rhs, 9),
+            Expr::Sub(lhs, rhs) => binop(lhs, "-", rhs, 9),
+            Expr::Mul(lhs, rhs) if **lhs == Expr::Num(-1.0) => {
+                format!("<mrow><mo>-</mo>{}</mrow>", operand(rhs, PREFIX_PRECEDENCE + 1))
+            }
+            Expr::Mul(lhs, rhs) if is_juxtaposable(lhs, rhs) => {
+                format!("<mrow>{}<mo>&#x2062;</mo>{}</mrow>", lhs.presentation(), rhs.presentation())
+            }
+            Expr::Mul(lhs, rhs) => binop(lhs, "⋅", rhs, 11),
+            Expr::Div(lhs, rhs) => format!("<mfrac>{}{}</mfrac>", lhs.presentation(), rhs.presentation()),
+            Expr::Mod(lhs, rhs) => binop(lhs, "mod", rhs, 11),
+            Expr::FloorDiv(lhs, rhs) => {
+                let fraction = format!("<mfrac>{}{}</mfrac>", lhs.presentation(), rhs.presentation());
+                fenced("⌊", &fraction, "⌋")
+            }
+
+            Expr::Pow(base, exp) => match exp.as_ref() {
+                Expr::Num(n) if *n == 0.5 => format!("<msqrt>{}</msqrt>", base.presentation()),
+                Expr::Div(one, index) if **one == Expr::Num(1.0) => {
+                    format!("<mroot>{}{}</mroot>", base.presentation(), index.presentation())
+                }
+                // Right associative, so a power in the base needs parentheses
+                _ => format!("<msup>{}{}</msup>", operand(base, 16), exp.presentation()),
+            },
+
+            Expr::Log(base, arg) => match base.as_ref() {
+                Expr::Const(name, _) if name == "e" => function("<mi>ln</mi>", &[arg]),
+                Expr::Num(n) if *n == 10.0 => function("<mi>log</mi>", &[arg]),
+                base => function(&format!("<msub><mi>log</mi>{}</msub>", base.presentation()), &[arg]),
+            },
+
+            Expr::Sin(inner) => function("<mi>sin</mi>", &[inner]),
+            Expr::Cos(inner) => function("<mi>cos</mi>", &[inner]),
+            Expr::Tan(inner) => function("<mi>tan</mi>", &[inner]),
+            Expr::Cot(inner) => function("<mi>cot</mi>", &[inner]),
+            // The angle is written in degrees, like in the input
+            Expr::Degrees(inner) => inner.presentation(),
+            Expr::Abs(inner) => fenced("|", &inner.presentation(), "|"),
+            Expr::Floor(inner) => fenced("⌊", &inner.presentation(), "⌋"),
+            Expr::Ceil(inner) => fenced("⌈", &inner.presentation(), "⌉"),
+            Expr::Round(inner) => function("<mi>round</mi>", &[inner]),
+            Expr::Trunc(inner) => function("<mi>trunc</mi>", &[inner]),
+            Expr::Frac(inner) => function("<mi>frac</mi>", &[inner]),
+            Expr::Sign(inner) => function("<mi>sgn</mi>", &[inner]),
+            Expr::Gamma(inner) => function("<mi mathvariant=\"normal\">Γ</mi>", &[inner]),
+            Expr::LnGamma(inner) => function("<mrow><mi>ln</mi><mi mathvariant=\"normal\">Γ</mi></mrow>", &[inner]),
+            Expr::Factorial(inner) => format!("<mrow>{}<mo>!</mo></mrow>", operand(inner, 18)),
+            Expr::Comb(n, k) => {
+                let binomial = format!("<mfrac linethickness=\"0\">{}{}</mfrac>", n.presentation(), k.presentation());
+                fenced("(", &binomial, ")")
+            }
+            Expr::Perm(n, k) => format!(
+                "<mmultiscripts><mi>P</mi>{}<none/><mprescripts/>{}<none/></mmultiscripts>",
+                k.presentation(),
+                n.presentation()
+            ),
+
+            Expr::Lt(lhs, rhs) => binop(lhs, "&lt;", rhs, 8),
+            Expr::Le(lhs, rhs) => binop(lhs, "≤", rhs, 8),
+            Expr::Eq(lhs, rhs) => binop(lhs, "=", rhs, 8),
+            Expr::Ne(lhs, rhs) => binop(lhs, "≠", rhs, 8),
+            Expr::Gt(lhs, rhs) => binop(lhs, "&gt;", rhs, 8),
+            Expr::Ge(lhs, rhs) => binop(lhs, "≥", rhs, 8),
+            Expr::And(lhs, rhs) => binop(lhs, "∧", rhs, 5),
+            Expr::Or(lhs, rhs) => binop(lhs, "∨", rhs, 3),
+            Expr::Not(inner) => format!("<mrow><mo>¬</mo>{}</mrow>", operand(inner, PREFIX_PRECEDENCE + 1)),
+            Expr::Cond(cond, then, otherwise) => format!(
+                "<mrow><mo>{{</mo><mtable>\
+                 <mtr><mtd>{}</mtd><mtd><mtext>if </mtext>{}</mtd></mtr>\
+                 <mtr><mtd>{}</mtd><mtd><mtext>otherwise</mtext></mtd></mtr>\
+                 </mtable></mrow>",
+                then.presentation(),
+                cond.presentation(),
+                otherwise.presentation()
+            ),
+
+            Expr::Derivative(expr, var, sub) => {
+                let derivative = format!(
+                    "<mrow><mfrac><mi>d</mi><mrow><mi>d</mi><mi>{}</mi></mrow></mfrac>{}</mrow>",
+                    identifier(var),
+                    parens(&expr.presentation())
+                );
+
+                match sub {
+                    None => derivative,
+                    Some(sub) => format!(
+                        "<msub><mrow>{}<mo>|</mo></mrow><mrow><mi>{}</mi><mo>=</mo>{}</mrow></msub>",
+                        derivative,
+                        identifier(var),
+                        sub.presentation()
+                    ),
+                }
+            }
+
+            Expr::Call(func, args) => {
+                let args: Vec<&Expr> = args.iter().collect();
+                function(&format!("<mi>{}</mi>", escape(func.name())), &args)
+            }
+        }
+    }
+
+    fn content(&self) -> String {
+        match self {
+            Expr::Num(n) if n.is_nan() => "<notanumber/>".to_string(),
+            Expr::Num(n) if n.is_infinite() && *n > 0.0 => "<infinity/>".to_string(),
+            Expr::Num(n) if n.is_infinite() => "<apply><minus/><infinity/></apply>".to_string(),
+            Expr::Num(n) => format!("<cn>{}</cn>", n),
+            Expr::Var(name) => format!("<ci>{}</ci>", escape(name)),
+            Expr::Const(name, _) => match name.as_str() {
+                "pi" => "<pi/>".to_string(),
+                "e" => "<exponentiale/>".to_string(),
+                "inf" => "<infinity/>".to_string(),
+                name => format!("<ci>{}</ci>", escape(name)),
+            },
+            Expr::Error => "<cerror><csymbol>error</csymbol></cerror>".to_string(),
+
+            Expr::Add(lhs, rhs) => apply("<plus/>", &[lhs, rhs]),
+            Expr::Sub(lhs, rhs) => apply("<minus/>", &[lhs, rhs]),
+            Expr::Mul(lhs, rhs) if **lhs == Expr::Num(-1.0) => apply("<minus/>", &[rhs]),
+            Expr::Mul(lhs, rhs) => apply("<times/>", &[lhs, rhs]),
+            Expr::Div(lhs, rhs) => apply("<divide/>", &[lhs, rhs]),
+            Expr::Mod(lhs, rhs) => apply("<rem/>", &[lhs, rhs]),
+            Expr::FloorDiv(lhs, rhs) => apply("<quotient/>", &[lhs, rhs]),
+
+            Expr::Pow(base, exp) => match exp.as_ref() {
+                Expr::Num(n) if *n == 0.5 => apply("<root/>", &[base]),
+                Expr::Div(one, index) if **one == Expr::Num(1.0) => {
+                    format!("<apply><root/><degree>{}</degree>{}</apply>", index.content(), base.content())
+                }
+                _ => apply("<power/>", &[base, exp]),
+            },
+
+            Expr::Log(base, arg) => match base.as_ref() {
+                Expr::Const(name, _) if name == "e" => apply("<ln/>", &[arg]),
+                Expr::Num(n) if *n == 10.0 => apply("<log/>", &[arg]),
+                base => format!("<apply><log/><logbase>{}</logbase>{}</apply>", base.content(), arg.content()),
+            },
+
+            Expr::Sin(inner) => apply("<sin/>", &[inner]),
+            Expr::Cos(inner) => apply("<cos/>", &[inner]),
+            Expr::Tan(inner) => apply("<tan/>", &[inner]),
+            Expr::Degrees(inner) => inner.content(),
+            Expr::Cot(inner) => apply("<cot/>", &[inner]),
+            Expr::Abs(inner) => apply("<abs/>", &[inner]),
+            Expr::Floor(inner) => apply("<floor/>", &[inner]),
+            Expr::Ceil(inner) => apply("<ceiling/>", &[inner]),
+            Expr::Round(inner) => apply("<csymbol>round</csymbol>", &[inner]),
+            Expr::Trunc(inner) => apply("<csymbol>trunc</csymbol>", &[inner]),
+            Expr::Frac(inner) => apply("<csymbol>frac</csymbol>", &[inner]),
+            Expr::Sign(inner) => apply("<csymbol>sign</csymbol>", &[inner]),
+            Expr::Gamma(inner) => apply("<csymbol>gamma</csymbol>", &[inner]),
+            Expr::LnGamma(inner) => apply("<csymbol>lgamma</csymbol>", &[inner]),
+            Expr::Factorial(inner) => apply("<factorial/>", &[inner]),
+            Expr::Comb(n, k) => apply("<csymbol>binomial</csymbol>", &[n, k]),
+            Expr::Perm(n, k) => apply("<csymbol>nPr</csymbol>", &[n, k]),
+
+            Expr::Lt(lhs, rhs) => apply("<lt/>", &[lhs, rhs]),
+            Expr::Le(lhs, rhs) => apply("<leq/>", &[lhs, rhs]),
+            Expr::Eq(lhs, rhs) => apply("<eq/>", &[lhs, rhs]),
+            Expr::Ne(lhs, rhs) => apply("<neq/>", &[lhs, rhs]),
+            Expr::Gt(lhs, rhs) => apply("<gt/>", &[lhs, rhs]),
+            Expr::Ge(lhs, rhs) => apply("<geq/>", &[lhs, rhs]),
+            Expr::And(lhs, rhs) => apply("<and/>", &[lhs, rhs]),
+            Expr::Or(lhs, rhs) => apply("<or/>", &[lhs, rhs]),
+            Expr::Not(inner) => apply("<not/>", &[inner]),
+            Expr::Cond(cond, then, otherwise) => format!(
+                "<piecewise><piece>{}{}</piece><otherwise>{}</otherwise></piecewise>",
+                then.content(),
+                cond.content(),
+                otherwise.content()
+            ),
+
+            // The derivative at a point is the derivative of the function `x -> expr` applied to the point
+            Expr::Derivative(expr, var, sub) => {
+                let bvar = format!("<bvar><ci>{}</ci></bvar>", escape(var));
+
+                match sub {
+                    None => format!("<apply><diff/>{}{}</apply>", bvar, expr.content()),
+                    Some(sub) => format!(
+                        "<apply><apply><diff/><lambda>{}{}</lambda></apply>{}</apply>",
+                        bvar,
+                        expr.content(),
+                        sub.content()
+                    ),
+                }
+            }
+
+            Expr::Call(func, args) => {
+                let args: Vec<&Expr> = args.iter().collect();
+                apply(&format!("<ci>{}</ci>", escape(func.name())), &args)
+            }
+        }
+    }
+}
+
+/// Left associative binary operator, `precedence` is the level of the operator, see `Expr::to_latex`
+fn binop(lhs: &Expr, op: &str, rhs: &Expr, precedence: u8) -> String {
+    let rhs = if rhs.precedence() == PREFIX_PRECEDENCE && !matches!(rhs, Expr::Not(_)) {
+        parens(&rhs.presentation())
+    } else {
+        operand(rhs, precedence + 1)
+    };
+
+    format!("<mrow>{}<mo>{}</mo>{}</mrow>", operand(lhs, precedence), op, rhs)
+}
+
+/// Renders the operand, in parentheses if it binds looser than `min_precedence`
+fn operand(expr: &Expr, min_precedence: u8) -> String {
+    if expr.precedence() < min_precedence {
+        parens(&expr.presentation())
+    } else {
+        expr.presentation()
+    }
+}
+
+fn parens(inner: &str) -> String {
+    fenced("(", inner, ")")
+}
+
+fn fenced(open: &str, inner: &str, close: &str) -> String {
+    format!("<mrow><mo>{}</mo>{}<mo>{}</mo></mrow>", open, inner, close)
+}
+
+/// Function application, `name` is already rendered and the arguments are in parentheses
+fn function(name: &str, args: &[&Expr]) -> String {
+    let args: Vec<String> = args.iter().map(|arg| arg.presentation()).collect();
+    format!("<mrow>{}<mo>&#x2061;</mo>{}</mrow>", name, parens(&args.join("<mo>,</mo>")))
+}
+
+fn apply(head: &str, args: &[&Expr]) -> String {
+    let args: String = args.iter().map(|arg| arg.content()).collect();
+    format!("<apply>{}{}</apply>", head, args)
+}
+
+/// Returns true if the product can be written without the operator, like `2x`
+fn is_juxtaposable(lhs: &Expr, rhs: &Expr) -> bool {
+    let is_letter = |expr: &Expr| matches!(expr, Expr::Var(_) | Expr::Const(_, _));
+
+    let is_rhs_juxtaposable = match rhs {
+        Expr::Pow(base, _) => is_letter(base),
+        rhs => is_letter(rhs),
+    };
+
+    matches!(lhs, Expr::Num(n) if n.is_sign_positive()) && is_rhs_juxtaposable
+}
+
+/// Known constants are written as their symbols
+fn identifier(name: &str) -> String {
+    match name {
+        "pi" => "π".to_string(),
+        "tau" => "τ".to_string(),
+        "phi" => "φ".to_string(),
+        "inf" => "∞".to_string(),
+        name => escape(name),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
@@ -0,0 +1,38 @@
+//! Lookup of names regardless of case, for the case insensitive parsing
+
+use std::collections::{BTreeSet, HashMap};
+
+/// Names of a registry by their lowercase form, kept up to date as the names are added and removed
+///
+/// Names that differ only in case have the same lowercase form, the first of them in order is found.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct CaseIndex {
+    names: HashMap<String, BTreeSet<String>>,
+}
+
+impl CaseIndex {
+    pub(crate) fn insert(&mut self, name: &str) {
+        self.names.entry(lowercase(name)).or_default().insert(name.to_string());
+    }
+
+    pub(crate) fn remove(&mut self, name: &str) {
+        let key = lowercase(name);
+
+        if let Some(names) = self.names.get_mut(&key) {
+            names.remove(name);
+
+            if names.is_empty() {
+                self.names.remove(&key);
+            }
+        }
+    }
+
+    /// Returns the name with the given lowercase form
+    pub(crate) fn get(&self, lowercase: &str) -> Option<&str> {
+        self.names.get(lowercase)?.first().map(String::as_str)
+    }
+}
+
+pub(crate) fn lowercase(name: &str) -> String {
+    name.chars().flat_map(char::to_lowercase).collect()
+}
@@ -0,0 +1,551 @@
+//! Concrete syntax tree of the text syntax
+//!
+//! Unlike `Expr`, the tree keeps every token as written, with the whitespace and comments before it,
+//! so the input can be printed back byte for byte. It is meant for formatters and editors,
+//! the tree is lowered to `Expr` by handing its tokens to the same parser as `Expr::parse_with`.
+
+use std::fmt::Display;
+
+use super::options::ParseOptions;
+use super::parse_error::ParseError;
+use super::parser::{infix_binding_power, is_comparison, parse, postfix_binding_power, PREFIX_BINDING_POWER};
+use super::span::Span;
+use super::token::Token;
+use crate::expr::Expr;
+
+/// Token of the syntax tree with the text it was parsed from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SyntaxToken<'a> {
+    pub token: Token<'a>,
+    pub span: Span,
+    /// Text of the token as written, `π` is `Token::Ident("pi")` with the text `π`
+    ///
+    /// The `^` of a superscript exponent like `x²` has an empty text.
+    pub text: &'a str,
+    /// Whitespace, new lines and comments between the previous token and this one
+    pub leading_trivia: &'a str,
+}
+
+/// Node of the syntax tree, the tokens of every node are in the order they are written
+///
+/// Operators keep the structure of the input, so `-x` is `Prefix`, `ln(x)` is `Call` and
+/// `a < b < c` is a comparison nested in another one, the meaning is given only by lowering to `Expr`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxNode<'a> {
+    Number(SyntaxToken<'a>),
+    /// Variable or constant
+    Name(SyntaxToken<'a>),
+    Parens {
+        open: SyntaxToken<'a>,
+        inner: Box<SyntaxNode<'a>>,
+        close: SyntaxToken<'a>,
+    },
+    /// Absolute value `|x|`, the bars of `||` are split into two tokens
+    Abs {
+        open: SyntaxToken<'a>,
+        inner: Box<SyntaxNode<'a>>,
+        close: SyntaxToken<'a>,
+    },
+    /// Function call, there is one comma less than the arguments
+    Call {
+        name: SyntaxToken<'a>,
+        open: SyntaxToken<'a>,
+        args: Vec<SyntaxNode<'a>>,
+        commas: Vec<SyntaxToken<'a>>,
+        close: SyntaxToken<'a>,
+    },
+    /// `+`, `-`, `!` or `√` before the operand
+    Prefix {
+        op: SyntaxToken<'a>,
+        operand: Box<SyntaxNode<'a>>,
+    },
+    /// Factorial `!` after the operand
+    Postfix {
+        operand: Box<SyntaxNode<'a>>,
+        op: SyntaxToken<'a>,
+    },
+    /// The operator is `None` for the implicit multiplication
+    Binary {
+        lhs: Box<SyntaxNode<'a>>,
+        op: Option<SyntaxToken<'a>>,
+        rhs: Box<SyntaxNode<'a>>,
+    },
+    Conditional {
+        cond: Box<SyntaxNode<'a>>,
+        question: SyntaxToken<'a>,
+        then: Box<SyntaxNode<'a>>,
+        colon: SyntaxToken<'a>,
+        otherwise: Box<SyntaxNode<'a>>,
+    },
+}
+
+/// Lossless syntax tree of an expression in the text syntax
+///
+/// ```
+/// use pemel::prelude::*;
+///
+/// let input = "2 * ( x+1 ) # comment";
+/// let tree = SyntaxTree::parse(input, &ParseOptions::default()).unwrap();
+///
+/// assert_eq!(tree.to_string(), input);
+/// assert_eq!(tree.format(), "2 * (x + 1) # comment");
+/// assert_eq!(tree.to_expr(&ParseOptions::default()), Expr::parse(input, false));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxTree<'a> {
+    pub root: SyntaxNode<'a>,
+    /// End of input, its trivia is the whitespace and comments after the expression
+    pub eof: SyntaxToken<'a>,
+}
+
+impl<'a> SyntaxTree<'a> {
+    /// Parses the syntax tree of the expression
+    ///
+    /// The options decide what is a function call in implicit multiplication mode and which limits apply,
+    /// names are not resolved, so `f(x)` is a `Call` even if `f` is not a function.
+    /// Those errors are reported by `to_expr`.
+    pub fn parse(input: &'a str, options: &ParseOptions) -> Result<SyntaxTree<'a>, ParseError> {
+        options.check_length(input)?;
+
+        let mut tokens = vec![];
+        let mut trivia_start = 0;
+
+        for result in options.lexer(input) {
+            let (token, span) = result?;
+
+            // New lines separate only the statements of a `Program`, in an expression they are trivia
+            if token == Token::Newline {
+                continue;
+            }
+
+            tokens.push(SyntaxToken {
+                token,
+                span,
+                text: &input[span.start..span.end],
+                leading_trivia: &input[trivia_start..span.start],
+            });
+            trivia_start = span.end;
+        }
+
+        let mut parser = CstParser {
+            tokens,
+            pos: 0,
+            options,
+            bar_depth: 0,
+            closing_bar: None,
+            depth: 0,
+        };
+
+        let root = parser.parse_expr()?;
+        let eof = parser.next();
+
+        match eof.token {
+            Token::EOF => Ok(SyntaxTree { root, eof }),
+            token => Err(ParseError::unexpected(token, eof.span)),
+        }
+    }
+
+    /// Returns all tokens in the order they are written, the last one is `Token::EOF`
+    pub fn tokens(&self) -> Vec<SyntaxToken<'a>> {
+        let mut tokens = vec![];
+        self.root.collect_tokens(&mut tokens);
+        tokens.push(self.eof);
+        tokens
+    }
+
+    /// Lowers the tree to the expression, it is the same as `Expr::parse_with` of the input
+    pub fn to_expr(&self, options: &ParseOptions) -> Result<Expr, ParseError> {
+        parse(self.tokens().into_iter().map(|token| Ok((token.token, token.span))), options)
+    }
+}
+
+/// Prints the input the tree was parsed from, byte for byte
+impl Display for SyntaxTree<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for token in self.tokens() {
+            write!(f, "{}{}", token.leading_trivia, token.text)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> SyntaxNode<'a> {
+    /// Returns the span from the first to the last token of the node, trivia is not included
+    pub fn span(&self) -> Span {
+        match self {
+            SyntaxNode::Number(token) | SyntaxNode::Name(token) => token.span,
+            SyntaxNode::Parens { open, close, .. } | SyntaxNode::Abs { open, close, .. } => open.span.to(close.span),
+            SyntaxNode::Call { name, close, .. } => name.span.to(close.span),
+            SyntaxNode::Prefix { op, operand } => op.span.to(operand.span()),
+            SyntaxNode::Postfix { operand, op } => operand.span().to(op.span),
+            SyntaxNode::Binary { lhs, rhs, .. } => lhs.span().to(rhs.span()),
+            SyntaxNode::Conditional { cond, otherwise, .. } => cond.span().to(otherwise.span()),
+        }
+    }
+
+    /// Returns the innermost node containing the span, `None` if the node doesn't contain it
+    pub fn find(&self, span: Span) -> Option<&SyntaxNode<'a>> {
+        let own = self.span();
+
+        if span.start < own.start || span.end > own.end {
+            return None;
+        }
+
+        let inner = match self {
+            SyntaxNode::Number(_) | SyntaxNode::Name(_) => vec![],
+            SyntaxNode::Parens { inner, .. } | SyntaxNode::Abs { inner, .. } => vec![inner.as_ref()],
+            SyntaxNode::Call { args, .. } => args.iter().collect(),
+            SyntaxNode::Prefix { operand, .. } | SyntaxNode::Postfix { operand, .. } => vec![operand.as_ref()],
+            SyntaxNode::Binary { lhs, rhs, .. } => vec![lhs.as_ref(), rhs.as_ref()],
+            SyntaxNode::Conditional { cond, then, otherwise, .. } => vec![cond.as_ref(), then, otherwise],
+        };
+
+        inner.into_iter().find_map(|node| node.find(span)).or(Some(self))
+    }
+
+    /// Lowers the node to the expression, as if its tokens were the whole input
+    pub fn to_expr(&self, options: &ParseOptions) -> Result<Expr, ParseError> {
+        parse(self.tokens().into_iter().map(|token| Ok((token.token, token.span))), options)
+    }
+
+    /// Returns the tokens of the node in the order they are written
+    pub fn tokens(&self) -> Vec<SyntaxToken<'a>> {
+        let mut tokens = vec![];
+        self.collect_tokens(&mut tokens);
+        tokens
+    }
+
+    fn collect_tokens(&self, tokens: &mut Vec<SyntaxToken<'a>>) {
+        match self {
+            SyntaxNode::Number(token) | SyntaxNode::Name(token) => tokens.push(*token),
+            SyntaxNode::Parens { open, inner, close } | SyntaxNode::Abs { open, inner, close } => {
+                tokens.push(*open);
+                inner.collect_tokens(tokens);
+                tokens.push(*close);
+            }
+            SyntaxNode::Call { name, open, args, commas, close } => {
+                tokens.extend([*name, *open]);
+
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        tokens.extend(commas.get(i - 1));
+                    }
+                    arg.collect_tokens(tokens);
+                }
+
+                tokens.push(*close);
+            }
+            SyntaxNode::Prefix { op, operand } => {
+                tokens.push(*op);
+                operand.collect_tokens(tokens);
+            }
+            SyntaxNode::Postfix { operand, op } => {
+                operand.collect_tokens(tokens);
+                tokens.push(*op);
+            }
+            SyntaxNode::Binary { lhs, op, rhs } => {
+                lhs.collect_tokens(tokens);
+                tokens.extend(op);
+                rhs.collect_tokens(tokens);
+            }
+            SyntaxNode::Conditional { cond, question, then, colon, otherwise } => {
+                cond.collect_tokens(tokens);
+                tokens.push(*question);
+                then.collect_tokens(tokens);
+                tokens.push(*colon);
+                otherwise.collect_tokens(tokens);
+            }
+        }
+    }
+
+    /// Returns left and right binding power of the node's operator, see `infix_binding_power`
+    ///
+    /// Operands and groups bind the tightest, prefix operators have no left side and postfix no right side.
+    pub(crate) fn binding_power(&self) -> (u8, u8) {
+        match self {
+            SyntaxNode::Binary { op: Some(op), .. } => infix_binding_power(&op.token).unwrap_or((u8::MAX, u8::MAX)),
+            // Implicit multiplication binds the same as `*`
+            SyntaxNode::Binary { op: None, .. } => infix_binding_power(&Token::Star).unwrap_or_default(),
+            SyntaxNode::Conditional { question, .. } => infix_binding_power(&question.token).unwrap_or_default(),
+            SyntaxNode::Prefix { .. } => (u8::MAX, PREFIX_BINDING_POWER),
+            SyntaxNode::Postfix { op, .. } => (postfix_binding_power(&op.token).unwrap_or(u8::MAX), u8::MAX),
+            _ => (u8::MAX, u8::MAX),
+        }
+    }
+
+    /// Returns true if the node is a comparison, comparisons next to each other are chained
+    pub(crate) fn is_comparison(&self) -> bool {
+        matches!(self, SyntaxNode::Binary { op: Some(op), .. } if is_comparison(&op.token))
+    }
+}
+
+/// Parser of the syntax tree, it follows the same grammar as the `Expr` parser
+///
+/// The tokens are collected up front, the tree keeps them anyway. The binding powers are shared with the parser,
+/// the fuzz tests check that the tree printed with all operations in parentheses parses to the same `Expr`.
+struct CstParser<'a, 'o> {
+    // Tokens without new lines, the last one is `Token::EOF`
+    tokens: Vec<SyntaxToken<'a>>,
+    pos: usize,
+    options: &'o ParseOptions,
+    // Number of absolute values whose closing `|` wasn't reached yet
+    bar_depth: usize,
+    // Second half of `||` that closed two absolute values at once, it is the next token
+    closing_bar: Option<SyntaxToken<'a>>,
+    // Nesting of the expression being parsed, see `ParseOptions::max_depth`
+    depth: usize,
+}
+
+type CstResult<'a> = Result<SyntaxNode<'a>, ParseError>;
+
+const EOF: SyntaxToken<'static> = SyntaxToken {
+    token: Token::EOF,
+    span: Span { start: 0, end: 0 },
+    text: "",
+    leading_trivia: "",
+};
+
+impl<'a> CstParser<'a, '_> {
+    /// Returns the token `n` positions ahead, the lexer always ends with `Token::EOF`
+    fn peek_nth(&self, n: usize) -> SyntaxToken<'a> {
+        let index = (self.pos + n).min(self.tokens.len().saturating_sub(1));
+        self.tokens.get(index).copied().unwrap_or(EOF)
+    }
+
+    fn peek(&self) -> SyntaxToken<'a> {
+        self.peek_nth(0)
+    }
+
+    fn next(&mut self) -> SyntaxToken<'a> {
+        let token = self.peek();
+        self.pos = (self.pos + 1).min(self.tokens.len());
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<SyntaxToken<'a>, ParseError> {
+        let token = self.peek();
+
+        if token.token != expected {
+            return Err(ParseError::unexpected(token.token, token.span));
+        }
+
+        Ok(self.next())
+    }
+
+    fn parse_expr(&mut self) -> CstResult<'a> {
+        self.parse_expr_bp(0)
+    }
+
+    fn parse_expr_bp(&mut self, min_bp: u8) -> CstResult<'a> {
+        let depth = self.depth;
+        self.enter()?;
+
+        let lhs = self.parse_prefix()?;
+        let result = self.parse_infix(lhs, min_bp);
+
+        self.depth = depth;
+        result
+    }
+
+    fn enter(&mut self) -> Result<(), ParseError> {
+        self.depth += 1;
+
+        match self.options.max_depth {
+            Some(max) if self.depth > max => Err(ParseError::TooDeep(max, self.peek().span)),
+            _ => Ok(()),
+        }
+    }
+
+    fn parse_infix(&mut self, mut lhs: SyntaxNode<'a>, min_bp: u8) -> CstResult<'a> {
+        while let Some((token, is_implicit)) = self.peek_infix() {
+            if let Some(l_bp) = postfix_binding_power(&token) {
+                if l_bp < min_bp {
+                    break;
+                }
+
+                self.enter()?;
+                let op = self.next();
+                lhs = SyntaxNode::Postfix { operand: Box::new(lhs), op };
+                continue;
+            }
+
+            let Some((l_bp, r_bp)) = infix_binding_power(&token) else {
+                break;
+            };
+
+            if l_bp < min_bp {
+                break;
+            }
+
+            self.enter()?;
+            let op = (!is_implicit).then(|| self.next());
+
+            if let Some(question @ SyntaxToken { token: Token::Question, .. }) = op {
+                let then = self.parse_expr()?;
+                let colon = self.expect(Token::Colon)?;
+                let otherwise = self.parse_expr_bp(r_bp)?;
+
+                return Ok(SyntaxNode::Conditional {
+                    cond: Box::new(lhs),
+                    question,
+                    then: Box::new(then),
+                    colon,
+                    otherwise: Box::new(otherwise),
+                });
+            }
+
+            let rhs = self.parse_expr_bp(r_bp)?;
+            lhs = SyntaxNode::Binary { lhs: Box::new(lhs), op, rhs: Box::new(rhs) };
+        }
+
+        Ok(lhs)
+    }
+
+    /// Returns the next infix operator, the last value tells that it is the implicit multiplication
+    fn peek_infix(&mut self) -> Option<(Token<'a>, bool)> {
+        if self.closing_bar.is_some() || self.closes_two_bars() {
+            return None;
+        }
+
+        match self.peek().token {
+            Token::Ident(_) | Token::LParen if self.options.implicit_multiplication => Some((Token::Star, true)),
+            token => Some((token, false)),
+        }
+    }
+
+    fn parse_prefix(&mut self) -> CstResult<'a> {
+        if !matches!(self.peek().token, Token::Plus | Token::Minus | Token::Bang | Token::Sqrt) {
+            return self.parse_atom();
+        }
+
+        let op = self.next();
+        let operand = self.parse_expr_bp(PREFIX_BINDING_POWER)?;
+        Ok(SyntaxNode::Prefix { op, operand: Box::new(operand) })
+    }
+
+    fn parse_atom(&mut self) -> CstResult<'a> {
+        let token = self.peek();
+
+        match token.token {
+            Token::Number(_) => Ok(SyntaxNode::Number(self.next())),
+            Token::Ident(_) => self.parse_ident(),
+            Token::LParen => {
+                let open = self.next();
+                let inner = self.parse_expr()?;
+                let close = self.expect(Token::RParen)?;
+                Ok(SyntaxNode::Parens { open, inner: Box::new(inner), close })
+            }
+            Token::Bar | Token::OrOr => self.parse_abs(),
+            token => Err(ParseError::unexpected(token, self.peek().span)),
+        }
+    }
+
+    fn parse_ident(&mut self) -> CstResult<'a> {
+        let name = self.next();
+        let Token::Ident(ident) = name.token else {
+            return Err(ParseError::unexpected(name.token, name.span));
+        };
+
+        // In implicit multiplication mode `x(...)` is a product, unless `x` is a function
+        let is_call = !self.options.implicit_multiplication
+            || self.options.is_function(&self.options.canonical_name(ident));
+
+        if !is_call || self.peek().token != Token::LParen {
+            return Ok(SyntaxNode::Name(name));
+        }
+
+        let open = self.next();
+        let mut args = vec![];
+        let mut commas = vec![];
+
+        if self.peek().token != Token::RParen {
+            args.push(self.parse_expr()?);
+
+            while self.peek().token == Token::Comma {
+                commas.push(self.next());
+                args.push(self.parse_expr()?);
+            }
+        }
+
+        let close = self.expect(Token::RParen)?;
+        Ok(SyntaxNode::Call { name, open, args, commas, close })
+    }
+
+    /// Parses `|x|`, an operand starting with `||` is the start of two nested absolute values
+    fn parse_abs(&mut self) -> CstResult<'a> {
+        let token = self.next();
+
+        if token.token == Token::Bar {
+            return self.parse_abs_inner(token);
+        }
+
+        let (outer, inner) = split_bars(token);
+
+        self.bar_depth += 1;
+        let inner = self.parse_abs_inner(inner)?;
+        let inner = self.parse_infix(inner, 0)?;
+        let close = self.expect_bar()?;
+        self.bar_depth -= 1;
+
+        Ok(SyntaxNode::Abs { open: outer, inner: Box::new(inner), close })
+    }
+
+    fn parse_abs_inner(&mut self, open: SyntaxToken<'a>) -> CstResult<'a> {
+        self.bar_depth += 1;
+        let inner = self.parse_expr()?;
+        let close = self.expect_bar()?;
+        self.bar_depth -= 1;
+
+        Ok(SyntaxNode::Abs { open, inner: Box::new(inner), close })
+    }
+
+    /// Consumes the closing `|`, `||` closing two absolute values is split like in the `Expr` parser
+    fn expect_bar(&mut self) -> Result<SyntaxToken<'a>, ParseError> {
+        if let Some(bar) = self.closing_bar.take() {
+            return Ok(bar);
+        }
+
+        if self.closes_two_bars() {
+            let (first, second) = split_bars(self.next());
+            self.closing_bar = Some(second);
+            return Ok(first);
+        }
+
+        self.expect(Token::Bar)
+    }
+
+    /// Returns true if the next `||` closes two absolute values instead of being the logical or
+    fn closes_two_bars(&self) -> bool {
+        if self.bar_depth < 2 || self.peek().token != Token::OrOr {
+            return false;
+        }
+
+        !matches!(
+            self.peek_nth(1).token,
+            Token::Number(_) | Token::Ident(_) | Token::LParen | Token::Plus | Token::Minus | Token::Bang
+        )
+    }
+}
+
+/// Splits `||` into two `|` tokens, the trivia stays before the first one
+fn split_bars(token: SyntaxToken<'_>) -> (SyntaxToken<'_>, SyntaxToken<'_>) {
+    let middle = token.span.start + 1;
+    let (first, second) = token.text.split_at(1.min(token.text.len()));
+
+    let first = SyntaxToken {
+        token: Token::Bar,
+        span: Span::new(token.span.start, middle),
+        text: first,
+        leading_trivia: token.leading_trivia,
+    };
+
+    let second = SyntaxToken {
+        token: Token::Bar,
+        span: Span::new(middle, token.span.end),
+        text: second,
+        leading_trivia: "",
+    };
+
+    (first, second)
+}
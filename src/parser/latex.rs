@@ -0,0 +1,867 @@
+//! Translation of LaTeX formulas to the tokens of the text syntax
+//!
+//! Every LaTeX construct is turned into tokens that the text parser understands,
+//! so `\frac{a}{b}` becomes `((a) / (b))` and the resulting `Expr` is the same as for the text input.
+//! The tokens keep the spans of the LaTeX source, added tokens like the `/` of a fraction get the span of the command.
+
+use super::lexer::CharIter;
+use super::options::ParseOptions;
+use super::parse_error::ParseError;
+use super::span::Span;
+use super::token::Token;
+
+type Tokens<'a> = Vec<(Token<'a>, Span)>;
+
+/// Deepest nesting of commands, scripts and groups the translator recurses into, even without `options.max_depth`
+const MAX_LATEX_NESTING: usize = 128;
+
+/// Where the translated sequence ends
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum End {
+    Eof,
+    /// Closing `}`, `)` or `]`
+    Char(char),
+    /// Command like `\right` or `\rfloor`
+    Command(&'static str),
+    /// `\\` or `\end` of a row in `\begin{cases}`, which is not consumed
+    Row,
+}
+
+/// Splits the LaTeX formula into the tokens of the text syntax
+///
+/// Single letters are separate identifiers, like in LaTeX, so `xy` is `x` followed by `y`.
+/// The last token is always `Token::EOF`, like the last token of the `Lexer`.
+///
+/// Groups, commands and scripts nested deeper than `options.max_depth` are `ParseError::TooDeep`,
+/// more translated tokens than `options.max_tokens` is `ParseError::TooManyTokens`.
+/// Commands, scripts and groups nested more than 128 levels deep are `ParseError::TooDeep` with any options.
+pub fn tokenize_latex<'i>(input: &'i str, options: &ParseOptions) -> Result<Vec<(Token<'i>, Span)>, ParseError> {
+    let mut lexer = LatexLexer {
+        input,
+        chars: input.char_indices().peekable(),
+        depth: 0,
+        max_depth: options.max_depth,
+        nesting: 0,
+    };
+
+    let mut tokens = lexer.sequence(End::Eof)?;
+
+    if let Some(max) = options.max_tokens {
+        if let Some((_, span)) = tokens.get(max) {
+            return Err(ParseError::TooManyTokens(max, Span::new(span.start, input.len())));
+        }
+    }
+
+    tokens.push((Token::EOF, Span::new(input.len(), input.len())));
+
+    Ok(tokens)
+}
+
+struct LatexLexer<'a> {
+    input: &'a str,
+    chars: CharIter<'a>,
+    // Number of the levels being translated, every item, group and parenthesis is one
+    depth: usize,
+    max_depth: Option<usize>,
+    // Number of the items being translated, it limits the recursion through commands and their arguments
+    nesting: usize,
+}
+
+impl<'a> LatexLexer<'a> {
+    fn pos(&mut self) -> usize {
+        self.chars.peek().map_or(self.input.len(), |&(i, _)| i)
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|&(_, c)| c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|&(_, c)| c.is_whitespace()).is_some() {}
+    }
+
+    /// Returns the name of the command at the current position without consuming it
+    fn peek_command(&self) -> Option<&'a str> {
+        let mut chars = self.chars.clone();
+        let (start, _) = chars.next_if(|&(_, c)| c == '\\')?;
+
+        let name = &self.input[start + 1..];
+        let len = name.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(name.len());
+        Some(&name[..len])
+    }
+
+    /// Consumes the command and returns its name, it is either a sequence of letters or a single other character
+    fn command(&mut self) -> (&'a str, Span) {
+        let start = self.pos();
+        self.chars.next();
+
+        while self.chars.next_if(|&(_, c)| c.is_ascii_alphabetic()).is_some() {}
+
+        if self.pos() == start + 1 {
+            self.chars.next();
+        }
+
+        let end = self.pos();
+        (&self.input[start + 1..end], Span::new(start, end))
+    }
+
+    /// Returns true at the `\\` or `\end` after a row of `\begin{cases}`
+    fn is_row_end(&mut self) -> bool {
+        let start = self.pos();
+        self.input[start..].starts_with(r"\\") || self.peek_command() == Some("end")
+    }
+
+    /// Translates everything up to `end`, which is consumed
+    fn sequence(&mut self, end: End) -> Result<Tokens<'a>, ParseError> {
+        self.enter()?;
+        let tokens = self.sequence_items(end);
+        self.depth -= 1;
+        tokens
+    }
+
+    /// Counts one more level of `options.max_depth`, the caller decrements the depth after the level
+    fn enter(&mut self) -> Result<(), ParseError> {
+        self.depth += 1;
+
+        match self.max_depth.filter(|&max| self.depth > max) {
+            Some(max) => {
+                let start = self.pos();
+                Err(ParseError::TooDeep(max, Span::new(start, start)))
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn sequence_items(&mut self, end: End) -> Result<Tokens<'a>, ParseError> {
+        let mut tokens = vec![];
+
+        loop {
+            self.skip_whitespace();
+            let start = self.pos();
+
+            match (self.peek(), end) {
+                (None, End::Eof) => return Ok(tokens),
+                (None, _) => return Err(ParseError::UnexpectedEof(Span::new(start, start))),
+                (Some(c), End::Char(end)) if c == end => {
+                    self.chars.next();
+                    return Ok(tokens);
+                }
+                (Some('\\'), End::Command(name)) if self.peek_command() == Some(name) => {
+                    self.command();
+                    return Ok(tokens);
+                }
+                (Some('\\'), End::Row) if self.is_row_end() => return Ok(tokens),
+                _ => (),
+            }
+
+            // The derivative can take the rest of the sequence, including the end
+            if self.item(&mut tokens, end)? {
+                return Ok(tokens);
+            }
+        }
+    }
+
+    /// Translates one item of the sequence, returns true if the rest of the sequence was consumed by it
+    fn item(&mut self, tokens: &mut Tokens<'a>, end: End) -> Result<bool, ParseError> {
+        self.enter()?;
+        self.nesting += 1;
+
+        if self.nesting > MAX_LATEX_NESTING {
+            let start = self.pos();
+            return Err(ParseError::TooDeep(MAX_LATEX_NESTING, Span::new(start, start)));
+        }
+
+        let consumed = self.item_inner(tokens, end);
+        self.nesting -= 1;
+        self.depth -= 1;
+        consumed
+    }
+
+    fn item_inner(&mut self, tokens: &mut Tokens<'a>, end: End) -> Result<bool, ParseError> {
+        let start = self.pos();
+        let Some(c) = self.peek() else {
+            return Err(ParseError::UnexpectedEof(Span::new(start, start)));
+        };
+
+        if c == '\\' {
+            return self.translate_command(tokens, end);
+        }
+
+        if c.is_ascii_digit() || c == '.' {
+            tokens.push(self.number()?);
+            return Ok(false);
+        }
+
+        self.chars.next();
+        let span = Span::new(start, self.pos());
+
+        let token = match c {
+            'π' => Token::Ident("pi"),
+            c if c.is_alphabetic() => Token::Ident(&self.input[span.start..span.end]),
+            '{' | '(' | '[' => {
+                let closing = match c {
+                    '{' => '}',
+                    '(' => ')',
+                    _ => ']',
+                };
+                let inner = self.sequence(End::Char(closing))?;
+                let span = span.to(Span::new(start, self.pos()));
+
+                if c == '{' && inner.is_empty() && self.peek() == Some('_') {
+                    self.permutation(tokens, span, end)?;
+                } else {
+                    wrap(tokens, inner, span);
+                }
+                return Ok(false);
+            }
+            '^' => {
+                tokens.push((Token::Caret, span));
+                let exponent = self.group(end)?;
+                wrap(tokens, exponent, span);
+                return Ok(false);
+            }
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Star,
+            '/' => Token::Slash,
+            ',' => Token::Comma,
+            '=' => Token::EqualEqual,
+            '<' => Token::Less,
+            '>' => Token::Greater,
+            '!' => Token::Bang,
+            '|' => Token::Bar,
+            _ => return Err(ParseError::UnexpectedChar(c, span)),
+        };
+
+        tokens.push((token, span));
+        Ok(false)
+    }
+
+    fn number(&mut self) -> Result<(Token<'a>, Span), ParseError> {
+        let start = self.pos();
+        let mut literal = String::new();
+
+        while let Some((_, c)) = self.chars.next_if(|&(_, c)| c.is_ascii_digit() || c == '.') {
+            literal.push(c);
+        }
+
+        let span = Span::new(start, self.pos());
+        // Rust accepts "1." but the text syntax doesn't
+        let is_valid = !literal.ends_with('.');
+
+        match literal.parse::<f32>() {
+            Ok(n) if is_valid && n.is_finite() => Ok((Token::Number(n), span)),
+            _ => Err(ParseError::InvalidNumber(literal, span)),
+        }
+    }
+
+    /// Translates the argument of a command, `{...}` or a single character or command, like in `\frac12`
+    fn group(&mut self, end: End) -> Result<Tokens<'a>, ParseError> {
+        self.enter()?;
+        self.skip_whitespace();
+        let start = self.pos();
+
+        let tokens = match self.peek() {
+            Some('{') => {
+                self.chars.next();
+                self.sequence(End::Char('}'))
+            }
+            Some(c) if c.is_ascii_digit() => {
+                self.chars.next();
+                let span = Span::new(start, self.pos());
+                Ok(vec![(Token::Number(c.to_digit(10).unwrap_or_default() as f32), span)])
+            }
+            _ => {
+                let mut tokens = vec![];
+                self.item(&mut tokens, end).map(|_| tokens)
+            }
+        };
+
+        self.depth -= 1;
+        tokens
+    }
+
+    /// Translates the argument of a function like `\sin`, it can also be in parentheses
+    fn argument(&mut self, end: End) -> Result<Tokens<'a>, ParseError> {
+        self.skip_whitespace();
+
+        if self.peek() == Some('(') {
+            self.chars.next();
+            return self.sequence(End::Char(')'));
+        }
+
+        self.group(end)
+    }
+
+    fn translate_command(&mut self, tokens: &mut Tokens<'a>, end: End) -> Result<bool, ParseError> {
+        let (name, span) = self.command();
+
+        let token = match name {
+            "frac" | "dfrac" | "tfrac" => {
+                let numerator = self.group(end)?;
+                let denominator = self.group(end)?;
+
+                if let Some(var) = derivative_var(&numerator, &denominator) {
+                    return self.derivative(tokens, var, span, end);
+                }
+
+                tokens.push((Token::LParen, span));
+                wrap(tokens, numerator, span);
+                tokens.push((Token::Slash, span));
+                wrap(tokens, denominator, span);
+                tokens.push((Token::RParen, span));
+                return Ok(false);
+            }
+
+            "sqrt" => {
+                self.skip_whitespace();
+                let index = match self.peek() {
+                    Some('[') => {
+                        self.chars.next();
+                        Some(self.sequence(End::Char(']'))?)
+                    }
+                    _ => None,
+                };
+
+                let radicand = self.group(end)?;
+
+                match index {
+                    None => {
+                        tokens.push((Token::Ident("sqrt"), span));
+                        wrap(tokens, radicand, span);
+                    }
+                    Some(index) => {
+                        // `\sqrt[n]{x}` is `x^(1/n)`
+                        tokens.push((Token::LParen, span));
+                        wrap(tokens, radicand, span);
+                        tokens.push((Token::Caret, span));
+                        tokens.push((Token::LParen, span));
+                        tokens.push((Token::Number(1.0), span));
+                        tokens.push((Token::Slash, span));
+                        wrap(tokens, index, span);
+                        tokens.push((Token::RParen, span));
+                        tokens.push((Token::RParen, span));
+                    }
+                }
+                return Ok(false);
+            }
+
+            "sin" | "cos" | "tan" | "cot" | "exp" => {
+                self.function(tokens, name, span, end)?;
+                return Ok(false);
+            }
+
+            "ln" => {
+                self.skip_whitespace();
+                // `\ln\Gamma(x)` is `lgamma(x)`
+                let (name, span) = match self.peek_command() {
+                    Some("Gamma") => ("lgamma", span.to(self.command().1)),
+                    _ => (name, span),
+                };
+                self.function(tokens, name, span, end)?;
+                return Ok(false);
+            }
+
+            "Gamma" => {
+                self.skip_whitespace();
+                // `\Gamma` followed by parentheses is the gamma function, otherwise the letter
+                if self.peek() == Some('(') || self.peek_command() == Some("left") {
+                    self.function(tokens, "gamma", span, end)?;
+                    return Ok(false);
+                }
+                Token::Ident("Γ")
+            }
+
+            "binom" => {
+                let n = self.group(end)?;
+                let k = self.group(end)?;
+                call(tokens, "nCr", n, k, span);
+                return Ok(false);
+            }
+
+            "lfloor" => {
+                let inner = self.sequence(End::Command("rfloor"))?;
+                floor(tokens, inner, span);
+                return Ok(false);
+            }
+
+            "lceil" => {
+                let inner = self.sequence(End::Command("rceil"))?;
+                tokens.push((Token::Ident("ceil"), span));
+                wrap(tokens, inner, span);
+                return Ok(false);
+            }
+
+            "begin" => {
+                self.cases(tokens, span)?;
+                return Ok(false);
+            }
+
+            "log" => {
+                self.skip_whitespace();
+                let base = match self.peek() {
+                    Some('_') => {
+                        self.chars.next();
+                        Some(self.group(end)?)
+                    }
+                    _ => None,
+                };
+
+                let argument = self.argument(end)?;
+
+                tokens.push((Token::Ident(name), span));
+                tokens.push((Token::LParen, span));
+                if let Some(base) = base {
+                    tokens.extend(base);
+                    tokens.push((Token::Comma, span));
+                }
+                tokens.extend(argument);
+                tokens.push((Token::RParen, span));
+                return Ok(false);
+            }
+
+            "left" => {
+                self.left_right(tokens, span, end)?;
+                return Ok(false);
+            }
+
+            "mathrm" | "operatorname" | "text" => match self.word()? {
+                "sgn" => Token::Ident("sign"),
+                word => Token::Ident(word),
+            },
+
+            // Spacing
+            "," | ";" | ":" | "!" | " " | "quad" | "qquad" => return Ok(false),
+
+            "cdot" | "times" => Token::Star,
+            "div" => Token::Slash,
+            "bmod" | "mod" => Token::Percent,
+            "pi" => Token::Ident("pi"),
+            "tau" => Token::Ident("tau"),
+            "phi" | "varphi" => Token::Ident("phi"),
+            "infty" => Token::Ident("inf"),
+            "le" | "leq" => Token::LessEqual,
+            "ge" | "geq" => Token::GreaterEqual,
+            "ne" | "neq" => Token::NotEqual,
+            "lt" => Token::Less,
+            "gt" => Token::Greater,
+            "vert" | "lvert" | "rvert" => Token::Bar,
+            "land" | "wedge" => Token::AndAnd,
+            "lor" | "vee" => Token::OrOr,
+            "lnot" | "neg" => Token::Bang,
+
+            name => match greek_letter(name) {
+                Some(letter) => Token::Ident(letter),
+                None => return Err(ParseError::UnsupportedCommand(name.to_string(), span)),
+            },
+        };
+
+        tokens.push((token, span));
+        Ok(false)
+    }
+
+    /// Translates `\sin x`, `\sin(x)` or `\sin^2 x`, which is `sin(x)^2`
+    fn function(&mut self, tokens: &mut Tokens<'a>, name: &'a str, span: Span, end: End) -> Result<(), ParseError> {
+        self.skip_whitespace();
+        let exponent = match self.peek() {
+            Some('^') => {
+                self.chars.next();
+                Some(self.group(end)?)
+            }
+            _ => None,
+        };
+
+        let argument = self.argument(end)?;
+
+        // `\exp` has no function in the text syntax, it is `e^x`
+        let call = if name == "exp" {
+            let mut call = vec![(Token::Ident("e"), span), (Token::Caret, span)];
+            wrap(&mut call, argument, span);
+            call
+        } else {
+            let mut call = vec![(Token::Ident(name), span)];
+            wrap(&mut call, argument, span);
+            call
+        };
+
+        match exponent {
+            None => tokens.extend(call),
+            Some(exponent) => {
+                wrap(tokens, call, span);
+                tokens.push((Token::Caret, span));
+                wrap(tokens, exponent, span);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Translates `\left( ... \right)` with any of the delimiters, the `\left` is already consumed
+    ///
+    /// `\left\lfloor` and `\left\lceil` are `floor` and `ceil`,
+    /// `\left. ... \right|_{x=a}` substitutes into the derivative.
+    fn left_right(&mut self, tokens: &mut Tokens<'a>, span: Span, end: End) -> Result<(), ParseError> {
+        self.skip_whitespace();
+        let opening = self.delimiter()?;
+        let inner = self.sequence(End::Command("right"))?;
+        self.skip_whitespace();
+        let closing = self.delimiter()?;
+        let span = span.to(opening.1);
+
+        match opening.0 {
+            '|' => {
+                tokens.push((Token::Bar, span));
+                tokens.extend(inner);
+                tokens.push((Token::Bar, closing.1));
+            }
+            '⌊' => floor(tokens, inner, span.to(closing.1)),
+            '⌈' => {
+                tokens.push((Token::Ident("ceil"), span));
+                wrap(tokens, inner, span.to(closing.1));
+            }
+            '.' if closing.0 == '|' && self.peek() == Some('_') => self.substitution(tokens, inner, span, end)?,
+            _ => {
+                tokens.push((Token::LParen, span));
+                tokens.extend(inner);
+                tokens.push((Token::RParen, closing.1));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the delimiter after `\left` or `\right`, `\lfloor` is `⌊` and `\lceil` is `⌈`
+    fn delimiter(&mut self) -> Result<(char, Span), ParseError> {
+        let start = self.pos();
+
+        if self.peek() == Some('\\') {
+            let (name, span) = self.command();
+            let c = match name {
+                "lfloor" => '⌊',
+                "rfloor" => '⌋',
+                "lceil" => '⌈',
+                "rceil" => '⌉',
+                "vert" | "lvert" | "rvert" => '|',
+                name => return Err(ParseError::UnsupportedCommand(name.to_string(), span)),
+            };
+            return Ok((c, span));
+        }
+
+        match self.chars.next() {
+            Some((_, c @ ('(' | ')' | '[' | ']' | '|' | '.'))) => Ok((c, Span::new(start, self.pos()))),
+            Some((_, c)) => Err(ParseError::UnexpectedChar(c, Span::new(start, self.pos()))),
+            None => Err(ParseError::UnexpectedEof(Span::new(start, start))),
+        }
+    }
+
+    /// Translates `\left. \frac{d}{dx}(...) \right|_{x=a}` to `D(x, ..., a)`, the subscript is not consumed yet
+    fn substitution(
+        &mut self,
+        tokens: &mut Tokens<'a>,
+        inner: Tokens<'a>,
+        span: Span,
+        end: End,
+    ) -> Result<(), ParseError> {
+        let start = self.pos();
+        self.chars.next();
+        let subscript_span = Span::new(start, self.pos());
+        let subscript = self.group(end)?;
+
+        let derivative_var = match inner.as_slice() {
+            [(Token::Ident("D"), _), (Token::LParen, _), (Token::Ident(var), _), (Token::Comma, _), ..] => Some(*var),
+            _ => None,
+        };
+        let is_derivative = match subscript.as_slice() {
+            [(Token::Ident(var), _), (Token::EqualEqual, _), _, ..] => {
+                derivative_var == Some(*var) && closing_paren(&inner, 1) == Some(inner.len() - 1)
+            }
+            _ => false,
+        };
+
+        if !is_derivative {
+            return Err(ParseError::UnexpectedChar('_', subscript_span));
+        }
+
+        // The value goes before the closing parenthesis of `D(x, ...)`
+        let (_, close_span) = inner[inner.len() - 1];
+        tokens.extend(inner[..inner.len() - 1].iter().cloned());
+        tokens.push((Token::Comma, span));
+        wrap(tokens, subscript[2..].to_vec(), span);
+        tokens.push((Token::RParen, close_span));
+
+        Ok(())
+    }
+
+    /// Translates `_{n}P_{k}` after the empty group of `{}_{n}P_{k}` to `nPr(n, k)`
+    fn permutation(&mut self, tokens: &mut Tokens<'a>, span: Span, end: End) -> Result<(), ParseError> {
+        self.chars.next();
+        let n = self.group(end)?;
+
+        for expected in ['P', '_'] {
+            self.skip_whitespace();
+            let start = self.pos();
+            if self.chars.next_if(|&(_, c)| c == expected).is_none() {
+                return Err(self.unexpected(start));
+            }
+        }
+
+        let k = self.group(end)?;
+        let span = span.to(Span::new(span.start, self.pos()));
+        call(tokens, "nPr", n, k, span);
+        Ok(())
+    }
+
+    /// Translates the rows of `\begin{cases}` to nested conditionals, the `\begin` is already consumed
+    ///
+    /// Every row but the last is `value & \text{if } condition \\`, the last one is `value & \text{otherwise}`.
+    fn cases(&mut self, tokens: &mut Tokens<'a>, span: Span) -> Result<(), ParseError> {
+        let environment = self.word()?;
+        if environment != "cases" {
+            return Err(ParseError::UnsupportedCommand(format!("begin{{{}}}", environment), span));
+        }
+
+        let mut rows = vec![];
+        let otherwise = loop {
+            let value = self.sequence(End::Char('&'))?;
+            let start = self.skip_to_command("text")?;
+
+            match self.word()? {
+                "otherwise" => break value,
+                "if" => {
+                    let condition = self.sequence(End::Row)?;
+                    rows.push((condition, value));
+
+                    let start = self.pos();
+                    if !self.input[start..].starts_with(r"\\") {
+                        return Err(self.unexpected(start));
+                    }
+                    self.chars.nth(1);
+                }
+                word => return Err(ParseError::UnexpectedToken(word.to_string(), Span::new(start, self.pos()))),
+            }
+        };
+
+        self.skip_to_command("end")?;
+        if self.word()? != "cases" {
+            let start = self.pos();
+            return Err(self.unexpected(start));
+        }
+
+        let span = span.to(Span::new(span.start, self.pos()));
+        let mut conditional = otherwise;
+        for (condition, value) in rows.into_iter().rev() {
+            let mut row = vec![(Token::LParen, span)];
+            wrap(&mut row, condition, span);
+            row.push((Token::Question, span));
+            wrap(&mut row, value, span);
+            row.push((Token::Colon, span));
+            wrap(&mut row, conditional, span);
+            row.push((Token::RParen, span));
+            conditional = row;
+        }
+
+        wrap(tokens, conditional, span);
+        Ok(())
+    }
+
+    /// Consumes the command with the name after optional whitespace, returns its start
+    fn skip_to_command(&mut self, name: &str) -> Result<usize, ParseError> {
+        self.skip_whitespace();
+        let start = self.pos();
+
+        if self.peek_command() != Some(name) {
+            return Err(self.unexpected(start));
+        }
+
+        self.command();
+        Ok(start)
+    }
+
+    /// Reads the letters in braces, the argument of `\mathrm`, whitespace around them is ignored like in `\text{if }`
+    fn word(&mut self) -> Result<&'a str, ParseError> {
+        self.skip_whitespace();
+        let start = self.pos();
+
+        if self.chars.next_if(|&(_, c)| c == '{').is_none() {
+            return Err(self.unexpected(start));
+        }
+
+        self.skip_whitespace();
+        let word_start = self.pos();
+        while self.chars.next_if(|&(_, c)| c.is_alphabetic() || c == '_').is_some() {}
+
+        let word = &self.input[word_start..self.pos()];
+        self.skip_whitespace();
+        let start = self.pos();
+        if self.chars.next_if(|&(_, c)| c == '}').is_none() {
+            return Err(self.unexpected(start));
+        }
+
+        Ok(word)
+    }
+
+    fn unexpected(&mut self, start: usize) -> ParseError {
+        match self.chars.next() {
+            Some((_, c)) => ParseError::UnexpectedChar(c, Span::new(start, self.pos())),
+            None => ParseError::UnexpectedEof(Span::new(start, start)),
+        }
+    }
+
+    /// Translates `\frac{d}{dx}` followed by the differentiated expression to `D(x, ...)`
+    ///
+    /// The expression is the following group in parentheses or braces,
+    /// otherwise it is the rest of the term, up to the next `+` or `-` outside of parentheses.
+    fn derivative(&mut self, tokens: &mut Tokens<'a>, var: &'a str, span: Span, end: End) -> Result<bool, ParseError> {
+        self.skip_whitespace();
+
+        let is_group = matches!(self.peek(), Some('(' | '{')) || self.peek_command() == Some("left");
+        let (operand, rest, is_rest_consumed) = if is_group {
+            let mut operand = vec![];
+            self.item(&mut operand, end)?;
+            (operand, vec![], false)
+        } else {
+            let mut operand = self.sequence(end)?;
+            let rest = operand.split_off(term_end(&operand));
+            (operand, rest, true)
+        };
+
+        tokens.push((Token::Ident("D"), span));
+        tokens.push((Token::LParen, span));
+        tokens.push((Token::Ident(var), span));
+        tokens.push((Token::Comma, span));
+        tokens.extend(operand);
+        tokens.push((Token::RParen, span));
+        tokens.extend(rest);
+
+        Ok(is_rest_consumed)
+    }
+}
+
+/// Appends the tokens in parentheses
+fn wrap<'a>(tokens: &mut Tokens<'a>, inner: Tokens<'a>, span: Span) {
+    tokens.push((Token::LParen, span));
+    tokens.extend(inner);
+    tokens.push((Token::RParen, span));
+}
+
+/// Appends the call with two arguments, like `nCr(n, k)`
+fn call<'a>(tokens: &mut Tokens<'a>, name: &'a str, first: Tokens<'a>, second: Tokens<'a>, span: Span) {
+    tokens.push((Token::Ident(name), span));
+    tokens.push((Token::LParen, span));
+    wrap(tokens, first, span);
+    tokens.push((Token::Comma, span));
+    wrap(tokens, second, span);
+    tokens.push((Token::RParen, span));
+}
+
+/// Appends `floor(...)`, or `a // b` if the floor is of one `\frac{a}{b}`, like `Expr::to_latex` writes `a // b`
+fn floor<'a>(tokens: &mut Tokens<'a>, mut inner: Tokens<'a>, span: Span) {
+    match fraction_slash(&inner) {
+        Some(slash) => {
+            inner[slash].0 = Token::SlashSlash;
+            tokens.extend(inner);
+        }
+        None => {
+            tokens.push((Token::Ident("floor"), span));
+            wrap(tokens, inner, span);
+        }
+    }
+}
+
+/// Returns the index of the slash if the tokens are exactly one translated `\frac`
+fn fraction_slash(tokens: &Tokens<'_>) -> Option<usize> {
+    let (Token::LParen, frac_span) = *tokens.first()? else {
+        return None;
+    };
+
+    if closing_paren(tokens, 0) != Some(tokens.len() - 1) {
+        return None;
+    }
+
+    let mut depth = 0usize;
+    for (i, (token, span)) in tokens.iter().enumerate() {
+        match token {
+            Token::LParen => depth += 1,
+            Token::RParen => depth -= 1,
+            Token::Slash if depth == 1 && *span == frac_span => return Some(i),
+            _ => (),
+        }
+    }
+
+    None
+}
+
+/// Returns the index of the parenthesis closing the one at `open`
+fn closing_paren(tokens: &Tokens<'_>, open: usize) -> Option<usize> {
+    let mut depth = 0usize;
+
+    for (i, (token, _)) in tokens.iter().enumerate().skip(open) {
+        match token {
+            Token::LParen => depth += 1,
+            Token::RParen if depth == 1 => return Some(i),
+            Token::RParen => depth = depth.checked_sub(1)?,
+            _ => (),
+        }
+    }
+
+    None
+}
+
+/// Returns the variable if the fraction is `\frac{d}{dx}`
+fn derivative_var<'a>(numerator: &Tokens<'a>, denominator: &Tokens<'a>) -> Option<&'a str> {
+    match (numerator.as_slice(), denominator.as_slice()) {
+        ([(Token::Ident("d"), _)], [(Token::Ident("d"), _), (Token::Ident(var), _)]) => Some(*var),
+        _ => None,
+    }
+}
+
+/// Returns the index of the first `+` or `-` outside of parentheses that is not a sign at the start
+fn term_end(tokens: &Tokens<'_>) -> usize {
+    let mut depth = 0usize;
+
+    for (i, (token, _)) in tokens.iter().enumerate() {
+        match token {
+            Token::LParen => depth += 1,
+            Token::RParen => depth = depth.saturating_sub(1),
+            Token::Plus | Token::Minus if depth == 0 && i > 0 => return i,
+            _ => (),
+        }
+    }
+
+    tokens.len()
+}
+
+fn greek_letter(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "alpha" => "α",
+        "beta" => "β",
+        "gamma" => "γ",
+        "delta" => "δ",
+        "epsilon" | "varepsilon" => "ε",
+        "zeta" => "ζ",
+        "eta" => "η",
+        "theta" | "vartheta" => "θ",
+        "iota" => "ι",
+        "kappa" => "κ",
+        "lambda" => "λ",
+        "mu" => "μ",
+        "nu" => "ν",
+        "xi" => "ξ",
+        "rho" => "ρ",
+        "sigma" => "σ",
+        "upsilon" => "υ",
+        "chi" => "χ",
+        "psi" => "ψ",
+        "omega" => "ω",
+        "Gamma" => "Γ",
+        "Delta" => "Δ",
+        "Theta" => "Θ",
+        "Lambda" => "Λ",
+        "Xi" => "Ξ",
+        "Sigma" => "Σ",
+        "Phi" => "Φ",
+        "Psi" => "Ψ",
+        "Omega" => "Ω",
+        _ => return None,
+    })
+}
@@ -1,116 +1,446 @@
+use std::borrow::Cow;
+use std::iter::{FusedIterator, Peekable};
+use std::str::CharIndices;
+
 use super::macros::char_pat;
+use super::options::NumberLiterals;
 use super::parse_error::ParseError;
+use super::span::{Location, Span};
 use super::token::Token;
 
-pub fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
-    let mut tokens = vec![];
-    let mut chars = input.chars().peekable();
+pub(super) type CharIter<'a> = Peekable<CharIndices<'a>>;
 
-    while let Some(&char) = chars.peek() {
-        let token = match char {
-            ' ' => {
-                chars.next();
-                continue;
-            }
+/// Returns the token standing in for the erroneous input, so the recovering parser can continue after it
+///
+/// Malformed number is still emitted as `Token::Number`, unexpected character has no replacement and is skipped.
+pub(super) fn placeholder(err: &ParseError) -> Option<(Token<'static>, Span)> {
+    match err {
+        ParseError::InvalidNumber(_, span) => Some((Token::Number(0.0), *span)),
+        _ => None,
+    }
+}
 
-            '+' => {
-                chars.next();
-                Token::Plus
-            }
+/// Lazy tokenizer of the text syntax, it yields each token with its byte range in the input
+///
+/// Tokens are produced one at a time as the iterator advances and identifiers borrow from the input,
+/// so tokenizing doesn't allocate. The parser consumes the lexer directly, without collecting the tokens first.
+///
+/// ```
+/// use pemel::prelude::*;
+///
+/// let tokens: Vec<_> = Lexer::new("2 * alpha").map(|result| result.unwrap().0).collect();
+/// assert_eq!(tokens, [Token::Number(2.0), Token::Star, Token::Ident("alpha"), Token::EOF]);
+/// ```
+///
+/// The last token is `Token::EOF` with an empty span at the end of the input.
+/// Errors are yielded in place of the erroneous part and the lexer continues after it.
+///
+/// Any Unicode whitespace separates tokens. `\n` is `Token::Newline`, which separates the statements of a `Program`,
+/// all other whitespace is skipped. Comments are skipped as well, `#` comments up to the end of the line
+/// and `/* */` block comments, which don't nest. `//` is the floor division, so `7 // 2 # note` is `3`,
+/// unless `with_slash_comments` makes it a line comment like `#`.
+#[derive(Debug, Clone)]
+pub struct Lexer<'a> {
+    input: &'a str,
+    chars: CharIter<'a>,
+    // The `^` of a superscript was emitted and the signs and digits of the exponent follow
+    in_superscript: bool,
+    is_done: bool,
+    // Line of the next character and the byte offset where the line starts
+    line: usize,
+    line_start: usize,
+    number_literals: NumberLiterals,
+    slash_comments: bool,
+    max_tokens: Option<usize>,
+    // Tokens yielded so far, new lines and EOF are not counted
+    count: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Lexer {
+            input,
+            chars: input.char_indices().peekable(),
+            in_superscript: false,
+            is_done: false,
+            line: 1,
+            line_start: 0,
+            number_literals: NumberLiterals::default(),
+            slash_comments: false,
+            max_tokens: None,
+            count: 0,
+        }
+    }
+
+    /// Accepts only the enabled forms of number literals, the other ones are `ParseError::InvalidNumber`
+    pub fn with_number_literals(mut self, literals: NumberLiterals) -> Self {
+        self.number_literals = literals;
+        self
+    }
+
+    /// Skips `//` up to the end of the line as a comment, there is no `Token::SlashSlash` then
+    pub fn with_slash_comments(mut self) -> Self {
+        self.slash_comments = true;
+        self
+    }
+
+    /// Yields `ParseError::TooManyTokens` in place of the token past the limit and ends there
+    ///
+    /// New lines and the final `Token::EOF` are not counted.
+    pub fn with_max_tokens(mut self, max: usize) -> Self {
+        self.max_tokens = Some(max);
+        self
+    }
+
+    /// Returns the line and column of the next character, the tokens before it were already yielded
+    pub fn location(&self) -> Location {
+        let pos = self.chars.clone().peek().map_or(self.input.len(), |&(i, _)| i);
+
+        Location {
+            line: self.line,
+            column: self.input[self.line_start..pos].chars().count() + 1,
+        }
+    }
+
+    fn pos(&mut self) -> usize {
+        self.chars.peek().map_or(self.input.len(), |&(i, _)| i)
+    }
+
+    /// Consumes the new line and moves the location to the next line
+    fn newline(&mut self) -> Token<'a> {
+        if let Some((i, _)) = self.chars.next() {
+            self.line += 1;
+            self.line_start = i + 1;
+        }
+
+        Token::Newline
+    }
+
+    /// Skips whitespace other than `\n` and comments, returns the error if a block comment is not closed
+    fn skip_trivia(&mut self) -> Result<(), ParseError> {
+        loop {
+            match (peek_nth(&self.chars, 0), peek_nth(&self.chars, 1)) {
+                (Some(c), _) if c.is_whitespace() && c != '\n' => {
+                    self.chars.next();
+                }
+
+                // The new line ending the comment is left as the separator of statements
+                (Some('#'), _) => skip_while(&mut self.chars, |c| c != '\n'),
+                (Some('/'), Some('/')) if self.slash_comments => skip_while(&mut self.chars, |c| c != '\n'),
+
+                (Some('/'), Some('*')) => {
+                    let start = self.pos();
+                    self.chars.nth(1);
+                    self.block_comment().ok_or(ParseError::UnterminatedComment(Span::new(start, start + 2)))?;
+                }
 
-            '-' => {
-                chars.next();
-                Token::Minus
+                _ => return Ok(()),
             }
+        }
+    }
 
-            '*' => {
-                chars.next();
-                Token::Star
+    /// Skips the rest of the block comment including the closing `*/`, returns `None` if the input ends first
+    fn block_comment(&mut self) -> Option<()> {
+        loop {
+            match (peek_nth(&self.chars, 0)?, peek_nth(&self.chars, 1)) {
+                ('*', Some('/')) => {
+                    self.chars.nth(1);
+                    return Some(());
+                }
+                ('\n', _) => {
+                    self.newline();
+                }
+                _ => {
+                    self.chars.next();
+                }
             }
+        }
+    }
 
-            '/' => {
-                chars.next();
-                Token::Slash
+    /// Consumes the next character and returns the token
+    fn single(&mut self, token: Token<'a>) -> Token<'a> {
+        self.chars.next();
+        token
+    }
+
+    /// Consumes the next character, and the one after it if it is `second`
+    fn pair(&mut self, second: char, double: Token<'a>, single: Token<'a>) -> Token<'a> {
+        self.chars.next();
+        if self.chars.next_if(|&(_, c)| c == second).is_some() {
+            double
+        } else {
+            single
+        }
+    }
+
+    /// Tokenizes the signs and digits of the superscript exponent, one token at a time
+    fn superscript_token(&mut self) -> Option<Result<(Token<'a>, Span), ParseError>> {
+        if let Some((start, sign)) = self.chars.next_if(|&(_, c)| matches!(c, '⁺' | '⁻')) {
+            let token = if sign == '⁻' { Token::Minus } else { Token::Plus };
+            return Some(Ok((token, Span::new(start, start + sign.len_utf8()))));
+        }
+
+        // Only the digits end the exponent, a sign after them starts another one
+        self.in_superscript = false;
+        let start = self.pos();
+        let mut value = Some(0u128);
+
+        while let Some((_, c)) = self.chars.next_if(|&(_, c)| superscript(c).is_some_and(|c| c.is_ascii_digit())) {
+            let digit = superscript(c).and_then(|c| c.to_digit(10)).unwrap_or_default();
+            value = value.and_then(|value| value.checked_mul(10)?.checked_add(digit as u128));
+        }
+
+        let end = self.pos();
+        if start == end {
+            return None;
+        }
+
+        let span = Span::new(start, end);
+        match value.map(|value| value as f32) {
+            Some(n) if n.is_finite() => Some(Ok((Token::Number(n), span))),
+            _ => {
+                let digits = self.input[start..end].chars().filter_map(superscript).collect();
+                Some(Err(ParseError::InvalidNumber(digits, span)))
             }
+        }
+    }
+}
 
-            '^' => {
-                chars.next();
-                Token::Caret
+impl<'a> Lexer<'a> {
+    /// Returns the next token or error, `None` after the `Token::EOF`
+    fn token(&mut self) -> Option<Result<(Token<'a>, Span), ParseError>> {
+        if self.in_superscript {
+            if let Some(result) = self.superscript_token() {
+                return Some(result);
             }
+        }
+
+        if let Err(err) = self.skip_trivia() {
+            return Some(Err(err));
+        }
 
-            '(' => {
-                chars.next();
-                Token::LParen
+        let Some(&(start, char)) = self.chars.peek() else {
+            if self.is_done {
+                return None;
             }
 
-            ')' => {
-                chars.next();
-                Token::RParen
+            self.is_done = true;
+            let end = self.input.len();
+            return Some(Ok((Token::EOF, Span::new(end, end))));
+        };
+
+        let token = match char {
+            '+' => self.single(Token::Plus),
+
+            // U+2212 is the minus sign used in typeset math
+            '-' | '−' => self.single(Token::Minus),
+            '*' | '×' | '·' | '⋅' => self.single(Token::Star),
+            '÷' => self.single(Token::Slash),
+            '√' => self.single(Token::Sqrt),
+            'π' => self.single(Token::Ident("pi")),
+
+            // `x²` and `x⁻¹` are tokenized as `x^2` and `x^-1`
+            c if superscript(c).is_some() => {
+                self.in_superscript = true;
+                return Some(Ok((Token::Caret, Span::new(start, start))));
             }
 
-            ',' => {
-                chars.next();
-                Token::Comma
+            '/' => self.pair('/', Token::SlashSlash, Token::Slash),
+            '%' => self.single(Token::Percent),
+            '^' => self.single(Token::Caret),
+            '(' => self.single(Token::LParen),
+            ')' => self.single(Token::RParen),
+            ',' => self.single(Token::Comma),
+            '=' => self.pair('=', Token::EqualEqual, Token::Equals),
+            '<' => self.pair('=', Token::LessEqual, Token::Less),
+            '>' => self.pair('=', Token::GreaterEqual, Token::Greater),
+            '!' => self.pair('=', Token::NotEqual, Token::Bang),
+
+            // Single `&` is not an operator, it is reported as unexpected character
+            '&' if peek_nth(&self.chars, 1) == Some('&') => {
+                self.chars.next();
+                self.single(Token::AndAnd)
             }
 
-            '0'..='9' => {
-                let mut num_str = String::new();
-                parse_digits(&mut num_str, &mut chars);
+            // `||` can also be two bars of nested absolute values, the parser splits it if needed
+            '|' => self.pair('|', Token::OrOr, Token::Bar),
+            '?' => self.single(Token::Question),
+            ':' => self.single(Token::Colon),
+            ';' => self.single(Token::Semicolon),
+            '\n' => self.newline(),
 
-                if chars.peek() == Some(&'.') {
-                    chars.next();
-                    num_str.push('.');
-                    parse_digits(&mut num_str, &mut chars);
+            '0'..='9' | '.' if char != '.' || peek_nth(&self.chars, 1).is_some_and(|c| c.is_ascii_digit()) => {
+                parse_number(&mut self.chars);
+                let end = self.pos();
+                let literal = &self.input[start..end];
 
-                    if num_str.ends_with('.') {
-                        panic!("Number literal must have digits after the decimal point");
-                    }
+                match number_value(literal) {
+                    Some(n) if self.number_literals.allows(literal) => Token::Number(n),
+                    _ => return Some(Err(ParseError::InvalidNumber(literal.to_string(), Span::new(start, end)))),
                 }
-
-                Token::Number(num_str.parse().unwrap())
             }
 
             char_pat!(IDENT) => {
-                let mut ident_str = String::new();
+                parse_ident(&mut self.chars);
+                let end = self.pos();
+                match_keyword(&self.input[start..end])
+            }
 
-                parse_ident(&mut ident_str, &mut chars);
-                match_keyword(ident_str)
+            _ => {
+                self.chars.next();
+                let span = Span::new(start, start + char.len_utf8());
+                return Some(Err(ParseError::UnexpectedChar(char, span)));
             }
+        };
 
-            _ => return Err(ParseError::UnexpectedChar(char)),
+        let end = self.pos();
+        Some(Ok((token, Span::new(start, end))))
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<(Token<'a>, Span), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.token()?;
+
+        let Some(max) = self.max_tokens else {
+            return Some(result);
         };
 
-        tokens.push(token);
+        match result {
+            Ok((token, span)) if !matches!(token, Token::Newline | Token::EOF) => {
+                self.count += 1;
+
+                if self.count > max {
+                    // The rest of the input is not tokenized, so the error is the last item
+                    let end = self.input.len();
+                    self.chars = self.input[end..].char_indices().peekable();
+                    self.in_superscript = false;
+                    self.is_done = true;
+                    return Some(Err(ParseError::TooManyTokens(max, Span::new(span.start, end))));
+                }
+
+                Some(Ok((token, span)))
+            }
+            result => Some(result),
+        }
+    }
+}
+
+impl FusedIterator for Lexer<'_> {}
+
+/// Consumes the characters of a number literal, it is validated later by `number_value`
+///
+/// Supported forms are `12`, `1.5`, `.5`, `6.02e23`, `1e-6`, `1_000`, `0x1F` and `0b101`.
+/// The exponent is only taken if digits follow the `e`, so `2e` is the number `2` followed by identifier `e`.
+fn parse_number(chars: &mut CharIter) {
+    if peek_nth(chars, 0) == Some('0') && matches!(peek_nth(chars, 1), Some('x' | 'X' | 'b' | 'B')) {
+        chars.nth(1);
+        // Taken greedily, so "0x1G" is reported as a whole instead of being split
+        skip_while(chars, |c| c.is_ascii_alphanumeric() || c == '_');
+        return;
+    }
+
+    parse_digits(chars);
+
+    if chars.next_if(|&(_, c)| c == '.').is_some() {
+        parse_digits(chars);
     }
 
-    Ok(tokens)
+    let has_exponent = match (peek_nth(chars, 0), peek_nth(chars, 1), peek_nth(chars, 2)) {
+        (Some('e' | 'E'), Some(c), _) if c.is_ascii_digit() => true,
+        (Some('e' | 'E'), Some('+' | '-'), Some(c)) if c.is_ascii_digit() => true,
+        _ => false,
+    };
+
+    if has_exponent {
+        chars.next();
+        chars.next_if(|(_, c)| matches!(c, '+' | '-'));
+        parse_digits(chars);
+    }
 }
 
-fn parse_digits(string: &mut String, chars: &mut std::iter::Peekable<std::str::Chars>) {
-    parse_sequence_while(string, chars, |c| c.is_digit(10));
+/// Converts the literal consumed by `parse_number` to its value
+///
+/// Returns `None` if the literal is malformed or if it doesn't fit into `f32`.
+pub(super) fn number_value(literal: &str) -> Option<f32> {
+    let (radix, digits) = match literal.get(..2) {
+        Some("0x" | "0X") => (16, &literal[2..]),
+        Some("0b" | "0B") => (2, &literal[2..]),
+        _ => (10, literal),
+    };
+
+    // Separators are allowed only between two digits, the literal is ASCII so the bytes are the characters
+    let bytes = digits.as_bytes();
+    let is_digit = |i: usize| bytes.get(i).is_some_and(|&b| (b as char).is_digit(radix));
+    let separators_valid = (0..bytes.len()).all(|i| bytes[i] != b'_' || (i > 0 && is_digit(i - 1) && is_digit(i + 1)));
+
+    if !separators_valid {
+        return None;
+    }
+
+    let value = if radix == 10 {
+        // Rust accepts "1." and "1.e5" but we want digits after the decimal point
+        let fraction_valid = digits
+            .split_once('.')
+            .is_none_or(|(_, fraction)| fraction.starts_with(|c: char| c.is_ascii_digit()));
+
+        if !fraction_valid {
+            return None;
+        }
+
+        let digits = match digits.contains('_') {
+            true => Cow::Owned(digits.replace('_', "")),
+            false => Cow::Borrowed(digits),
+        };
+
+        digits.parse::<f32>().ok()?
+    } else {
+        if digits.is_empty() {
+            return None;
+        }
+
+        let value = digits.chars().filter(|&c| c != '_').try_fold(0u128, |value, c| {
+            value.checked_mul(radix as u128)?.checked_add(c.to_digit(radix)? as u128)
+        })?;
+
+        value as f32
+    };
+
+    value.is_finite().then_some(value)
 }
 
-fn parse_ident(string: &mut String, chars: &mut std::iter::Peekable<std::str::Chars>) {
-    parse_sequence_while(string, chars, |c| matches!(c, char_pat!(IDENT)));
+/// Returns the ASCII character for the superscript digit or sign
+fn superscript(c: char) -> Option<char> {
+    Some(match c {
+        '⁰' => '0',
+        '¹' => '1',
+        '²' => '2',
+        '³' => '3',
+        '⁴'..='⁹' => char::from_digit(c as u32 - '⁴' as u32 + 4, 10)?,
+        '⁺' => '+',
+        '⁻' => '-',
+        _ => return None,
+    })
 }
 
-fn parse_sequence_while(
-    string: &mut String,
-    chars: &mut std::iter::Peekable<std::str::Chars>,
-    f: fn(char) -> bool,
-) {
-    while let Some(&char) = chars.peek() {
-        if f(char) {
-            string.push(char);
-            chars.next();
-        } else {
-            break;
-        }
-    }
+fn parse_digits(chars: &mut CharIter) {
+    skip_while(chars, |c| c.is_ascii_digit() || c == '_');
 }
 
-fn match_keyword(string: String) -> Token {
-    match string.as_str() {
-        _ => Token::Ident(string),
-    }
+fn peek_nth(chars: &CharIter, n: usize) -> Option<char> {
+    chars.clone().nth(n).map(|(_, c)| c)
+}
+
+fn parse_ident(chars: &mut CharIter) {
+    skip_while(chars, |c| matches!(c, char_pat!(IDENT)));
+}
+
+fn skip_while(chars: &mut CharIter, f: fn(char) -> bool) {
+    while chars.next_if(|&(_, c)| f(c)).is_some() {}
+}
+
+fn match_keyword(string: &str) -> Token<'_> {
+    Token::Ident(string)
 }
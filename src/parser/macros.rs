@@ -1,33 +1,21 @@
 macro_rules! char_pat {
+    // Greek letters except `π`, it is always the constant `pi`
     (IDENT) => {
-        'a'..='z' | 'A'..='Z' | '_'
+        'a'..='z' | 'A'..='Z' | '_' | 'Α'..='Ω' | 'α'..='ο' | 'ρ'..='ω'
     };
 }
 
+// Consumes the next token, if the pattern is not matched the function returns an error
+// Running out of tokens is treated the same as reaching the EOF token
 macro_rules! expect_token {
     ($pat:pat in ITER $iter:expr) => {
-        #[allow(irrefutable_let_patterns)]
-        let $pat = $iter.next().unwrap() else {
-            panic!("Unexpected token {:?}", $iter.next().unwrap())
-        };
-    };
-}
+        let next = $iter.next().unwrap_or((Token::EOF, Span::default()));
 
-// If the pattern is not matched the function returns an error
-// The return statements is used twice, because it use the Let ... = (if let ... else ...) else ... pattern
-macro_rules! expect_token_ret {
-    ($pat:pat in ITER $iter:expr) => {
         #[allow(irrefutable_let_patterns)]
-        let $pat = (if let Some(_) = $iter.peek() {
-            $iter.next().unwrap()
-        } else {
-            // there is no token to return
-            return Err(ParseError::UnexpectedToken(Token::EOF));
-        }) else {
-            // the token is not the expected one
-            return Err(ParseError::UnexpectedToken($iter.next().unwrap()));
+        let $pat = next else {
+            return Err(ParseError::unexpected(next.0, next.1));
         };
     };
 }
 
-pub(super) use {char_pat, expect_token, expect_token_ret};
+pub(super) use {char_pat, expect_token};
@@ -0,0 +1,575 @@
+//! Import of content MathML
+//!
+//! Content MathML is already a tree, so unlike LaTeX it is not translated to tokens,
+//! the elements are turned into `Expr` nodes directly.
+//! Function names are resolved the same way as in the text syntax, so `<csymbol>round</csymbol>`
+//! is the built-in `round` and `<ci>f</ci>` in the head of `<apply>` can be a registered or defined function.
+
+use std::f32::consts::{E, PI};
+
+use super::lexer::number_value;
+use super::parse_error::ParseError;
+use super::parser::{is_builtin_function, wrap_with_func};
+use super::span::Span;
+use super::ParseOptions;
+use crate::expr::Expr;
+
+/// Parses the content MathML into the expression
+///
+/// The root can be `<math>`, `<semantics>` or the expression element itself, annotations are ignored.
+/// Attributes are ignored as well, so `<cn type="integer">` is read as a plain number.
+/// `<cn>` is validated like a number literal of the text syntax, `inf`, `NaN` and values that overflow `f32`
+/// are `ParseError::InvalidNumber`, infinity is `<infinity/>`.
+///
+/// Every element is one level of `options.max_depth`, `options.max_tokens` doesn't apply to MathML.
+pub fn parse_content_mathml(input: &str, options: &ParseOptions) -> Result<Expr, ParseError> {
+    let mut reader = XmlReader {
+        input,
+        pos: 0,
+        depth: 0,
+        max_depth: options.max_depth,
+    };
+    let root = reader.document()?;
+
+    let expr = Converter { options }.expr(&root)?;
+    options.check_nodes(&expr, root.span)?;
+    Ok(expr)
+}
+
+/// Element of the XML tree, the namespace prefix is removed from the name
+///
+/// Text of the element is concatenated from all its parts, with entities decoded.
+struct Element<'a> {
+    name: &'a str,
+    children: Vec<Element<'a>>,
+    text: String,
+    span: Span,
+}
+
+struct XmlReader<'a> {
+    input: &'a str,
+    pos: usize,
+    // Number of the elements whose closing tag wasn't reached yet
+    depth: usize,
+    max_depth: Option<usize>,
+}
+
+impl<'a> XmlReader<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn unexpected(&self) -> ParseError {
+        match self.peek() {
+            Some(c) => ParseError::UnexpectedChar(c, Span::new(self.pos, self.pos + c.len_utf8())),
+            None => ParseError::UnexpectedEof(Span::new(self.pos, self.pos)),
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        if self.peek() != Some(c) {
+            return Err(self.unexpected());
+        }
+
+        self.pos += c.len_utf8();
+        Ok(())
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Skips everything up to and including `end`
+    fn skip_past(&mut self, end: &str) -> Result<(), ParseError> {
+        match self.rest().find(end) {
+            Some(i) => {
+                self.pos += i + end.len();
+                Ok(())
+            }
+            None => {
+                self.pos = self.input.len();
+                Err(self.unexpected())
+            }
+        }
+    }
+
+    /// Skips whitespace, comments, the XML declaration and the doctype
+    fn skip_misc(&mut self) -> Result<(), ParseError> {
+        loop {
+            self.skip_whitespace();
+
+            if self.rest().starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if self.rest().starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if self.rest().starts_with("<!") {
+                self.skip_past(">")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn document(&mut self) -> Result<Element<'a>, ParseError> {
+        self.skip_misc()?;
+        let root = self.element()?;
+        self.skip_misc()?;
+
+        match self.peek() {
+            None => Ok(root),
+            Some(_) => Err(self.unexpected()),
+        }
+    }
+
+    fn name(&mut self) -> Result<&'a str, ParseError> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | ':')))
+            .unwrap_or(rest.len());
+
+        if len == 0 {
+            return Err(self.unexpected());
+        }
+
+        self.pos += len;
+        let name = &rest[..len];
+        Ok(name.rsplit(':').next().unwrap_or(name))
+    }
+
+    fn element(&mut self) -> Result<Element<'a>, ParseError> {
+        let start = self.pos;
+        self.depth += 1;
+
+        if let Some(max) = self.max_depth.filter(|&max| self.depth > max) {
+            return Err(ParseError::TooDeep(max, Span::new(start, start + 1)));
+        }
+
+        self.expect('<')?;
+        let name = self.name()?;
+
+        let mut element = Element {
+            name,
+            children: vec![],
+            text: String::new(),
+            span: Span::default(),
+        };
+
+        loop {
+            self.skip_whitespace();
+
+            if self.rest().starts_with("/>") {
+                self.pos += 2;
+                element.span = Span::new(start, self.pos);
+                self.depth -= 1;
+                return Ok(element);
+            }
+
+            if self.peek() == Some('>') {
+                self.pos += 1;
+                break;
+            }
+
+            self.attribute()?;
+        }
+
+        loop {
+            let rest = self.rest();
+
+            if rest.starts_with("</") {
+                let closing_start = self.pos;
+                self.pos += 2;
+                let closing = self.name()?;
+                self.skip_whitespace();
+                self.expect('>')?;
+
+                if closing != name {
+                    return Err(ParseError::MismatchedTag(closing.to_string(), Span::new(closing_start, self.pos)));
+                }
+
+                element.span = Span::new(start, self.pos);
+                self.depth -= 1;
+                return Ok(element);
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+                let Some(len) = cdata.find("]]>") else {
+                    self.pos = self.input.len();
+                    return Err(self.unexpected());
+                };
+                element.text.push_str(&cdata[..len]);
+                self.pos += "<![CDATA[".len() + len + "]]>".len();
+            } else if rest.starts_with('<') {
+                element.children.push(self.element()?);
+            } else if rest.is_empty() {
+                return Err(self.unexpected());
+            } else {
+                self.text(&mut element.text)?;
+            }
+        }
+    }
+
+    /// Skips the attribute, `name="value"` or `name='value'`
+    fn attribute(&mut self) -> Result<(), ParseError> {
+        self.name()?;
+        self.skip_whitespace();
+        self.expect('=')?;
+        self.skip_whitespace();
+
+        let quote = match self.peek() {
+            Some(quote @ ('"' | '\'')) => quote,
+            _ => return Err(self.unexpected()),
+        };
+
+        self.pos += 1;
+        self.skip_past(&quote.to_string())
+    }
+
+    /// Reads the text up to the next `<`, decoding the entities
+    fn text(&mut self, text: &mut String) -> Result<(), ParseError> {
+        while let Some(c) = self.peek() {
+            match c {
+                '<' => break,
+                '&' => text.push(self.entity()?),
+                c => {
+                    text.push(c);
+                    self.pos += c.len_utf8();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decodes the predefined XML entities and character references, like `&#x3C0;`
+    fn entity(&mut self) -> Result<char, ParseError> {
+        let start = self.pos;
+        let rest = self.rest();
+        let error = ParseError::UnexpectedChar('&', Span::new(start, start + 1));
+
+        let Some(len) = rest.find(';') else {
+            return Err(error);
+        };
+
+        let c = match &rest[1..len] {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            reference => match reference.strip_prefix('#') {
+                Some(hex) if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16).ok(),
+                Some(decimal) => decimal.parse().ok(),
+                None => None,
+            }
+            .and_then(char::from_u32),
+        };
+
+        self.pos += len + 1;
+        c.ok_or(error)
+    }
+}
+
+struct Converter<'o> {
+    options: &'o ParseOptions,
+}
+
+impl Converter<'_> {
+    fn expr(&self, element: &Element) -> Result<Expr, ParseError> {
+        let span = element.span;
+
+        Ok(match element.name {
+            "math" | "semantics" => match element.children.first() {
+                Some(child) if element.name == "semantics" || element.children.len() == 1 => self.expr(child)?,
+                _ => return Err(ParseError::WrongNumberOfArgs(element.name.to_string(), element.children.len(), span)),
+            },
+
+            "cn" => {
+                let literal = element.text.trim();
+                match number_value(literal) {
+                    Some(n) if element.children.is_empty() => Expr::Num(n),
+                    _ => return Err(ParseError::InvalidNumber(literal.to_string(), span)),
+                }
+            }
+            "ci" => {
+                let name = self.options.canonical_name(self.leaf_text(element)?);
+                match self.options.constants.get(&name) {
+                    Some(value) => Expr::new_const(name, value),
+                    None if !self.options.is_variable_allowed(&name) => {
+                        return Err(ParseError::UnknownVariable(name.into_owned(), span));
+                    }
+                    None => Expr::Var(name.into_owned()),
+                }
+            }
+
+            "pi" => Expr::new_const("pi", PI),
+            "exponentiale" => Expr::new_const("e", E),
+            "infinity" => Expr::new_const("inf", f32::INFINITY),
+            "notanumber" => Expr::Num(f32::NAN),
+            "true" => Expr::Num(1.0),
+            "false" => Expr::Num(0.0),
+            "cerror" => Expr::Error,
+
+            "apply" => self.apply(element)?,
+            "piecewise" => self.piecewise(element)?,
+
+            name => return Err(ParseError::InvalidElement(name.to_string(), span)),
+        })
+    }
+
+    /// Returns the trimmed text of the element that can't have child elements, like `<ci>`
+    fn leaf_text<'e>(&self, element: &'e Element) -> Result<&'e str, ParseError> {
+        let text = element.text.trim();
+
+        match element.children.first() {
+            _ if text.is_empty() => Err(ParseError::InvalidElement(element.name.to_string(), element.span)),
+            Some(child) => Err(ParseError::InvalidElement(child.name.to_string(), child.span)),
+            None => Ok(text),
+        }
+    }
+
+    /// Evaluates the expression if it is constant and implicit evaluation is enabled
+    fn fold(&self, expr: Expr, span: Span) -> Result<Expr, ParseError> {
+        if !(self.options.implicit_evaluation && expr.is_constant()) {
+            return Ok(expr);
+        }
+
+        expr.eval_const()
+            .map(Expr::Num)
+            .map_err(|err| ParseError::EvalError(err, span))
+    }
+
+    fn apply(&self, element: &Element) -> Result<Expr, ParseError> {
+        let span = element.span;
+        let Some((head, rest)) = element.children.split_first() else {
+            return Err(ParseError::InvalidElement(element.name.to_string(), span));
+        };
+
+        // Qualifiers like `<bvar>` and `<logbase>` are not arguments
+        let (qualifiers, rest): (Vec<&Element>, Vec<&Element>) =
+            rest.iter().partition(|e| matches!(e.name, "bvar" | "degree" | "logbase"));
+
+        let find_qualifier = |name: &str| qualifiers.iter().find(|e| e.name == name).copied();
+
+        if let Some(unexpected) = qualifiers.iter().find(|e| match e.name {
+            "bvar" => head.name != "diff",
+            "degree" => head.name != "root",
+            _ => head.name != "log",
+        }) {
+            return Err(ParseError::InvalidElement(unexpected.name.to_string(), unexpected.span));
+        }
+
+        if head.name == "diff" {
+            let (body, var) = self.derivative(find_qualifier("bvar"), &rest, span)?;
+            return Ok(Expr::new_derivative(var, body));
+        }
+
+        // The derivative of a function applied to the point, `(d/dx expr)(a)`
+        if head.name == "apply" && head.children.first().is_some_and(|e| e.name == "diff") {
+            let diff: Vec<&Element> = head.children[1..].iter().collect();
+            let (body, var) = self.derivative(None, &diff, head.span)?;
+
+            let [point] = rest[..] else {
+                return Err(ParseError::WrongNumberOfArgs("diff".to_string(), rest.len(), span));
+            };
+
+            let point = self.expr(point)?;
+            return Ok(Expr::Derivative(Box::new(body), var, Some(Box::new(point))));
+        }
+
+        let args = rest
+            .iter()
+            .map(|arg| Ok((self.expr(arg)?, arg.span)))
+            .collect::<Result<Vec<(Expr, Span)>, ParseError>>()?;
+
+        let expr = match head.name {
+            "ci" | "csymbol" => self.call(head, args, span)?,
+
+            "root" => {
+                let [(arg, _)] = take_args(head, args, span)?;
+                match find_qualifier("degree") {
+                    Some(degree) => Expr::new_pow(arg, Expr::new_div(Expr::Num(1.0), self.qualifier(degree)?)),
+                    None => Expr::new_pow(arg, Expr::Num(0.5)),
+                }
+            }
+            "log" => {
+                let [(arg, _)] = take_args(head, args, span)?;
+                match find_qualifier("logbase") {
+                    Some(base) => Expr::new_log(self.qualifier(base)?, arg),
+                    None => Expr::new_log(Expr::Num(10.0), arg),
+                }
+            }
+
+            "plus" | "times" | "and" | "or" if args.len() >= 2 => {
+                let mut args = args.into_iter().map(|(arg, _)| arg);
+                let first = args.next().unwrap_or(Expr::Num(0.0));
+
+                args.fold(first, |lhs, rhs| match head.name {
+                    "plus" => Expr::new_add(lhs, rhs),
+                    "times" => Expr::new_mul(lhs, rhs),
+                    "and" => Expr::new_and(lhs, rhs),
+                    _ => Expr::new_or(lhs, rhs),
+                })
+            }
+
+            // `<apply><lt/>a b c</apply>` is the chain `a < b < c`
+            "lt" | "leq" | "eq" | "gt" | "geq" if args.len() >= 2 => {
+                let args: Vec<Expr> = args.into_iter().map(|(arg, _)| arg).collect();
+                let comparison = |lhs: &Expr, rhs: &Expr| {
+                    let (lhs, rhs) = (lhs.clone(), rhs.clone());
+                    match head.name {
+                        "lt" => Expr::new_lt(lhs, rhs),
+                        "leq" => Expr::new_le(lhs, rhs),
+                        "eq" => Expr::new_eq(lhs, rhs),
+                        "gt" => Expr::new_gt(lhs, rhs),
+                        _ => Expr::new_ge(lhs, rhs),
+                    }
+                };
+
+                let mut pairs = args.windows(2).map(|pair| comparison(&pair[0], &pair[1]));
+                let first = pairs.next().unwrap_or(Expr::Num(1.0));
+                pairs.fold(first, Expr::new_and)
+            }
+
+            "minus" if args.len() == 1 => {
+                let [(arg, _)] = take_args(head, args, span)?;
+                Expr::new_mul(Expr::Num(-1.0), arg)
+            }
+
+            "minus" | "divide" | "rem" | "quotient" | "power" | "neq" => {
+                let [(lhs, _), (rhs, _)] = take_args(head, args, span)?;
+                match head.name {
+                    "minus" => Expr::new_sub(lhs, rhs),
+                    "divide" => Expr::new_div(lhs, rhs),
+                    "rem" => Expr::new_mod(lhs, rhs),
+                    "quotient" => Expr::new_floor_div(lhs, rhs),
+                    "power" => Expr::new_pow(lhs, rhs),
+                    _ => Expr::new_ne(lhs, rhs),
+                }
+            }
+
+            "plus" | "times" | "and" | "or" | "lt" | "leq" | "eq" | "gt" | "geq" | "ln" | "sin" | "cos" | "tan"
+            | "cot" | "abs" | "floor" | "ceiling" | "factorial" | "not" => {
+                let [(arg, _)] = take_args(head, args, span)?;
+                match head.name {
+                    "ln" => Expr::new_log(Expr::new_const("e", E), arg),
+                    "sin" => Expr::new_sin(self.options.angle_unit.to_radians(arg)),
+                    "cos" => Expr::new_cos(self.options.angle_unit.to_radians(arg)),
+                    "tan" => Expr::new_tan(self.options.angle_unit.to_radians(arg)),
+                    "cot" => Expr::new_cot(self.options.angle_unit.to_radians(arg)),
+                    "abs" => Expr::new_abs(arg),
+                    "floor" => Expr::new_floor(arg),
+                    "ceiling" => Expr::new_ceil(arg),
+                    "factorial" => Expr::new_factorial(arg),
+                    "not" => Expr::new_not(arg),
+                    // Single operand of an n-ary operator
+                    _ => arg,
+                }
+            }
+
+            name => return Err(ParseError::InvalidElement(name.to_string(), head.span)),
+        };
+
+        self.fold(expr, span)
+    }
+
+    /// Pieces are tried in order, so `<piece>a c1</piece><piece>b c2</piece><otherwise>d</otherwise>`
+    /// is `c1 ? a : c2 ? b : d`, the `<otherwise>` is required
+    fn piecewise(&self, element: &Element) -> Result<Expr, ParseError> {
+        let span = element.span;
+        let Some((otherwise, pieces)) = element.children.split_last() else {
+            return Err(ParseError::InvalidElement(element.name.to_string(), span));
+        };
+
+        if otherwise.name != "otherwise" {
+            return Err(ParseError::InvalidElement(element.name.to_string(), span));
+        }
+
+        let mut expr = self.qualifier(otherwise)?;
+
+        for piece in pieces.iter().rev() {
+            if piece.name != "piece" {
+                return Err(ParseError::InvalidElement(piece.name.to_string(), piece.span));
+            }
+
+            let [value, cond] = &piece.children[..] else {
+                let name = piece.name.to_string();
+                return Err(ParseError::WrongNumberOfArgs(name, piece.children.len(), piece.span));
+            };
+
+            expr = Expr::new_cond(self.expr(cond)?, self.expr(value)?, expr);
+        }
+
+        self.fold(expr, span)
+    }
+
+    /// Calls the function named by `<ci>` or `<csymbol>`, names are the same as in the text syntax
+    fn call(&self, head: &Element, args: Vec<(Expr, Span)>, span: Span) -> Result<Expr, ParseError> {
+        let name = self.options.canonical_name(self.leaf_text(head)?);
+        let name = name.as_ref();
+
+        if !self.options.is_function_allowed(name) {
+            return Err(ParseError::FunctionNotRecognized(name.to_string(), head.span));
+        }
+
+        match self.options.scope.get(name) {
+            Some(definition) if !is_builtin_function(name) => {
+                if definition.params.len() != args.len() {
+                    return Err(ParseError::WrongNumberOfArgs(name.to_string(), args.len(), span));
+                }
+
+                let expr = definition.apply(args.into_iter().map(|(arg, _)| arg).collect());
+                self.options.check_nodes(&expr, span)?;
+                Ok(expr)
+            }
+            _ if name == "if" && args.len() == 3 => {
+                let [(cond, _), (then, _), (otherwise, _)] = take_args(head, args, span)?;
+                Ok(Expr::new_cond(cond, then, otherwise))
+            }
+            _ => wrap_with_func((name, head.span), args, span, self.options),
+        }
+    }
+
+    /// Returns the body and the variable of `<diff/>` with `<bvar>` and the body,
+    /// or with `<lambda>` containing them
+    fn derivative(&self, bvar: Option<&Element>, args: &[&Element], span: Span) -> Result<(Expr, String), ParseError> {
+        let (bvar, body) = match (bvar, args) {
+            (Some(bvar), [body]) => (bvar, *body),
+            (None, [lambda]) if lambda.name == "lambda" => match &lambda.children[..] {
+                [bvar, body] if bvar.name == "bvar" => (bvar, body),
+                _ => return Err(ParseError::InvalidElement(lambda.name.to_string(), lambda.span)),
+            },
+            _ => return Err(ParseError::WrongNumberOfArgs("diff".to_string(), args.len(), span)),
+        };
+
+        let var = match self.qualifier(bvar)? {
+            Expr::Var(var) => var,
+            expr => return Err(ParseError::DerivativeNotVariable(expr.to_string(), bvar.span)),
+        };
+
+        Ok((self.expr(body)?, var))
+    }
+
+    /// Returns the expression inside the wrapper like `<degree>` or `<otherwise>`, which must have a single child
+    fn qualifier(&self, element: &Element) -> Result<Expr, ParseError> {
+        match &element.children[..] {
+            [child] => self.expr(child),
+            children => Err(ParseError::WrongNumberOfArgs(element.name.to_string(), children.len(), element.span)),
+        }
+    }
+}
+
+/// Checks that the operator got exactly `N` arguments
+fn take_args<const N: usize>(
+    head: &Element,
+    args: Vec<(Expr, Span)>,
+    span: Span,
+) -> Result<[(Expr, Span); N], ParseError> {
+    let len = args.len();
+    args.try_into()
+        .map_err(|_| ParseError::WrongNumberOfArgs(head.name.to_string(), len, span))
+}
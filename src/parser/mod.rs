@@ -1,10 +1,22 @@
+mod cst;
+mod latex;
 mod lexer;
 mod macros;
+mod mathml;
+mod options;
 mod parse_error;
+#[allow(clippy::module_inception)]
 mod parser;
+mod span;
 mod token;
 
-pub use lexer::tokenize;
+pub use cst::{SyntaxNode, SyntaxToken, SyntaxTree};
+pub use latex::tokenize_latex;
+pub use lexer::Lexer;
+pub use mathml::parse_content_mathml;
+pub use options::{AngleUnit, NumberLiterals, ParseOptions};
 pub use parse_error::ParseError;
-pub use parser::parse;
+pub use parser::{parse, parse_definition, parse_program, parse_recovering};
+pub(crate) use parser::{builtin_function, BUILTIN_FUNCTIONS, PREFIX_BINDING_POWER};
+pub use span::{Location, Span};
 pub use token::Token;
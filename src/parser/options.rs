@@ -0,0 +1,398 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use crate::constants::Constants;
+use crate::expr::Expr;
+use crate::function::FunctionRegistry;
+use crate::names::lowercase;
+use crate::scope::{Definition, Scope};
+
+use super::lexer::Lexer;
+use super::parser::{is_builtin_function, BUILTIN_FUNCTIONS};
+use super::span::Span;
+use super::ParseError;
+
+/// Options that control how the input is parsed, see `Expr::parse_with`
+///
+/// The default options parse the standard syntax: no implicit evaluation or multiplication, case sensitive names,
+/// angles in radians, all forms of number literals and no limits. Only the built-in constants are recognized
+/// and no functions are registered or defined.
+///
+/// The options can be set through the fields or with the builder methods:
+///
+/// ```
+/// use pemel::prelude::*;
+///
+/// let options = ParseOptions::new()
+///     .with_implicit_multiplication(true)
+///     .with_angle_unit(AngleUnit::Degrees)
+///     .with_allowed_variables(["x", "y"]);
+///
+/// let expr = Expr::parse_with("2sin(x)", &options).unwrap();
+/// assert_eq!(expr.eval_with_var("x", 30.0).map(|y| y.round()), Ok(1.0));
+/// assert!(Expr::parse_with("2sin(z)", &options).is_err());
+/// ```
+///
+/// Untrusted input should be parsed with all the limits set, so it can't overflow the stack or blow up the tree,
+/// and evaluated with `Expr::eval_with_budget`:
+///
+/// ```
+/// use pemel::prelude::*;
+///
+/// let options = ParseOptions::new()
+///     .with_max_input_length(1_000)
+///     .with_max_tokens(200)
+///     .with_max_depth(50)
+///     .with_max_nodes(500);
+///
+/// let nested = format!("{}x{}", "(".repeat(100_000), ")".repeat(100_000));
+/// assert!(matches!(Expr::parse_with(&nested, &options), Err(ParseError::InputTooLong(..))));
+///
+/// let expr = Expr::parse_with("x^2 + 1", &options).unwrap();
+/// assert_eq!(expr.eval_with_budget(&[("x", 2.0)], 100), Ok(5.0));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Evaluate constant parts of the expression during parsing
+    pub implicit_evaluation: bool,
+
+    /// Treat operands written next to each other as multiplication
+    ///
+    /// The multiplication is inserted when an identifier or `(` follows an operand,
+    /// it binds the same as `*`, so:
+    ///
+    /// - `2x^2` is `2 * (x^2)`
+    /// - `1/2x` is `(1/2) * x`
+    /// - `(a+b)(a-b)` and `2 sin(x)` are products
+    /// - `x(y + 1)` is `x * (y + 1)` because `x` is not a function
+    /// - `2 3` is an error, a number never starts an implicit multiplication
+    /// - `sin x` is an error, function names still require parentheses
+    /// - `sinx` is a single variable, identifiers are not split
+    /// - `0x1` is a hexadecimal literal, not `0 * x1`
+    pub implicit_multiplication: bool,
+
+    /// Names parsed as `Expr::Const` instead of `Expr::Var`
+    pub constants: Constants,
+
+    /// Functions resolved when the name is not one of the built-in functions
+    pub functions: FunctionRegistry,
+
+    /// Functions defined in the expression language, see `define`
+    pub scope: Scope,
+
+    /// Names of the functions that can be called, `None` allows all of them
+    ///
+    /// It covers the built-in, registered and defined functions, a call of any other one is
+    /// `ParseError::FunctionNotRecognized`. In MathML it covers the functions named by `<ci>` and `<csymbol>`.
+    pub allowed_functions: Option<HashSet<String>>,
+
+    /// Names of the variables that can be used, `None` allows all of them
+    ///
+    /// Any other variable is `ParseError::UnknownVariable`. Constants, parameters of function definitions
+    /// and names bound in a `Program` are not variables, so they are always allowed.
+    ///
+    /// With case insensitive options the names are compared in lowercase, the builder methods convert them,
+    /// so `with_allowed_variables(["X"])` allows both `x` and `X`. The same holds for `allowed_functions`.
+    pub allowed_variables: Option<HashSet<String>>,
+
+    /// Match names regardless of case, `SIN(X)` is `sin(x)` and `PI` is the constant `pi`
+    ///
+    /// Names of functions and constants are matched to their registered spelling,
+    /// all other names are variables in lowercase.
+    pub case_insensitive: bool,
+
+    /// Unit of the arguments of `sin`, `cos`, `tan` and `cot`
+    pub angle_unit: AngleUnit,
+
+    /// Forms of number literals accepted in the text syntax
+    pub number_literals: NumberLiterals,
+
+    /// Read `//` as a line comment, like `#`, instead of the floor division
+    ///
+    /// The floor division is not available then, `x // 2` is `x` followed by a comment.
+    pub slash_comments: bool,
+
+    /// Longest input in bytes that is parsed, longer input is `ParseError::InputTooLong`
+    pub max_input_length: Option<usize>,
+
+    /// Most tokens the input can have, more of them is `ParseError::TooManyTokens`
+    ///
+    /// New lines and the final `Token::EOF` are not counted. In LaTeX, the tokens of the translated formula are counted.
+    /// The recovering parser parses the tokens up to the limit.
+    pub max_tokens: Option<usize>,
+
+    /// Deepest nesting of the expression, deeper one is `ParseError::TooDeep`
+    ///
+    /// Every parenthesis, function call and operator is one level, so `a + b + c` is as deep as `a + (b + c)`.
+    /// In LaTeX every group, command and script is a level too, in MathML every element is one level.
+    /// It keeps the recursive parser and the evaluation from overflowing the stack.
+    pub max_depth: Option<usize>,
+
+    /// Most nodes the parsed expression can have, more of them is `ParseError::TooManyNodes`
+    ///
+    /// It is checked also after each call of a defined function is inlined, so nested calls can't blow up the tree.
+    pub max_nodes: Option<usize>,
+}
+
+/// Unit of the angles taken by the trigonometric functions
+///
+/// In degrees, the argument is parsed as `Expr::Degrees`, which is converted to radians when evaluated,
+/// so `sin(x)` is `sin(x * pi / 180)` and it is printed back as `sin(x)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AngleUnit {
+    #[default]
+    Radians,
+    Degrees,
+}
+
+impl AngleUnit {
+    /// Returns the argument of a trigonometric function converted to radians
+    pub(crate) fn to_radians(self, arg: Expr) -> Expr {
+        match self {
+            AngleUnit::Radians => arg,
+            AngleUnit::Degrees => Expr::new_degrees(arg),
+        }
+    }
+}
+
+/// Forms of number literals accepted besides the plain decimal ones like `12` and `1.5`
+///
+/// All of them are accepted by default, a literal of a disabled form is `ParseError::InvalidNumber`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumberLiterals {
+    /// Scientific notation, `6.02e23` and `1e-6`
+    pub exponent: bool,
+
+    /// No digits before the decimal point, `.5`
+    pub leading_dot: bool,
+
+    /// `_` between digits, `1_000`
+    pub separators: bool,
+
+    /// `0x1F`
+    pub hexadecimal: bool,
+
+    /// `0b101`
+    pub binary: bool,
+}
+
+impl NumberLiterals {
+    /// Only the plain decimal literals
+    pub const DECIMAL: NumberLiterals = NumberLiterals {
+        exponent: false,
+        leading_dot: false,
+        separators: false,
+        hexadecimal: false,
+        binary: false,
+    };
+
+    /// Returns true if the literal is of an enabled form, the literal itself is validated by the lexer
+    pub(crate) fn allows(&self, literal: &str) -> bool {
+        match literal.get(..2) {
+            Some("0x" | "0X") => return self.hexadecimal && (self.separators || !literal.contains('_')),
+            Some("0b" | "0B") => return self.binary && (self.separators || !literal.contains('_')),
+            _ => (),
+        }
+
+        (self.exponent || !literal.contains(['e', 'E']))
+            && (self.leading_dot || !literal.starts_with('.'))
+            && (self.separators || !literal.contains('_'))
+    }
+}
+
+impl Default for NumberLiterals {
+    fn default() -> Self {
+        NumberLiterals {
+            exponent: true,
+            leading_dot: true,
+            separators: true,
+            hexadecimal: true,
+            binary: true,
+        }
+    }
+}
+
+impl ParseOptions {
+    /// Same as `ParseOptions::default()`, to start the chain of the builder methods
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_implicit_evaluation(mut self, enabled: bool) -> Self {
+        self.implicit_evaluation = enabled;
+        self
+    }
+
+    pub fn with_implicit_multiplication(mut self, enabled: bool) -> Self {
+        self.implicit_multiplication = enabled;
+        self
+    }
+
+    pub fn with_constants(mut self, constants: Constants) -> Self {
+        self.constants = constants;
+        self
+    }
+
+    pub fn with_functions(mut self, functions: FunctionRegistry) -> Self {
+        self.functions = functions;
+        self
+    }
+
+    pub fn with_scope(mut self, scope: Scope) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    /// Allows calls of only the listed functions, see `allowed_functions`
+    pub fn with_allowed_functions<S: Into<String>>(mut self, names: impl IntoIterator<Item = S>) -> Self {
+        self.allowed_functions = Some(names.into_iter().map(Into::into).collect());
+        self.normalize_allowed_names();
+        self
+    }
+
+    /// Allows only the listed variables, see `allowed_variables`
+    pub fn with_allowed_variables<S: Into<String>>(mut self, names: impl IntoIterator<Item = S>) -> Self {
+        self.allowed_variables = Some(names.into_iter().map(Into::into).collect());
+        self.normalize_allowed_names();
+        self
+    }
+
+    /// Matches names regardless of case, the allowed names set before are converted as well
+    pub fn case_insensitive(mut self) -> Self {
+        self.case_insensitive = true;
+        self.normalize_allowed_names();
+        self
+    }
+
+    pub fn with_angle_unit(mut self, unit: AngleUnit) -> Self {
+        self.angle_unit = unit;
+        self
+    }
+
+    pub fn with_number_literals(mut self, literals: NumberLiterals) -> Self {
+        self.number_literals = literals;
+        self
+    }
+
+    /// Reads `//` as a line comment instead of the floor division
+    pub fn slash_comments(mut self) -> Self {
+        self.slash_comments = true;
+        self
+    }
+
+    pub fn with_max_input_length(mut self, bytes: usize) -> Self {
+        self.max_input_length = Some(bytes);
+        self
+    }
+
+    pub fn with_max_tokens(mut self, tokens: usize) -> Self {
+        self.max_tokens = Some(tokens);
+        self
+    }
+
+    pub fn with_max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    pub fn with_max_nodes(mut self, nodes: usize) -> Self {
+        self.max_nodes = Some(nodes);
+        self
+    }
+
+    /// Parses the function definition, like `f(x) = x^2 + 1`, and adds it to the `scope`
+    ///
+    /// The definition is parsed with these options, so it can use the functions defined before.
+    pub fn define(&mut self, input: &str) -> Result<(), ParseError> {
+        let definition = Definition::parse(input, self)?;
+        self.scope.insert(definition);
+        Ok(())
+    }
+
+    /// Returns the lexer of the text syntax with these options
+    pub(crate) fn lexer<'i>(&self, input: &'i str) -> Lexer<'i> {
+        let mut lexer = Lexer::new(input).with_number_literals(self.number_literals);
+
+        if self.slash_comments {
+            lexer = lexer.with_slash_comments();
+        }
+
+        match self.max_tokens {
+            Some(max) => lexer.with_max_tokens(max),
+            None => lexer,
+        }
+    }
+
+    /// Returns the error if the input is longer than `max_input_length`
+    pub(crate) fn check_length(&self, input: &str) -> Result<(), ParseError> {
+        match self.max_input_length {
+            Some(max) if input.len() > max => Err(ParseError::InputTooLong(max, Span::new(max, input.len()))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the error if the expression has more than `max_nodes` nodes, the span is of the whole expression
+    pub(crate) fn check_nodes(&self, expr: &Expr, span: Span) -> Result<(), ParseError> {
+        match self.max_nodes {
+            Some(max) if expr.node_count() > max => Err(ParseError::TooManyNodes(max, span)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the name the parser looks up, it is the same unless the options are case insensitive
+    ///
+    /// Case insensitive name is the spelling of the function or constant with the same name, otherwise lowercase.
+    pub(crate) fn canonical_name<'n>(&self, name: &'n str) -> Cow<'n, str> {
+        if !self.case_insensitive {
+            return Cow::Borrowed(name);
+        }
+
+        let name_lowercase = lowercase(name);
+
+        // The names of the built-in functions are ASCII, the registries index their names by the lowercase
+        let known = BUILTIN_FUNCTIONS
+            .iter()
+            .map(|&(name, ..)| name)
+            .find(|known| known.eq_ignore_ascii_case(&name_lowercase))
+            .or_else(|| self.functions.get_ignore_case(&name_lowercase))
+            .or_else(|| self.scope.get_ignore_case(&name_lowercase))
+            .or_else(|| self.constants.get_ignore_case(&name_lowercase));
+
+        match known {
+            Some(known) if known == name => Cow::Borrowed(name),
+            Some(known) => Cow::Owned(known.to_string()),
+            None => Cow::Owned(name_lowercase),
+        }
+    }
+
+    /// Converts the allowed names to lowercase, if the options are case insensitive
+    fn normalize_allowed_names(&mut self) {
+        if !self.case_insensitive {
+            return;
+        }
+
+        for names in [&mut self.allowed_functions, &mut self.allowed_variables].into_iter().flatten() {
+            *names = names.iter().map(|name| lowercase(name)).collect();
+        }
+    }
+
+    /// Returns true if the name is a built-in, registered or defined function, the name must be canonical
+    pub(crate) fn is_function(&self, name: &str) -> bool {
+        is_builtin_function(name) || self.functions.get(name).is_some() || self.scope.get(name).is_some()
+    }
+
+    pub(crate) fn is_function_allowed(&self, name: &str) -> bool {
+        self.is_allowed(self.allowed_functions.as_ref(), name)
+    }
+
+    pub(crate) fn is_variable_allowed(&self, name: &str) -> bool {
+        self.is_allowed(self.allowed_variables.as_ref(), name)
+    }
+
+    fn is_allowed(&self, allowed: Option<&HashSet<String>>, name: &str) -> bool {
+        allowed.is_none_or(|allowed| match self.case_insensitive {
+            true => allowed.contains(&lowercase(name)),
+            false => allowed.contains(name),
+        })
+    }
+}
@@ -1,16 +1,167 @@
+use std::fmt::Display;
+
+use super::span::{floor_char_boundary, Location, Span};
 use super::token::Token;
 use crate::eval_error::EvalError;
 
 /// Error indicating that the parser encountered an unexpected syntax
+///
+/// Every variant carries the `Span` of the input it refers to,
+/// use `render` to get a caret-style diagnostic pointing at it.
 #[derive(Debug, Clone, PartialEq)]
 pub enum ParseError {
-    UnexpectedToken(Token),
-    UnexpectedChar(char),
-    WrongNumberOfArgs(usize),
-    FunctionNotRecognized(String),
+    /// The token is as written in the input, or its description like `new line`
+    UnexpectedToken(String, Span),
+    /// The input ended where more tokens were expected, e.g. empty input or a trailing operator
+    UnexpectedEof(Span),
+    /// Number literal is malformed, e.g. `1.` without digits after the decimal point
+    InvalidNumber(String, Span),
+    UnexpectedChar(char, Span),
+    /// Block comment `/* ...` is not closed by `*/`, the span covers the opening `/*`
+    UnterminatedComment(Span),
+    /// Function with the name was called with wrong number of arguments, the span covers the whole call
+    WrongNumberOfArgs(String, usize, Span),
+    /// Function is not built-in, registered or defined, or it is not in `ParseOptions::allowed_functions`
+    FunctionNotRecognized(String, Span),
+    /// Variable is not in `ParseOptions::allowed_variables`
+    UnknownVariable(String, Span),
+    /// Input is longer than `ParseOptions::max_input_length`, the span covers the part past the limit
+    InputTooLong(usize, Span),
+    /// Input has more tokens than `ParseOptions::max_tokens`, the span covers the input from the first token past the limit
+    TooManyTokens(usize, Span),
+    /// Expression is nested deeper than `ParseOptions::max_depth`, the span covers the token where the limit was reached
+    TooDeep(usize, Span),
+    /// Expression has more nodes than `ParseOptions::max_nodes`
+    TooManyNodes(usize, Span),
+    /// Function name is not followed by arguments in parentheses, e.g. `sin x` in implicit multiplication mode
+    MissingArguments(String, Span),
+    /// Function definition calls itself, directly or through its arguments
+    RecursiveDefinition(String, Span),
+    /// Parameter name is used twice in one function definition
+    DuplicateParameter(String, Span),
+    /// LaTeX command is not supported, or `\right` has no matching `\left`
+    UnsupportedCommand(String, Span),
+    /// MathML element is unknown, not allowed in its position, or has invalid content
+    InvalidElement(String, Span),
+    /// Closing tag of the MathML input doesn't match the open element, the name is of the closing tag
+    MismatchedTag(String, Span),
     /// Indicates that a derivative was taken with repsect to a non-variable
-    DerivativeNotVariable(String),
+    DerivativeNotVariable(String, Span),
 
     /// This error can occur when the parser is evaluating during parsing.
-    EvalError(EvalError),
+    ///
+    /// The span covers the constant subexpression that failed to evaluate.
+    EvalError(EvalError, Span),
+}
+
+impl ParseError {
+    pub(crate) fn unexpected(token: Token, span: Span) -> Self {
+        match token {
+            Token::EOF => ParseError::UnexpectedEof(span),
+            token => ParseError::UnexpectedToken(token.to_string(), span),
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            ParseError::UnexpectedToken(_, span)
+            | ParseError::UnexpectedEof(span)
+            | ParseError::InvalidNumber(_, span)
+            | ParseError::UnexpectedChar(_, span)
+            | ParseError::UnterminatedComment(span)
+            | ParseError::WrongNumberOfArgs(_, _, span)
+            | ParseError::FunctionNotRecognized(_, span)
+            | ParseError::UnknownVariable(_, span)
+            | ParseError::InputTooLong(_, span)
+            | ParseError::TooManyTokens(_, span)
+            | ParseError::TooDeep(_, span)
+            | ParseError::TooManyNodes(_, span)
+            | ParseError::MissingArguments(_, span)
+            | ParseError::RecursiveDefinition(_, span)
+            | ParseError::DuplicateParameter(_, span)
+            | ParseError::UnsupportedCommand(_, span)
+            | ParseError::InvalidElement(_, span)
+            | ParseError::MismatchedTag(_, span)
+            | ParseError::DerivativeNotVariable(_, span)
+            | ParseError::EvalError(_, span) => *span,
+        }
+    }
+
+    /// Renders the error as a human readable diagnostic
+    ///
+    /// The line of `input` containing the error is printed with the erroneous part underlined:
+    ///
+    /// ```text
+    /// error: unexpected token `x`
+    ///   |
+    /// 1 | 5 + 12x
+    ///   |       ^
+    /// ```
+    ///
+    /// `input` should be the same string that was parsed.
+    pub fn render(&self, input: &str) -> String {
+        let span = self.span();
+        let start = floor_char_boundary(input, span.start);
+        let end = floor_char_boundary(input, span.end).max(start);
+
+        let location = Location::at(input, start);
+        let line_start = input[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = input[start..].find('\n').map_or(input.len(), |i| start + i);
+        let line = &input[line_start..line_end];
+
+        let width = input[start..end.min(line_end)].chars().count().max(1);
+
+        let gutter = location.line.to_string();
+        let pad = " ".repeat(gutter.len());
+
+        format!(
+            "error: {}\n{} |\n{} | {}\n{} | {}{}",
+            self,
+            pad,
+            gutter,
+            line,
+            pad,
+            " ".repeat(location.column - 1),
+            "^".repeat(width),
+        )
+    }
 }
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::UnexpectedToken(token, _) => write!(f, "unexpected token `{}`", token),
+            ParseError::UnexpectedEof(_) => write!(f, "unexpected end of input"),
+            ParseError::InvalidNumber(literal, _) => write!(f, "invalid number literal `{}`", literal),
+            ParseError::UnexpectedChar(char, _) => write!(f, "unexpected character `{}`", char),
+            ParseError::UnterminatedComment(_) => write!(f, "unterminated block comment"),
+            ParseError::WrongNumberOfArgs(name, n, _) => {
+                write!(f, "wrong number of arguments for `{}` ({} given)", name, n)
+            }
+            ParseError::FunctionNotRecognized(name, _) => {
+                write!(f, "function `{}` is not recognized", name)
+            }
+            ParseError::UnknownVariable(name, _) => write!(f, "variable `{}` is not allowed", name),
+            ParseError::InputTooLong(max, _) => write!(f, "input is longer than {} bytes", max),
+            ParseError::TooManyTokens(max, _) => write!(f, "input has more than {} tokens", max),
+            ParseError::TooDeep(max, _) => write!(f, "expression is nested deeper than {} levels", max),
+            ParseError::TooManyNodes(max, _) => write!(f, "expression has more than {} nodes", max),
+            ParseError::MissingArguments(name, _) => {
+                write!(f, "function `{}` must be called with arguments in parentheses", name)
+            }
+            ParseError::RecursiveDefinition(name, _) => {
+                write!(f, "function `{}` can't call itself", name)
+            }
+            ParseError::DuplicateParameter(name, _) => write!(f, "parameter `{}` is already defined", name),
+            ParseError::UnsupportedCommand(name, _) => write!(f, "unsupported LaTeX command `\\{}`", name),
+            ParseError::InvalidElement(name, _) => write!(f, "invalid MathML element `<{}>`", name),
+            ParseError::MismatchedTag(name, _) => write!(f, "closing tag `</{}>` doesn't match the open element", name),
+            ParseError::DerivativeNotVariable(expr, _) => {
+                write!(f, "derivative must be taken with respect to a variable, found `{}`", expr)
+            }
+            ParseError::EvalError(err, _) => write!(f, "failed to evaluate constant expression: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
@@ -1,228 +1,1030 @@
 use core::f32;
 
 use super::macros::expect_token;
+use super::options::ParseOptions;
 use super::parse_error::ParseError;
-use super::token::{Token, TokenIter};
-use crate::expr::Expr;
-use crate::parser::macros::expect_token_ret;
+use super::span::Span;
+use super::token::{Token, TokenStream};
+use crate::expr::{is_true, Expr};
+use crate::function::Arity;
+use crate::program::Program;
+use crate::scope::Definition;
 
 type IsConst = bool;
-type ParseResult = Result<(Expr, IsConst), ParseError>;
+type ParseResult = Result<(Expr, IsConst, Span), ParseError>;
+/// Tokens of the statement ending with `Token::EOF`, and the separator that ended it
+type Statement<'t> = (Vec<(Token<'t>, Span)>, (Token<'t>, Span));
 
-pub fn parse(tokens: Vec<Token>, implicit_evaluation: bool) -> Result<Expr, ParseError> {
-    let mut tokens = tokens.into_iter().peekable();
-    let result = parse_expr(&mut tokens, implicit_evaluation).map(|x| x.0);
+/// Binding power of prefix `+`, `-` and `!`
+pub(crate) const PREFIX_BINDING_POWER: u8 = 13;
 
-    if tokens.peek().is_some() {
-        return Err(ParseError::UnexpectedToken(tokens.next().unwrap()));
+/// Returns left and right binding power of the infix operator, higher binds tighter
+///
+/// | operator              | left | right | associativity |
+/// |-----------------------|------|-------|---------------|
+/// | `? :`                 | 2    | 1     | right         |
+/// | `\|\|`                | 3    | 4     | left          |
+/// | `&&`                  | 5    | 6     | left          |
+/// | `< <= == != > >=`     | 7    | 8     | chained       |
+/// | `+` `-`               | 9    | 10    | left          |
+/// | `*` `/` `//` `%`      | 11   | 12    | left          |
+/// | implicit `*`          | 11   | 12    | left          |
+/// | prefix `+ - ! √`      |      | 13    |               |
+/// | `^`                   | 16   | 15    | right         |
+/// | postfix `!`           | 17   |       |               |
+///
+/// Left associative operators have the right power higher than the left one, right associative the other way around.
+/// So `a - b - c` is `(a - b) - c` and `a ^ b ^ c` is `a ^ (b ^ c)`.
+///
+/// Prefix operators sit between `*` and `^`, so `-x^2` is `-(x^2)` but `-x * y` is `(-x) * y`.
+///
+/// Comparisons are parsed as left associative, but `a < b < c` is then turned into `a < b && b < c`.
+///
+/// Postfix `!` binds tighter than `^`, so `2^3!` is `2^(3!)` and `-3!` is `-(3!)`.
+pub(super) fn infix_binding_power(token: &Token) -> Option<(u8, u8)> {
+    Some(match token {
+        Token::Question => (2, 1),
+        Token::OrOr => (3, 4),
+        Token::AndAnd => (5, 6),
+        token if is_comparison(token) => (7, 8),
+        Token::Plus | Token::Minus => (9, 10),
+        Token::Star | Token::Slash | Token::SlashSlash | Token::Percent => (11, 12),
+        Token::Caret => (16, 15),
+        _ => return None,
+    })
+}
+
+/// Returns left binding power of the postfix operator, `!` after an operand is the factorial
+pub(super) fn postfix_binding_power(token: &Token) -> Option<u8> {
+    match token {
+        Token::Bang => Some(17),
+        _ => None,
     }
+}
 
-    result
+pub(super) fn is_comparison(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Less | Token::LessEqual | Token::EqualEqual | Token::NotEqual | Token::Greater | Token::GreaterEqual
+    )
 }
 
-fn parse_expr(tokens: &mut TokenIter, implicit_evaluation: bool) -> ParseResult {
-    let result = parse_sum(tokens, implicit_evaluation);
+/// Parses the expression, the tokens are pulled from the iterator only when the parser gets to them
+///
+/// The iterator is usually the `Lexer`, so the input is tokenized along the way.
+/// If it yields an error, the error is returned unless the parser failed earlier in the input.
+pub fn parse<'t, I>(tokens: I, options: &ParseOptions) -> Result<Expr, ParseError>
+where
+    I: Iterator<Item = Result<(Token<'t>, Span), ParseError>>,
+{
+    let mut parser = Parser::new(tokens, options, false);
+    let result = parser.parse_expr().and_then(|(expr, _, span)| {
+        parser.expect_eof()?;
+        options.check_nodes(&expr, span)?;
+        Ok(expr)
+    });
 
-    result
+    parser.tokens.finish(result)
 }
 
-fn parse_sum(tokens: &mut TokenIter, implicit_evaluation: bool) -> ParseResult {
-    parse_binop(
-        |t| matches!(t, Some(Token::Plus | Token::Minus)),
-        |iter| parse_product(iter, implicit_evaluation),
-        tokens,
-        implicit_evaluation
-    )
+/// Parses the tokens without stopping at the first error
+///
+/// Erroneous parts of the input are replaced with `Expr::Error` and parsing continues
+/// at the next `,` or `)`, so the returned tree still contains every valid part.
+///
+/// The tree is `None` only if nothing could be parsed at all.
+/// Errors of the lexer and the parser are returned together, ordered by their position in the input.
+pub fn parse_recovering<'t, I>(tokens: I, options: &ParseOptions) -> (Option<Expr>, Vec<ParseError>)
+where
+    I: Iterator<Item = Result<(Token<'t>, Span), ParseError>>,
+{
+    let mut parser = Parser::new(tokens, options, true);
+
+    // In recovery mode the parser returns only the errors of the limits, which end parsing, the other ones are collected
+    let result = parser.parse_expr().and_then(|(expr, _, span)| {
+        parser.expect_eof()?;
+        options.check_nodes(&expr, span)?;
+        Ok(expr)
+    });
+
+    let mut errors = parser.tokens.errors;
+    errors.extend(parser.errors.unwrap_or_default());
+
+    let expr = match result {
+        Ok(expr) => Some(expr),
+        Err(err) => {
+            errors.push(err);
+            None
+        }
+    };
+    errors.sort_by_key(|err| err.span().start);
+
+    match expr {
+        Some(Expr::Error) | None => (None, errors),
+        expr => (expr, errors),
+    }
 }
 
-fn parse_product(tokens: &mut TokenIter, implicit_evaluation: bool) -> ParseResult {
-    parse_binop(
-        |t| matches!(t, Some(Token::Star | Token::Slash)),
-        |iter| parse_power(iter, implicit_evaluation),
-        tokens,
-        implicit_evaluation
-    )
+/// Parses the function definition in the form `name(param, ...) = body`
+pub fn parse_definition<'t, I>(tokens: I, options: &ParseOptions) -> Result<Definition, ParseError>
+where
+    I: Iterator<Item = Result<(Token<'t>, Span), ParseError>>,
+{
+    let mut parser = Parser::new(tokens, options, false);
+    let result = parser.parse_definition();
+    parser.tokens.finish(result)
 }
 
-fn parse_power(tokens: &mut TokenIter, implicit_evaluation: bool) -> ParseResult {
-    parse_binop(
-        |t| matches!(t, Some(Token::Caret)),
-        |iter| parse_atom(iter, implicit_evaluation),
-        tokens,
-        implicit_evaluation
-    )
+/// Parses the statements of a `Program`, separated by `;` or new lines
+///
+/// Every statement except the last one is either a binding `name = expr` or a function definition,
+/// the last one is the resulting expression.
+/// New lines inside parentheses don't separate statements.
+///
+/// The statements are split before parsing, so unlike `parse` this function needs all the tokens up front.
+pub fn parse_program(tokens: Vec<(Token, Span)>, options: &ParseOptions) -> Result<Program, ParseError> {
+    // Functions defined in the program are visible only in it
+    let mut options = options.clone();
+    let mut bindings: Vec<(String, Expr)> = vec![];
+    let mut statements = split_statements(tokens).into_iter().peekable();
+
+    while let Some((statement, separator)) = statements.next() {
+        let is_last = statements.peek().is_none();
+        let bound = bindings.iter().map(|(name, _)| name.clone()).collect();
+
+        match statement.as_slice() {
+            [(Token::Ident(_), _), (Token::Equals, _), ..] => {
+                let mut parser = Parser::new(statement.into_iter().map(Ok), &options, false);
+                parser.params = bound;
+                let tokens = &mut parser.tokens;
+                expect_token!((Token::Ident(name), _) in ITER tokens);
+                tokens.next();
+
+                let (expr, _, span) = parser.parse_expr()?;
+                parser.expect_eof()?;
+                options.check_nodes(&expr, span)?;
+                bindings.push((options.canonical_name(name).into_owned(), expr));
+            }
+
+            [(Token::Ident(_), _), (Token::LParen, _), ..] if statement.iter().any(|(t, _)| *t == Token::Equals) => {
+                let mut parser = Parser::new(statement.into_iter().map(Ok), &options, false);
+                parser.params = bound;
+                let mut definition = parser.parse_definition()?;
+
+                // Bindings are resolved where the function is defined, not where it is called
+                for (name, expr) in bindings.iter().rev() {
+                    if !definition.params.contains(name) {
+                        definition.body.substitute(name, expr.clone());
+                    }
+                }
+
+                options.scope.insert(definition);
+            }
+
+            _ if is_last => {
+                let mut parser = Parser::new(statement.into_iter().map(Ok), &options, false);
+                parser.params = bound;
+                let (result, _, span) = parser.parse_expr()?;
+                parser.expect_eof()?;
+                options.check_nodes(&result, span)?;
+
+                return Ok(Program { bindings, result });
+            }
+
+            // Only the last statement can be an expression, there would be no way to use the other ones
+            _ => return Err(ParseError::unexpected(separator.0, separator.1)),
+        }
+
+        if is_last {
+            let end = separator.1.end;
+            return Err(ParseError::UnexpectedEof(Span::new(end, end)));
+        }
+    }
+
+    // Only empty input has no statements, `split_statements` keeps at least the last one
+    Err(ParseError::UnexpectedEof(Span::default()))
 }
 
-fn parse_binop(
-    match_op: fn(Option<&Token>) -> bool,
-    parse_prev: impl Fn(&mut TokenIter) -> ParseResult,
-    tokens: &mut TokenIter,
-    implicit_evaluation: bool,
-) -> ParseResult {
-    let (mut lhs, mut is_lhs_const) = parse_prev(tokens)?;
+/// Splits the tokens at `;` and new lines that are not inside parentheses
+///
+/// Each statement ends with `Token::EOF` at the position of its separator, which is returned with it.
+/// Empty statements are skipped, except the last one, so the input always has at least one statement.
+fn split_statements(tokens: Vec<(Token<'_>, Span)>) -> Vec<Statement<'_>> {
+    let mut statements = vec![];
+    let mut statement = vec![];
+    let mut depth = 0usize;
 
-    while match_op(tokens.peek()) {
-        expect_token!(token in ITER tokens);
+    for (token, span) in tokens {
+        match token {
+            Token::LParen => depth += 1,
+            Token::RParen => depth = depth.saturating_sub(1),
+            Token::Newline if depth > 0 => continue,
+            // `;` inside parentheses is left for the parser to report
+            Token::Semicolon if depth > 0 => (),
+            Token::Semicolon | Token::Newline | Token::EOF => {
+                if !statement.is_empty() || (token == Token::EOF && statements.is_empty()) {
+                    statement.push((Token::EOF, Span::new(span.start, span.start)));
+                    statements.push((std::mem::take(&mut statement), (token, span)));
+                }
+                continue;
+            }
+            _ => (),
+        }
 
-        let (rhs, is_rhs_const) = parse_prev(tokens)?;
-        let are_both_const = is_lhs_const && is_rhs_const;
-        lhs = to_binop(&token, lhs, rhs, are_both_const && implicit_evaluation)?;
-        is_lhs_const = are_both_const;
+        statement.push((token, span));
     }
 
-    Ok((lhs, is_lhs_const))
+    statements
 }
 
-fn parse_atom(tokens: &mut TokenIter, implicit_evaluation: bool) -> ParseResult {
-    let sign = parse_sign(tokens);
-    let atom = match tokens.peek().unwrap() {
-        Token::LParen => parse_parens(tokens, implicit_evaluation),
-        Token::Ident(_) => parse_ident(tokens, implicit_evaluation),
+struct Parser<'a, 't, I> {
+    // New lines separate only the statements of a `Program`, the stream skips them as whitespace
+    tokens: TokenStream<'t, I>,
+    options: &'a ParseOptions,
+    // Errors collected in recovery mode, `None` if the parser should stop at the first error
+    errors: Option<Vec<ParseError>>,
+    // Parameters of the function being defined, they are always variables, even if a constant has the same name
+    params: Vec<String>,
+    // Name of the function being defined, to detect recursion
+    defining: Option<String>,
+    // Number of absolute values whose closing `|` wasn't reached yet
+    bar_depth: usize,
+    // Second half of `||` that closed two absolute values at once, it is the next token
+    closing_bar: Option<Span>,
+    // Nesting of the expression being parsed, see `ParseOptions::max_depth`
+    depth: usize,
+    // Parsing a branch of `?:` or `if`, or the right side of `&&` or `||`, which may never be evaluated
+    lazy: bool,
+    // Errors of the implicit evaluation in lazy branches, reported only if the branch is taken, see `settle`
+    deferred: Vec<ParseError>,
+}
 
-        Token::Number(_) => {
-            expect_token!(Token::Number(n) in ITER tokens);
-            Ok((Expr::Num(n), true))
+impl<'a, 't, I> Parser<'a, 't, I>
+where
+    I: Iterator<Item = Result<(Token<'t>, Span), ParseError>>,
+{
+    fn new(tokens: I, options: &'a ParseOptions, recovery: bool) -> Self {
+        Parser {
+            tokens: TokenStream::new(tokens, recovery),
+            options,
+            errors: recovery.then(Vec::new),
+            params: vec![],
+            defining: None,
+            bar_depth: 0,
+            closing_bar: None,
+            depth: 0,
+            lazy: false,
+            deferred: vec![],
         }
+    }
 
-        _ => Err(ParseError::UnexpectedToken(tokens.next().unwrap())),
-    };
+    /// Parses `name(param, ...) = body`, names already in `params` stay variables in the body
+    fn parse_definition(&mut self) -> Result<Definition, ParseError> {
+        let tokens = &mut self.tokens;
+
+        expect_token!((Token::Ident(name), _) in ITER tokens);
+        expect_token!((Token::LParen, _) in ITER tokens);
+
+        let name = self.options.canonical_name(name).into_owned();
+        let mut params: Vec<String> = vec![];
+
+        while let Some((Token::Ident(_), _)) = tokens.peek() {
+            expect_token!((Token::Ident(param), span) in ITER tokens);
+            let param = self.options.canonical_name(param).into_owned();
+
+            if params.contains(&param) {
+                return Err(ParseError::DuplicateParameter(param, span));
+            }
+            params.push(param);
+
+            if tokens.next_if(|(t, _)| *t == Token::Comma).is_none() {
+                break;
+            }
+        }
+
+        expect_token!((Token::RParen, _) in ITER tokens);
+        expect_token!((Token::Equals, _) in ITER tokens);
+
+        self.params.extend(params.iter().cloned());
+        self.defining = Some(name.clone());
+
+        let (body, _, span) = self.parse_expr()?;
+        self.expect_eof()?;
+        self.options.check_nodes(&body, span)?;
 
-    if sign == -1.0 {
-        let (expr, is_const) = atom?;
-        Ok((Expr::new_mul(-1.0, expr), is_const))
-    } else {
-        atom
+        Ok(Definition { name, params, body })
     }
-}
 
-fn parse_sign(tokens: &mut TokenIter) -> f32 {
-    let mut sign = 1.0;
+    /// Returns the error, or in recovery mode records it and returns `Expr::Error` in place of the failed part
+    fn recover(&mut self, err: ParseError, span: Span) -> ParseResult {
+        match &mut self.errors {
+            Some(errors) => {
+                // A token that was not consumed can be reported again by the caller
+                if errors.last() != Some(&err) {
+                    errors.push(err);
+                }
 
-    while let Some(&Token::Plus | &Token::Minus) = tokens.peek() {
-        let token = tokens.next().unwrap();
-        if let Token::Plus = token {
-            sign *= -1.0;
+                Ok((Expr::Error, false, span))
+            }
+            None => Err(err),
         }
     }
 
-    sign
-}
+    fn peek_span(&mut self) -> Span {
+        self.tokens.peek().map_or(Span::default(), |(_, span)| *span)
+    }
 
-fn parse_ident(tokens: &mut TokenIter, implicit_evaluation: bool) -> ParseResult {
-    expect_token!(Token::Ident(ident) in ITER tokens);
+    fn parse_expr(&mut self) -> ParseResult {
+        self.parse_expr_bp(0)
+    }
+
+    /// Parses an expression whose operators all bind at least as tight as `min_bp`
+    ///
+    /// See `infix_binding_power` for the operator table.
+    fn parse_expr_bp(&mut self, min_bp: u8) -> ParseResult {
+        let depth = self.depth;
+        self.enter()?;
 
-    if let Some(Token::LParen) = tokens.peek() {
-        expect_token!(Token::LParen in ITER tokens);
-        let (args, is_const) = parse_args(tokens, implicit_evaluation)?;
-        expect_token_ret!(Token::RParen in ITER tokens);
+        let lhs = self.parse_prefix()?;
+        let result = self.parse_infix(lhs, min_bp);
 
-        let func = to_func(ident, args, is_const && implicit_evaluation)?;
-        return Ok((func, is_const));
+        self.depth = depth;
+        result
     }
 
-    Ok((ident.into(), false))
-}
+    /// Goes one level deeper into the expression, returns the error if it is deeper than `max_depth`
+    ///
+    /// The error is returned even in recovery mode, the limit ends parsing.
+    fn enter(&mut self) -> Result<(), ParseError> {
+        self.depth += 1;
 
-fn parse_parens(tokens: &mut TokenIter, implicit_evaluation: bool) -> ParseResult {
-    expect_token!(Token::LParen in ITER tokens);
-    let result = parse_expr(tokens, implicit_evaluation);
-    expect_token_ret!(Token::RParen in ITER tokens);
-    result
-}
+        match self.options.max_depth {
+            Some(max) if self.depth > max => Err(ParseError::TooDeep(max, self.peek_span())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Parses the operators following the already parsed left operand, see `parse_expr_bp`
+    fn parse_infix(&mut self, (mut lhs, mut is_lhs_const, mut lhs_span): (Expr, IsConst, Span), min_bp: u8) -> ParseResult {
+        // Right operand of the comparison parsed in the previous iteration, it is the left operand of a chained one
+        let mut chained: Option<(Expr, IsConst, Span)> = None;
 
-fn parse_args(tokens: &mut TokenIter, implicit_evaluation: bool) -> Result<(Vec<Expr>, IsConst), ParseError> {
-    let mut args = vec![];
-    let mut is_const = true;
+        while let Some((token, token_span, is_implicit)) = self.peek_infix() {
+            if let Some(l_bp) = postfix_binding_power(&token) {
+                if l_bp < min_bp {
+                    break;
+                }
 
-    loop {
-        let (arg, is_arg_const) = parse_expr(tokens, implicit_evaluation)?;
-        args.push(arg);
-        is_const = is_const && is_arg_const;
+                // Each operator in a row nests the left operand one level deeper, it is restored by `parse_expr_bp`
+                self.enter()?;
+                self.tokens.next();
+                lhs_span = lhs_span.to(token_span);
+                (lhs, is_lhs_const, _) = self.fold(Expr::new_factorial(lhs), is_lhs_const, lhs_span)?;
+                continue;
+            }
+
+            let Some((l_bp, r_bp)) = infix_binding_power(&token) else {
+                break;
+            };
+
+            if l_bp < min_bp {
+                break;
+            }
+
+            self.enter()?;
+
+            if !is_implicit {
+                self.tokens.next();
+            }
+
+            if token == Token::Question {
+                return self.parse_conditional((lhs, lhs_span), r_bp);
+            }
+
+            if matches!(token, Token::AndAnd | Token::OrOr) {
+                (lhs, is_lhs_const, lhs_span) = self.parse_logical((&token, token_span), (lhs, is_lhs_const, lhs_span), r_bp)?;
+                continue;
+            }
+
+            let (rhs, is_rhs_const, rhs_span) = self.parse_expr_bp(r_bp)?;
+            let previous = chained.take();
 
-        if let Some(Token::Comma) = tokens.peek() {
-            tokens.next();
-        } else {
-            break;
+            if is_comparison(&token) {
+                chained = Some((rhs.clone(), is_rhs_const, rhs_span));
+            }
+
+            if let (true, Some((middle, is_middle_const, middle_span))) = (is_comparison(&token), previous) {
+                let span = middle_span.to(rhs_span);
+                let are_both_const = is_middle_const && is_rhs_const;
+                let (cmp, is_cmp_const, _) = self.build_binop((&token, token_span), middle, rhs, are_both_const, span)?;
+
+                lhs_span = lhs_span.to(rhs_span);
+                let are_both_const = is_lhs_const && is_cmp_const;
+                (lhs, is_lhs_const, _) = self.build_binop((&Token::AndAnd, token_span), lhs, cmp, are_both_const, lhs_span)?;
+                continue;
+            }
+
+            let are_both_const = is_lhs_const && is_rhs_const;
+            lhs_span = lhs_span.to(rhs_span);
+            (lhs, is_lhs_const, _) = self.build_binop((&token, token_span), lhs, rhs, are_both_const, lhs_span)?;
         }
+
+        Ok((lhs, is_lhs_const, lhs_span))
     }
 
-    Ok((args, is_const))
-}
+    /// Parses the right operand of `&&` or `||`, the operator is already consumed
+    ///
+    /// The right operand is evaluated only if the left one doesn't decide the result,
+    /// so with implicit evaluation `0 && 1 / 0` is `0` and `x > 0 && 1 / 0 > 1` is kept as it is.
+    fn parse_logical(&mut self, op: (&Token, Span), (lhs, is_lhs_const, lhs_span): (Expr, IsConst, Span), r_bp: u8) -> ParseResult {
+        let mark = self.deferred.len();
+        let lazy = std::mem::replace(&mut self.lazy, true);
+        let (rhs, is_rhs_const, rhs_span) = self.parse_expr_bp(r_bp)?;
+        self.lazy = lazy;
+
+        let span = lhs_span.to(rhs_span);
+        let decided = match (self.options.implicit_evaluation && is_lhs_const, op.0) {
+            (true, Token::AndAnd) => lhs.eval_const().ok().filter(|value| !is_true(*value)).map(|_| 0.0),
+            (true, _) => lhs.eval_const().ok().filter(|value| is_true(*value)).map(|_| 1.0),
+            (false, _) => None,
+        };
 
-fn to_func(ident: String, args: Vec<Expr>, is_const: IsConst) -> Result<Expr, ParseError> {
-    let func = wrap_with_func(ident, args)?;
+        if let Some(value) = decided {
+            self.settle(mark, &[rhs_span]);
+            return Ok((Expr::Num(value), true, span));
+        }
+
+        // The right operand of a constant left one is always evaluated
+        let skipped = if is_lhs_const { vec![] } else { vec![rhs_span] };
+        if let Some(err) = self.settle(mark, &skipped) {
+            return self.recover(err, span);
+        }
 
-    if is_const {
-        let val = func
-            .eval_const()
-            .map_err(|err| ParseError::EvalError(err))?;
+        self.build_binop(op, lhs, rhs, is_lhs_const && is_rhs_const, span)
+    }
 
-        Ok(val.into())
-    } else {
-        Ok(func)
+    /// Parses the branches of `cond ? then : otherwise`, the `?` is already consumed
+    fn parse_conditional(&mut self, (cond, cond_span): (Expr, Span), r_bp: u8) -> ParseResult {
+        let mark = self.deferred.len();
+        let lazy = std::mem::replace(&mut self.lazy, true);
+        let (then, _, then_span) = self.parse_expr()?;
+
+        if self.tokens.next_if(|(t, _)| *t == Token::Colon).is_none() {
+            self.lazy = lazy;
+            let span = self.peek_span();
+            let token = self.tokens.peek().map_or(Token::EOF, |(t, _)| *t);
+            return self.recover(ParseError::unexpected(token, span), span);
+        }
+
+        let (otherwise, _, otherwise_span) = self.parse_expr_bp(r_bp)?;
+        self.lazy = lazy;
+
+        let span = cond_span.to(otherwise_span);
+        self.build_cond(cond, (then, then_span), (otherwise, otherwise_span), span, mark)
+    }
+
+    /// Builds the conditional, with implicit evaluation a constant condition is replaced by the chosen branch
+    ///
+    /// Errors deferred since `mark` are reported only if they are in the chosen branch.
+    fn build_cond(&mut self, cond: Expr, then: (Expr, Span), otherwise: (Expr, Span), span: Span, mark: usize) -> ParseResult {
+        if !(self.options.implicit_evaluation && cond.is_constant()) {
+            if let Some(err) = self.settle(mark, &[then.1, otherwise.1]) {
+                return self.recover(err, span);
+            }
+
+            let expr = Expr::new_cond(cond, then.0, otherwise.0);
+            let is_const = expr.is_constant();
+            return Ok((expr, is_const, span));
+        }
+
+        match cond.eval_const() {
+            Ok(value) => {
+                let (branch, skipped) = if is_true(value) { (then.0, otherwise.1) } else { (otherwise.0, then.1) };
+
+                if let Some(err) = self.settle(mark, &[skipped]) {
+                    return self.recover(err, span);
+                }
+
+                let is_const = branch.is_constant();
+                Ok((branch, is_const, span))
+            }
+            Err(err) => self.recover(ParseError::EvalError(err, span), span),
+        }
+    }
+
+    /// Takes the errors deferred since `mark`, drops those inside the skipped spans and returns the first
+    /// of the others, in recovery mode the rest of them is recorded
+    ///
+    /// Inside another lazy branch the errors stay deferred, the outer branch decides about them.
+    fn settle(&mut self, mark: usize, skipped: &[Span]) -> Option<ParseError> {
+        let is_skipped = |err: &ParseError| {
+            let span = err.span();
+            skipped.iter().any(|skipped| skipped.start <= span.start && span.end <= skipped.end)
+        };
+
+        let mut errors = self.deferred.split_off(mark).into_iter().filter(|err| !is_skipped(err));
+
+        if self.lazy {
+            self.deferred.extend(errors);
+            return None;
+        }
+
+        let first = errors.next();
+        self.errors.iter_mut().for_each(|recorded| recorded.extend(errors.by_ref()));
+        first
+    }
+
+    /// Evaluates the expression if it is constant and implicit evaluation is enabled
+    fn fold(&mut self, expr: Expr, is_const: IsConst, span: Span) -> ParseResult {
+        if !(is_const && self.options.implicit_evaluation) {
+            return Ok((expr, is_const, span));
+        }
+
+        match expr.eval_const() {
+            Ok(val) => Ok((val.into(), true, span)),
+            // The branch may never be taken, the expression is kept and the error is reported by `settle`
+            Err(err) if self.lazy => {
+                self.deferred.push(ParseError::EvalError(err, span));
+                Ok((expr, false, span))
+            }
+            Err(err) => self.recover(ParseError::EvalError(err, span), span),
+        }
+    }
+
+    /// Returns the next infix operator without consuming it
+    ///
+    /// In implicit multiplication mode an identifier or `(` is the implicit `*`,
+    /// the last value tells that such operator has no token in the input.
+    fn peek_infix(&mut self) -> Option<(Token<'t>, Span, bool)> {
+        // The second half of a split `||` closes the absolute value, see `expect_bar`
+        if self.closing_bar.is_some() || self.closes_two_bars() {
+            return None;
+        }
+
+        match self.tokens.peek()? {
+            (Token::Ident(_) | Token::LParen, span) if self.options.implicit_multiplication => {
+                Some((Token::Star, Span::new(span.start, span.start), true))
+            }
+            (token, span) => Some((*token, *span, false)),
+        }
+    }
+
+    /// Parses prefix `+`, `-`, `!` and `√`, they bind looser than `^` so `-x^2` is `-(x^2)`
+    fn parse_prefix(&mut self) -> ParseResult {
+        let is_prefix = |(t, _): &(Token, Span)| matches!(t, Token::Plus | Token::Minus | Token::Bang | Token::Sqrt);
+        let Some((token, sign_span)) = self.tokens.next_if(is_prefix) else {
+            return self.parse_atom();
+        };
+
+        let is_literal = matches!(self.tokens.peek(), Some((Token::Number(_), _)));
+        let (expr, is_const, span) = self.parse_expr_bp(PREFIX_BINDING_POWER)?;
+        let span = sign_span.to(span);
+
+        match (token, expr) {
+            // `-2` is the negative literal, so printed negative numbers are parsed back as numbers
+            (Token::Minus, Expr::Num(n)) if is_literal => Ok((Expr::Num(-n), true, span)),
+            (Token::Minus, expr) => self.build_binop((&Token::Star, sign_span), Expr::Num(-1.0), expr, is_const, span),
+            (Token::Bang, expr) => self.fold(Expr::new_not(expr), is_const, span),
+            (Token::Sqrt, expr) => self.build_binop((&Token::Caret, sign_span), expr, Expr::Num(0.5), is_const, span),
+            (_, expr) => Ok((expr, is_const, span)),
+        }
     }
+
+    fn parse_atom(&mut self) -> ParseResult {
+        match self.tokens.peek() {
+            Some((Token::LParen, _)) => self.parse_parens(),
+            Some((Token::Bar | Token::OrOr, _)) => self.parse_abs(),
+            Some((Token::Ident(_), _)) => self.parse_ident(),
+            Some((Token::Number(_), _)) => {
+                let tokens = &mut self.tokens;
+                expect_token!((Token::Number(n), span) in ITER tokens);
+                Ok((Expr::Num(n), true, span))
+            }
+
+            // The token is not consumed, so the recovering parser can continue with it,
+            // it is either an operator or one of `,`, `)` and EOF
+            Some((token, span)) => {
+                let (token, span) = (*token, *span);
+                self.recover(ParseError::unexpected(token, span), span)
+            }
+            None => self.recover(ParseError::UnexpectedEof(Span::default()), Span::default()),
+        }
+    }
+
+    /// Builds the binary operation, evaluating it right away if both operands are constant
+    fn build_binop(&mut self, op: (&Token, Span), lhs: Expr, rhs: Expr, is_const: IsConst, span: Span) -> ParseResult {
+        match wrap_with_binop(op, lhs, rhs) {
+            Ok(expr) => self.fold(expr, is_const, span),
+            Err(err) => self.recover(err, span),
+        }
+    }
+
+    fn parse_ident(&mut self) -> ParseResult {
+        let tokens = &mut self.tokens;
+        expect_token!((Token::Ident(ident), ident_span) in ITER tokens);
+        let name = self.options.canonical_name(ident);
+        let ident = name.as_ref();
+
+        // In implicit multiplication mode `x(...)` is a product, unless `x` is a function
+        let is_function = self.options.is_function(ident);
+        let is_call = !self.options.implicit_multiplication || is_function;
+
+        if is_call && matches!(self.tokens.peek(), Some((Token::LParen, _))) {
+            if self.defining.as_deref() == Some(ident) {
+                let err = ParseError::RecursiveDefinition(ident.to_string(), ident_span);
+                return self.recover(err, ident_span);
+            }
+
+            self.tokens.next();
+
+            // The branches of `if` are lazy like the ones of `?:`, the condition is settled with them
+            let mark = self.deferred.len();
+            let lazy = self.lazy;
+            self.lazy = lazy || ident == "if";
+            let (args, is_const) = self.parse_args()?;
+            self.lazy = lazy;
+
+            let rparen_span = self.expect_closing()?;
+            let span = ident_span.to(rparen_span);
+
+            if !self.options.is_function_allowed(ident) {
+                return self.recover(ParseError::FunctionNotRecognized(ident.to_string(), ident_span), span);
+            }
+
+            if let Some(definition) = self.definition(ident) {
+                return self.call_definition(definition, args, span);
+            }
+
+            // Only the chosen branch of `if` is evaluated, so it is not folded like the other functions
+            let args = match (ident, <[_; 3]>::try_from(args)) {
+                ("if", Ok([(cond, _), then, otherwise])) => {
+                    return self.build_cond(cond, then, otherwise, span, mark);
+                }
+                (_, Ok(args)) => args.into(),
+                (_, Err(args)) => args,
+            };
+
+            if let Some(err) = self.settle(mark, &[]) {
+                return self.recover(err, span);
+            }
+
+            // Calls of impure functions are never constant
+            let is_const = is_const && self.is_pure(ident);
+            let options = self.options;
+
+            return match wrap_with_func((ident, ident_span), args, span, options) {
+                Ok(func) => self.fold(func, is_const, span),
+                Err(err) => self.recover(err, span),
+            };
+        }
+
+        if self.options.implicit_multiplication && is_function {
+            return self.recover(ParseError::MissingArguments(ident.to_string(), ident_span), ident_span);
+        }
+
+        if self.params.iter().any(|param| param == ident) {
+            return Ok((ident.into(), false, ident_span));
+        }
+
+        if let Some(value) = self.options.constants.get(ident) {
+            return Ok((Expr::new_const(ident, value), true, ident_span));
+        }
+
+        if !self.options.is_variable_allowed(ident) {
+            return self.recover(ParseError::UnknownVariable(ident.to_string(), ident_span), ident_span);
+        }
+
+        Ok((ident.into(), false, ident_span))
+    }
+
+    /// Inlines the call of the function defined in the expression language
+    fn call_definition(&mut self, definition: &Definition, args: Vec<(Expr, Span)>, span: Span) -> ParseResult {
+        if definition.params.len() != args.len() {
+            let err = ParseError::WrongNumberOfArgs(definition.name.clone(), args.len(), span);
+            return self.recover(err, span);
+        }
+
+        let expr = definition.apply(args.into_iter().map(|(arg, _)| arg).collect());
+        self.options.check_nodes(&expr, span)?;
+        let is_const = expr.is_constant();
+        self.fold(expr, is_const, span)
+    }
+
+    /// Returns the definition of the function, built-in functions take precedence over the definitions
+    fn definition(&self, ident: &str) -> Option<&'a Definition> {
+        let options = self.options;
+
+        if is_builtin_function(ident) {
+            return None;
+        }
+
+        options.scope.get(ident)
+    }
+
+    fn is_pure(&self, ident: &str) -> bool {
+        is_builtin_function(ident) || self.options.functions.get(ident).is_none_or(|func| func.is_pure())
+    }
+
+    fn parse_parens(&mut self) -> ParseResult {
+        let tokens = &mut self.tokens;
+        expect_token!((Token::LParen, lparen_span) in ITER tokens);
+        let (expr, is_const, _) = self.parse_expr()?;
+        let rparen_span = self.expect_closing()?;
+        Ok((expr, is_const, lparen_span.to(rparen_span)))
+    }
+
+    /// Parses `|x|`, an operand starting with `||` is the start of two nested absolute values, `||x| - 1|`
+    fn parse_abs(&mut self) -> ParseResult {
+        let Some((token, span)) = self.tokens.next() else {
+            return self.recover(ParseError::UnexpectedEof(Span::default()), Span::default());
+        };
+
+        if token == Token::Bar {
+            return self.parse_abs_inner(span);
+        }
+
+        let outer_span = Span::new(span.start, span.start + 1);
+        let inner_span = Span::new(span.start + 1, span.end);
+
+        self.bar_depth += 1;
+        let inner = self.parse_abs_inner(inner_span)?;
+        let (expr, is_const, _) = self.parse_infix(inner, 0)?;
+        let closing_span = self.expect_bar()?;
+        self.bar_depth -= 1;
+
+        let span = outer_span.to(closing_span);
+        self.fold(Expr::new_abs(expr), is_const, span)
+    }
+
+    /// Parses the contents of the absolute value and the closing bar, the opening one is already consumed
+    fn parse_abs_inner(&mut self, opening_span: Span) -> ParseResult {
+        self.bar_depth += 1;
+        let (expr, is_const, _) = self.parse_expr()?;
+        let closing_span = self.expect_bar()?;
+        self.bar_depth -= 1;
+
+        let span = opening_span.to(closing_span);
+        self.fold(Expr::new_abs(expr), is_const, span)
+    }
+
+    /// Consumes the closing `|` and returns its span
+    ///
+    /// `||` closes two absolute values, if there are two of them open and no operand follows it.
+    /// The second half is then left in `closing_bar` for the outer absolute value.
+    fn expect_bar(&mut self) -> Result<Span, ParseError> {
+        if let Some(span) = self.closing_bar.take() {
+            return Ok(span);
+        }
+
+        if self.closes_two_bars() {
+            if let Some((_, span)) = self.tokens.next() {
+                self.closing_bar = Some(Span::new(span.start + 1, span.end));
+                return Ok(Span::new(span.start, span.start + 1));
+            }
+        }
+
+        if let Some((_, span)) = self.tokens.next_if(|(t, _)| *t == Token::Bar) {
+            return Ok(span);
+        }
+
+        let span = self.peek_span();
+        let token = self.tokens.peek().map_or(Token::EOF, |(t, _)| *t);
+        self.recover(ParseError::unexpected(token, span), span)?;
+
+        Ok(span)
+    }
+
+    /// Returns true if the next `||` closes two absolute values instead of being the logical or
+    fn closes_two_bars(&mut self) -> bool {
+        if self.bar_depth < 2 || !matches!(self.tokens.peek(), Some((Token::OrOr, _))) {
+            return false;
+        }
+
+        // `||` followed by an operand is the logical or, like in `||a| || b|`,
+        // a bar after it is taken as another closing one, so `|||x|||` is `abs(abs(abs(x)))`
+        let next = self.tokens.peek_second().map(|(t, _)| *t);
+        !matches!(
+            next,
+            Some(Token::Number(_) | Token::Ident(_) | Token::LParen | Token::Plus | Token::Minus | Token::Bang)
+        )
+    }
+
+    fn parse_args(&mut self) -> Result<(Vec<(Expr, Span)>, IsConst), ParseError> {
+        let mut args = vec![];
+        let mut is_const = true;
+
+        if let Some((Token::RParen, _)) = self.tokens.peek() {
+            return Ok((args, is_const));
+        }
+
+        loop {
+            let (arg, is_arg_const, span) = self.parse_expr()?;
+            args.push((arg, span));
+            is_const = is_const && is_arg_const;
+
+            if let Some((Token::Comma, _)) = self.tokens.peek() {
+                self.tokens.next();
+            } else {
+                break;
+            }
+        }
+
+        Ok((args, is_const))
+    }
+
+    /// Consumes the closing `)` and returns its span
+    ///
+    /// In recovery mode a missing `)` is recorded, then the tokens are skipped up to the matching `)`.
+    /// If the input ends first, the parenthesis is treated as closed at the end of input.
+    fn expect_closing(&mut self) -> Result<Span, ParseError> {
+        if let Some((_, span)) = self.tokens.next_if(|(t, _)| *t == Token::RParen) {
+            return Ok(span);
+        }
+
+        if self.errors.is_none() {
+            let tokens = &mut self.tokens;
+            expect_token!((Token::RParen, span) in ITER tokens);
+            return Ok(span);
+        }
+
+        let span = self.peek_span();
+        let token = self.tokens.peek().map_or(Token::EOF, |(t, _)| *t);
+        self.recover(ParseError::unexpected(token, span), span)?;
+
+        Ok(self.synchronize().unwrap_or(span))
+    }
+
+    /// Skips tokens until the `)` closing the current group, which is consumed, or the end of input
+    ///
+    /// Returns the span of the consumed `)`.
+    fn synchronize(&mut self) -> Option<Span> {
+        let mut depth = 0;
+
+        while let Some((token, span)) = self.tokens.next_if(|(t, _)| *t != Token::EOF) {
+            match token {
+                Token::LParen => depth += 1,
+                Token::RParen if depth == 0 => return Some(span),
+                Token::RParen => depth -= 1,
+                _ => (),
+            }
+        }
+
+        None
+    }
+
+    /// Checks that all tokens were consumed
+    ///
+    /// In recovery mode, leftover tokens are reported and the rest of the input
+    /// is parsed as well, so errors in it are also collected.
+    fn expect_eof(&mut self) -> Result<(), ParseError> {
+        loop {
+            match self.tokens.next() {
+                Some((Token::EOF, _)) | None => return Ok(()),
+                Some((token, span)) => {
+                    self.recover(ParseError::unexpected(token, span), span)?;
+
+                    if !matches!(self.tokens.peek(), Some((Token::EOF | Token::RParen | Token::Comma, _))) {
+                        self.parse_expr()?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Built-in functions with their arity and signature, `wrap_with_func` builds their nodes
+pub(crate) const BUILTIN_FUNCTIONS: [(&str, Arity, &str); 21] = [
+    ("sin", Arity::Fixed(1), "sin(x)"),
+    ("cos", Arity::Fixed(1), "cos(x)"),
+    ("tan", Arity::Fixed(1), "tan(x)"),
+    ("cot", Arity::Fixed(1), "cot(x)"),
+    ("abs", Arity::Fixed(1), "abs(x)"),
+    ("sqrt", Arity::Fixed(1), "sqrt(x)"),
+    ("ln", Arity::Fixed(1), "ln(x)"),
+    ("log", Arity::Range { min: 1, max: 2 }, "log(x), log(base, x)"),
+    ("D", Arity::Range { min: 2, max: 3 }, "D(var, expr), D(var, expr, at)"),
+    ("if", Arity::Fixed(3), "if(cond, then, else)"),
+    ("floor", Arity::Fixed(1), "floor(x)"),
+    ("ceil", Arity::Fixed(1), "ceil(x)"),
+    ("round", Arity::Fixed(1), "round(x)"),
+    ("trunc", Arity::Fixed(1), "trunc(x)"),
+    ("frac", Arity::Fixed(1), "frac(x)"),
+    ("sign", Arity::Fixed(1), "sign(x)"),
+    ("gamma", Arity::Fixed(1), "gamma(x)"),
+    ("lgamma", Arity::Fixed(1), "lgamma(x)"),
+    ("nCr", Arity::Fixed(2), "nCr(n, k)"),
+    ("nPr", Arity::Fixed(2), "nPr(n, k)"),
+    ("binomial", Arity::Fixed(2), "binomial(n, k)"),
+];
+
+/// Returns the arity and signature of the built-in function
+pub(crate) fn builtin_function(ident: &str) -> Option<(Arity, &'static str)> {
+    BUILTIN_FUNCTIONS
+        .iter()
+        .find(|(name, ..)| *name == ident)
+        .map(|&(_, arity, signature)| (arity, signature))
+}
+
+pub(super) fn is_builtin_function(ident: &str) -> bool {
+    builtin_function(ident).is_some()
 }
 
-fn wrap_with_func(ident: String, mut args: Vec<Expr>) -> Result<Expr, ParseError> {
+pub(super) fn wrap_with_func(
+    (ident, ident_span): (&str, Span),
+    mut args: Vec<(Expr, Span)>,
+    span: Span,
+    options: &ParseOptions,
+) -> Result<Expr, ParseError> {
     use std::mem;
 
     let len = args.len();
 
-    if len > 2 || args.is_empty() {
-        return Err(ParseError::WrongNumberOfArgs(len));
+    let Some((arity, _)) = builtin_function(ident) else {
+        return match options.functions.get(ident) {
+            Some(func) if func.arity().accepts(len) => {
+                let args = args.into_iter().map(|(arg, _)| arg).collect();
+                Ok(Expr::Call(func.clone(), args))
+            }
+            Some(_) => Err(ParseError::WrongNumberOfArgs(ident.to_string(), len, span)),
+            None => Err(ParseError::FunctionNotRecognized(ident.to_string(), ident_span)),
+        };
+    };
+
+    if !arity.accepts(len) {
+        return Err(ParseError::WrongNumberOfArgs(ident.to_string(), len, span));
     }
 
-    let arg0 = mem::take(&mut args[0]);
+    let (arg0, arg0_span) = mem::take(&mut args[0]);
+    let angle = |arg| options.angle_unit.to_radians(arg);
 
-    Ok(match (ident.as_str(), len) {
-        ("sin", 1) => Expr::new_sin(arg0),
-        ("cos", 1) => Expr::new_cos(arg0),
-        ("tan", 1) => Expr::new_tan(arg0),
-        ("cot", 1) => Expr::new_cot(arg0),
+    Ok(match (ident, len) {
+        ("sin", 1) => Expr::new_sin(angle(arg0)),
+        ("cos", 1) => Expr::new_cos(angle(arg0)),
+        ("tan", 1) => Expr::new_tan(angle(arg0)),
+        ("cot", 1) => Expr::new_cot(angle(arg0)),
         ("abs", 1) => Expr::new_abs(arg0),
-        ("ln", 1) => Expr::new_log(f32::consts::E, arg0),
+        ("sqrt", 1) => Expr::new_pow(arg0, Expr::Num(0.5)),
+        ("floor", 1) => Expr::new_floor(arg0),
+        ("ceil", 1) => Expr::new_ceil(arg0),
+        ("round", 1) => Expr::new_round(arg0),
+        ("trunc", 1) => Expr::new_trunc(arg0),
+        ("frac", 1) => Expr::new_frac(arg0),
+        ("sign", 1) => Expr::new_sign(arg0),
+        ("ln", 1) => Expr::new_log(Expr::new_const("e", f32::consts::E), arg0),
         ("log", 1) => Expr::new_log(Expr::Num(10.0), arg0),
+        ("gamma", 1) => Expr::new_gamma(arg0),
+        ("lgamma", 1) => Expr::new_ln_gamma(arg0),
+
+        ("nCr" | "binomial", 2) => {
+            let (arg1, _) = mem::take(&mut args[1]);
+            Expr::new_comb(arg0, arg1)
+        }
+
+        ("nPr", 2) => {
+            let (arg1, _) = mem::take(&mut args[1]);
+            Expr::new_perm(arg0, arg1)
+        }
 
         ("log", 2) => {
-            let arg1 = mem::take(&mut args[1]);
+            let (arg1, _) = mem::take(&mut args[1]);
             Expr::new_log(arg0, arg1)
         }
 
-        ("D", 2) => {
-            let arg1 = mem::take(&mut args[1]);
-            if let Expr::Var(var) = arg0 {
-                Expr::new_derivative(var, arg1)
-            } else {
-                return Err(ParseError::DerivativeNotVariable(arg0.to_string()));
+        // `D(x, expr, a)` is the derivative at `x = a`
+        ("D", 2 | 3) => {
+            let (arg1, _) = mem::take(&mut args[1]);
+            let Expr::Var(var) = arg0 else {
+                return Err(ParseError::DerivativeNotVariable(arg0.to_string(), arg0_span));
+            };
+
+            match args.get_mut(2) {
+                Some((arg2, _)) => Expr::Derivative(Box::new(arg1), var, Some(Box::new(mem::take(arg2)))),
+                None => Expr::new_derivative(var, arg1),
             }
         }
 
-        // ("cos" | "sin" | "ln" | "log" | "tan" | "cot", _) => return Err(ParseError::WrongNumberOfArgs(len)),
-        _ => return Err(ParseError::FunctionNotRecognized(ident)),
+        // The name is always one of the built-in functions here, only the number of arguments is wrong
+        _ => return Err(ParseError::WrongNumberOfArgs(ident.to_string(), len, span)),
     })
 }
 
-fn to_binop(token: &Token, lhs: Expr, rhs: Expr, is_const: bool) -> Result<Expr, ParseError> {
-    let expr = wrap_with_binop(token, lhs, rhs)?;
-
-    if is_const {
-        let val = expr
-            .eval_const()
-            .map_err(|err| ParseError::EvalError(err))?;
-
-        Ok(val.into())
-    } else {
-        Ok(expr)
-    }
-}
-
-fn wrap_with_binop(token: &Token, lhs: Expr, rhs: Expr) -> Result<Expr, ParseError> {
+fn wrap_with_binop((token, span): (&Token, Span), lhs: Expr, rhs: Expr) -> Result<Expr, ParseError> {
     Ok(match token {
         Token::Plus => Expr::new_add(lhs, rhs),
         Token::Minus => Expr::new_sub(lhs, rhs),
         Token::Star => Expr::new_mul(lhs, rhs),
         Token::Slash => Expr::new_div(lhs, rhs),
+        Token::SlashSlash => Expr::new_floor_div(lhs, rhs),
+        Token::Percent => Expr::new_mod(lhs, rhs),
         Token::Caret => Expr::new_pow(lhs, rhs),
-        _ => return Err(ParseError::UnexpectedToken(token.clone())),
+        Token::Less => Expr::new_lt(lhs, rhs),
+        Token::LessEqual => Expr::new_le(lhs, rhs),
+        Token::EqualEqual => Expr::new_eq(lhs, rhs),
+        Token::NotEqual => Expr::new_ne(lhs, rhs),
+        Token::Greater => Expr::new_gt(lhs, rhs),
+        Token::GreaterEqual => Expr::new_ge(lhs, rhs),
+        Token::AndAnd => Expr::new_and(lhs, rhs),
+        Token::OrOr => Expr::new_or(lhs, rhs),
+        _ => return Err(ParseError::unexpected(*token, span)),
     })
 }
@@ -0,0 +1,73 @@
+use std::fmt::Display;
+
+/// Byte range of a token or subexpression in the parsed input
+///
+/// `start` is inclusive and `end` is exclusive, so the spanned text is `&input[start..end]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// Returns the smallest span that covers both `self` and `other`
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Returns the line and column where the span starts in `input`
+    pub fn location(&self, input: &str) -> Location {
+        Location::at(input, self.start)
+    }
+}
+
+/// Line and column of a position in the input, both starting at 1
+///
+/// The column counts characters, not bytes, so it is the same as an editor shows for non-ASCII input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    /// Returns the location of the byte offset in `input`, offsets past the end are at the end
+    ///
+    /// Lines are separated by `\n`, so `\r\n` line endings work as well.
+    pub fn at(input: &str, offset: usize) -> Location {
+        let offset = floor_char_boundary(input, offset);
+        let line_start = input[..offset].rfind('\n').map_or(0, |i| i + 1);
+
+        Location {
+            line: input[..offset].matches('\n').count() + 1,
+            column: input[line_start..offset].chars().count() + 1,
+        }
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Returns the index clamped to the input and moved back to the start of the character it points into
+pub(super) fn floor_char_boundary(input: &str, mut index: usize) -> usize {
+    index = index.min(input.len());
+    while !input.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
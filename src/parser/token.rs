@@ -1,21 +1,197 @@
-use std::iter::Peekable;
-use std::vec::IntoIter;
+use std::fmt::Display;
 
-pub type TokenIter = Peekable<IntoIter<Token>>;
+use super::lexer::placeholder;
+use super::parse_error::ParseError;
+use super::span::Span;
 
-/// Token for the pemel parser
-#[derive(Debug, PartialEq, Clone)]
-pub enum Token {
+/// Token for the pemel parser, identifiers borrow from the input
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Token<'a> {
     Plus,
     Minus,
     Star,
     Slash,
+    /// `//`, floor division
+    SlashSlash,
+    Percent,
     Caret,
+    /// Prefix `√`, the square root
+    Sqrt,
     LParen,
     RParen,
     Number(f32),
     /// sin, and other special names are also Ident.
-    Ident(String),
+    Ident(&'a str),
     Comma,
+    Equals,
+    Less,
+    LessEqual,
+    EqualEqual,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    AndAnd,
+    OrOr,
+    /// Single `|` of the absolute value `|x|`
+    Bar,
+    Bang,
+    Question,
+    Colon,
+    /// Separates statements of a `Program`, just like `Newline`
+    Semicolon,
+    /// Separates statements of a `Program`, elsewhere it is skipped as whitespace
+    Newline,
     EOF,
 }
+
+impl Display for Token<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Star => write!(f, "*"),
+            Token::Slash => write!(f, "/"),
+            Token::SlashSlash => write!(f, "//"),
+            Token::Percent => write!(f, "%"),
+            Token::Caret => write!(f, "^"),
+            Token::Sqrt => write!(f, "√"),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Ident(s) => write!(f, "{}", s),
+            Token::Comma => write!(f, ","),
+            Token::Equals => write!(f, "="),
+            Token::Less => write!(f, "<"),
+            Token::LessEqual => write!(f, "<="),
+            Token::EqualEqual => write!(f, "=="),
+            Token::NotEqual => write!(f, "!="),
+            Token::Greater => write!(f, ">"),
+            Token::GreaterEqual => write!(f, ">="),
+            Token::AndAnd => write!(f, "&&"),
+            Token::OrOr => write!(f, "||"),
+            Token::Bar => write!(f, "|"),
+            Token::Bang => write!(f, "!"),
+            Token::Question => write!(f, "?"),
+            Token::Colon => write!(f, ":"),
+            Token::Semicolon => write!(f, ";"),
+            Token::Newline => write!(f, "new line"),
+            Token::EOF => write!(f, "end of input"),
+        }
+    }
+}
+
+/// Tokens consumed by the parser, pulled from the lexer only when the parser looks at them
+///
+/// New lines are skipped, the statements of a program are split before parsing.
+/// After the last token, `Token::EOF` is returned repeatedly.
+///
+/// Lexer errors are collected in `errors`. In recovery mode the erroneous input is replaced by its placeholder
+/// and lexing continues, otherwise the first error ends the tokens as if the input ended there.
+pub(super) struct TokenStream<'a, I> {
+    tokens: I,
+    peeked: Option<(Token<'a>, Span)>,
+    // The token after `peeked`, for the two token lookahead of `peek_second`
+    second: Option<(Token<'a>, Span)>,
+    pub errors: Vec<ParseError>,
+    recovery: bool,
+    // End of the last token, where the sentinel `Token::EOF` is
+    end: usize,
+    is_done: bool,
+}
+
+impl<'a, I> TokenStream<'a, I>
+where
+    I: Iterator<Item = Result<(Token<'a>, Span), ParseError>>,
+{
+    pub fn new(tokens: I, recovery: bool) -> Self {
+        TokenStream {
+            tokens,
+            peeked: None,
+            second: None,
+            errors: vec![],
+            recovery,
+            end: 0,
+            is_done: false,
+        }
+    }
+
+    fn pull(&mut self) -> (Token<'a>, Span) {
+        while !self.is_done {
+            match self.tokens.next() {
+                Some(Ok((Token::Newline, _))) => continue,
+                Some(Ok((token, span))) => {
+                    self.end = span.end;
+                    self.is_done = token == Token::EOF;
+                    return (token, span);
+                }
+                Some(Err(err)) if self.recovery => {
+                    let placeholder = placeholder(&err);
+                    self.errors.push(err);
+
+                    if let Some((token, span)) = placeholder {
+                        self.end = span.end;
+                        return (token, span);
+                    }
+                }
+                Some(Err(err)) => {
+                    self.end = err.span().start;
+                    self.errors.push(err);
+                    self.is_done = true;
+                }
+                None => self.is_done = true,
+            }
+        }
+
+        (Token::EOF, Span::new(self.end, self.end))
+    }
+
+    pub fn peek(&mut self) -> Option<&(Token<'a>, Span)> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.second.take().unwrap_or_else(|| self.pull()));
+        }
+
+        self.peeked.as_ref()
+    }
+
+    /// Returns the token after the next one
+    pub fn peek_second(&mut self) -> Option<&(Token<'a>, Span)> {
+        self.peek();
+
+        if self.second.is_none() {
+            self.second = Some(self.pull());
+        }
+
+        self.second.as_ref()
+    }
+
+    pub fn next_if(&mut self, f: impl FnOnce(&(Token<'a>, Span)) -> bool) -> Option<(Token<'a>, Span)> {
+        match self.peek() {
+            Some(next) if f(next) => self.peeked.take(),
+            _ => None,
+        }
+    }
+
+    /// Returns the error of the whole input, the lexer error takes precedence unless the parser failed before it
+    pub fn finish<T>(mut self, result: Result<T, ParseError>) -> Result<T, ParseError> {
+        let Some(lexer_error) = self.errors.pop() else {
+            return result;
+        };
+
+        match result {
+            Err(err) if err.span().start < lexer_error.span().start => Err(err),
+            _ => Err(lexer_error),
+        }
+    }
+}
+
+impl<'a, I> Iterator for TokenStream<'a, I>
+where
+    I: Iterator<Item = Result<(Token<'a>, Span), ParseError>>,
+{
+    type Item = (Token<'a>, Span);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.peek();
+        self.peeked.take()
+    }
+}
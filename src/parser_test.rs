@@ -0,0 +1,1245 @@
+//! Property tests checking that parsing never panics, no matter the input.
+//!
+//! Inputs are generated by a small deterministic PRNG, so failures are reproducible.
+
+use crate::constants::Constants;
+use crate::editor::{CompletionKind, Document, SemanticKind, SemanticToken};
+use crate::eval_error::EvalError;
+use crate::expr::Expr;
+use crate::function::{Arity, Function};
+use crate::parser::Lexer;
+use crate::parser::Location;
+use crate::parser::ParseError;
+use crate::parser::ParseOptions;
+use crate::parser::Span;
+use crate::parser::{SyntaxNode, SyntaxTree};
+use crate::parser::Token;
+use crate::printer::PrintOptions;
+use crate::program::Program;
+
+const FRAGMENTS: &[&str] = &[
+    "1", "0", "2.5", "1.", ".", "x", "y", "sin", "cos", "log", "ln", "abs", "D", "(", ")", "+",
+    "-", "*", "/", "^", ",", " ", "  ", "e", "1000000000000000000000000000000000000000", "π", "é", "²", "⁻", "√", "×", "θ",
+    "\t", "!", "\u{0}", "<", "<=", "==", "!=", "&&", "||", "&", "|", "?", ":", "if",
+    "#", "/*", "*/", "\n", "\u{a0}",
+];
+
+/// xorshift64, good enough to shuffle fragments around
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+fn random_fragments(rng: &mut Rng) -> String {
+    let len = rng.below(16);
+    (0..len).map(|_| FRAGMENTS[rng.below(FRAGMENTS.len())]).collect()
+}
+
+fn random_chars(rng: &mut Rng) -> String {
+    let len = rng.below(16);
+    (0..len)
+        .filter_map(|_| char::from_u32(rng.below(0x250) as u32))
+        .collect()
+}
+
+fn options(implicit_evaluation: bool, implicit_multiplication: bool) -> ParseOptions {
+    ParseOptions {
+        implicit_evaluation,
+        implicit_multiplication,
+        ..Default::default()
+    }
+}
+
+fn check(input: &str) {
+    for implicit_evaluation in [false, true] {
+        if let Ok(expr) = Expr::parse_with(input, &options(implicit_evaluation, true)) {
+            check_implicit_round_trip(&expr);
+        }
+
+        match Expr::parse(input, implicit_evaluation) {
+            Ok(expr) => {
+                let _ = expr.eval_with(&[("x", 1.5), ("y", -2.0)]);
+                check_round_trip(&expr);
+            }
+            Err(err) => {
+                let _ = err.render(input);
+            }
+        }
+
+        let (expr, errors) = Expr::parse_recovering(input, &options(implicit_evaluation, false));
+        assert_eq!(errors.is_empty(), Expr::parse(input, implicit_evaluation).is_ok());
+
+        if let Some(expr) = expr {
+            let _ = expr.eval_with(&[("x", 1.5), ("y", -2.0)]);
+        }
+    }
+
+    check_syntax_tree(input, &options(false, false));
+    check_syntax_tree(input, &options(false, true));
+}
+
+/// Checks that the syntax tree prints the input back, is lowered like the input is parsed and formats without changing meaning
+///
+/// The syntax tree has its own copy of the grammar, so its structure is checked against the parser too:
+/// the tree printed with every operation in parentheses must parse to the same expression as the input.
+fn check_syntax_tree(input: &str, options: &ParseOptions) {
+    let expr = Expr::parse_with(input, options);
+
+    let tree = match SyntaxTree::parse(input, options) {
+        Ok(tree) => tree,
+        Err(_) => return assert!(expr.is_err(), "{}", input),
+    };
+
+    assert_eq!(tree.to_string(), input);
+    assert_eq!(tree.to_expr(options), expr, "{}", input);
+
+    if let Ok(expr) = expr {
+        let grouped = parenthesized(&tree.root);
+        assert_eq!(Expr::parse_with(&grouped, options), Ok(expr.clone()), "{:?} -> {:?}", input, grouped);
+
+        let formatted = tree.format();
+        assert_eq!(Expr::parse_with(&formatted, options), Ok(expr), "{:?} -> {:?}", input, formatted);
+
+        let tree = SyntaxTree::parse(&formatted, options).unwrap();
+        assert_eq!(tree.format(), formatted, "{:?}", input);
+    }
+}
+
+/// Prints the tokens of the node with parentheses around every operation, so the precedence doesn't matter
+fn parenthesized(node: &SyntaxNode) -> String {
+    let operand = |node: &SyntaxNode| match node {
+        SyntaxNode::Number(_) | SyntaxNode::Name(_) | SyntaxNode::Parens { .. } | SyntaxNode::Call { .. } => parenthesized(node),
+        _ => format!("( {} )", parenthesized(node)),
+    };
+
+    match node {
+        SyntaxNode::Number(token) | SyntaxNode::Name(token) => token.token.to_string(),
+        SyntaxNode::Parens { inner, .. } => format!("( {} )", parenthesized(inner)),
+        SyntaxNode::Abs { inner, .. } => format!("| ( {} ) |", parenthesized(inner)),
+        SyntaxNode::Call { name, args, .. } => {
+            let args: Vec<String> = args.iter().map(operand).collect();
+            format!("{} ( {} )", name.token, args.join(" , "))
+        }
+        SyntaxNode::Prefix { op, operand: inner } => format!("{} {}", op.token, operand(inner)),
+        SyntaxNode::Postfix { operand: inner, op } => format!("{} {}", operand(inner), op.token),
+        SyntaxNode::Binary { lhs, op, rhs } => {
+            // `a < b < c` is a chain, not `(a < b) < c`
+            let lhs = match node.is_comparison() && lhs.is_comparison() {
+                true => parenthesized(lhs),
+                false => operand(lhs),
+            };
+            let op = op.map_or(String::new(), |op| op.token.to_string());
+            format!("{} {} {}", lhs, op, operand(rhs))
+        }
+        SyntaxNode::Conditional { cond, then, otherwise, .. } => {
+            format!("{} ? {} : {}", operand(cond), operand(then), operand(otherwise))
+        }
+    }
+}
+
+/// Checks that the printed expression is parsed back to the same tree, in all printing modes
+fn check_round_trip(expr: &Expr) {
+    let printed = expr.to_string();
+
+    // Infinite numbers and error placeholders have no syntax
+    if printed.contains("inf") || printed.contains("NaN") || printed.contains("<error>") {
+        return;
+    }
+
+    assert_eq!(Expr::parse(&printed, false).as_ref(), Ok(expr), "{}", printed);
+
+    let compact = PrintOptions {
+        compact: true,
+        ..Default::default()
+    };
+    let printed = expr.to_string_with(&compact);
+    assert_eq!(Expr::parse(&printed, false).as_ref(), Ok(expr), "{}", printed);
+}
+
+/// Checks that the expression parsed with implicit multiplication is parsed back the same without `*`
+fn check_implicit_round_trip(expr: &Expr) {
+    let implicit = PrintOptions {
+        implicit_multiplication: true,
+        ..Default::default()
+    };
+    let printed = expr.to_string_with(&implicit);
+
+    if printed.contains("inf") || printed.contains("NaN") || printed.contains("<error>") {
+        return;
+    }
+
+    assert_eq!(Expr::parse_with(&printed, &options(false, true)).as_ref(), Ok(expr), "{}", printed);
+}
+
+#[test]
+fn fuzz_fragments() {
+    let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+
+    for _ in 0..20_000 {
+        check(&random_fragments(&mut rng));
+    }
+}
+
+#[test]
+fn fuzz_chars() {
+    let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+
+    for _ in 0..20_000 {
+        check(&random_chars(&mut rng));
+    }
+}
+
+#[test]
+fn empty_input() {
+    assert_eq!(Expr::parse("", false), Err(ParseError::UnexpectedEof(Span::new(0, 0))));
+    assert_eq!(Expr::parse("   ", false), Err(ParseError::UnexpectedEof(Span::new(3, 3))));
+}
+
+#[test]
+fn trailing_operator() {
+    assert_eq!(Expr::parse("1 +", false), Err(ParseError::UnexpectedEof(Span::new(3, 3))));
+    assert_eq!(Expr::parse("sin(", false), Err(ParseError::UnexpectedEof(Span::new(4, 4))));
+}
+
+#[test]
+fn missing_fraction_digits() {
+    let expr = Expr::parse("2 * 1.", false);
+
+    assert_eq!(expr, Err(ParseError::InvalidNumber("1.".to_string(), Span::new(4, 6))));
+}
+
+#[test]
+fn lexer_borrows_identifiers() {
+    let input = "alpha_beta * sin(θ) + π";
+    let tokens: Vec<_> = Lexer::new(input).map(|result| result.unwrap()).collect();
+
+    assert_eq!(
+        tokens.iter().map(|(token, _)| *token).collect::<Vec<_>>(),
+        vec![
+            Token::Ident("alpha_beta"),
+            Token::Star,
+            Token::Ident("sin"),
+            Token::LParen,
+            Token::Ident("θ"),
+            Token::RParen,
+            Token::Plus,
+            Token::Ident("pi"),
+            Token::EOF,
+        ]
+    );
+
+    // Identifiers written in the input are its slices, not copies
+    for (token, span) in &tokens[..5] {
+        if let Token::Ident(name) = token {
+            assert!(std::ptr::eq(*name, &input[span.start..span.end]), "{}", name);
+        }
+    }
+}
+
+#[test]
+fn lexer_continues_after_errors() {
+    let results: Vec<_> = Lexer::new("x $ 1. x²").collect();
+
+    assert_eq!(
+        results,
+        vec![
+            Ok((Token::Ident("x"), Span::new(0, 1))),
+            Err(ParseError::UnexpectedChar('$', Span::new(2, 3))),
+            Err(ParseError::InvalidNumber("1.".to_string(), Span::new(4, 6))),
+            Ok((Token::Ident("x"), Span::new(7, 8))),
+            Ok((Token::Caret, Span::new(8, 8))),
+            Ok((Token::Number(2.0), Span::new(8, 10))),
+            Ok((Token::EOF, Span::new(10, 10))),
+        ]
+    );
+}
+
+#[test]
+fn unicode_whitespace() {
+    let input = "1 +\t2\u{a0}*\u{2003}x\r\n";
+    let expected = Expr::new_add(1.0, Expr::new_mul(2.0, "x"));
+
+    assert_eq!(Expr::parse(input, false), Ok(expected));
+}
+
+#[test]
+fn comments() {
+    let expected = Expr::new_add(Expr::new_mul(2.0, "x"), 1.0);
+
+    assert_eq!(Expr::parse("2 * x + 1 # linear", false), Ok(expected.clone()));
+    assert_eq!(Expr::parse("/* slope */ 2 * x /* spanning\nlines */ + 1", false), Ok(expected.clone()));
+    assert_eq!(Expr::parse("2/**/*x+1", false), Ok(expected));
+
+    // `//` is the floor division, not a comment
+    assert_eq!(Expr::parse("7 // 2 # note", false), Ok(Expr::new_floor_div(7.0, 2.0)));
+    assert_eq!(Expr::parse("x // note", false), Ok(Expr::new_floor_div("x", "note")));
+}
+
+#[test]
+fn slash_comments() {
+    let options = ParseOptions::new().slash_comments();
+    let parse = |input: &str| Expr::parse_with(input, &options);
+
+    assert_eq!(parse("x // note"), Ok(Expr::Var("x".to_string())));
+    assert_eq!(parse("7 /* a */ / 2 // b\n"), Ok(Expr::new_div(7.0, 2.0)));
+    assert_eq!(parse("7 // 2"), Ok(Expr::Num(7.0)));
+    assert!(matches!(parse("7 / / 2"), Err(ParseError::UnexpectedToken(..))));
+
+    let program = Program::parse_with("a = 2 // the base\na * x // result", &options).unwrap();
+    assert_eq!(program.eval_with(&[("x", 3.0)]), Ok(6.0));
+
+    let tokens: Vec<_> = Lexer::new("1 // 2").with_slash_comments().map(|result| result.unwrap().0).collect();
+    assert_eq!(tokens, [Token::Number(1.0), Token::EOF]);
+
+    let input = "// area\n pi * ( r^2 ) // r in meters";
+    let tree = SyntaxTree::parse(input, &options).unwrap();
+    assert_eq!(tree.to_string(), input);
+    assert_eq!(tree.format(), "// area\npi * r^2 // r in meters");
+}
+
+#[test]
+fn comments_in_program() {
+    let input = "a = 2 # the base\n/* the product,\n   not a statement */ b = a * x\nb + 1 # result";
+    let program = Program::parse(input, false).unwrap();
+
+    assert_eq!(program.bindings.len(), 2);
+    assert_eq!(program.eval_with(&[("x", 3.0)]), Ok(7.0));
+}
+
+#[test]
+fn unterminated_comment() {
+    let expr = Expr::parse("x + /* y", false);
+
+    assert_eq!(expr, Err(ParseError::UnterminatedComment(Span::new(4, 6))));
+    assert_eq!(expr.unwrap_err().to_string(), "unterminated block comment");
+}
+
+#[test]
+fn lexer_locations() {
+    let input = "θ +\n  /* a\n */ λ";
+    let mut lexer = Lexer::new(input);
+
+    assert_eq!(lexer.location(), Location { line: 1, column: 1 });
+    lexer.by_ref().take(3).for_each(drop);
+    assert_eq!(lexer.location(), Location { line: 2, column: 1 });
+
+    let (token, span) = lexer.next().unwrap().unwrap();
+    assert_eq!(token, Token::Ident("λ"));
+    assert_eq!(span.location(input), Location { line: 3, column: 5 });
+    assert_eq!(span.location(input).to_string(), "3:5");
+    assert_eq!(Location::at(input, input.len() + 10), Location { line: 3, column: 6 });
+}
+
+#[test]
+fn first_error_in_input_is_reported() {
+    // The input is tokenized lazily, so the parser can fail before the lexer gets to the unexpected character
+    assert_eq!(Expr::parse("x y $", false), Err(ParseError::UnexpectedToken("y".to_string(), Span::new(2, 3))));
+    assert_eq!(Expr::parse("(1 + $", false), Err(ParseError::UnexpectedChar('$', Span::new(5, 6))));
+    assert_eq!(Expr::parse("1 + 2 $", false), Err(ParseError::UnexpectedChar('$', Span::new(6, 7))));
+}
+
+#[test]
+fn recover_all_errors() {
+    let input = "1 + * 2 + sinc(3) + (4 5) + x";
+    let (expr, errors) = Expr::parse_recovering(input, &ParseOptions::default());
+
+    assert_eq!(
+        errors,
+        vec![
+            ParseError::UnexpectedToken("*".to_string(), Span::new(4, 5)),
+            ParseError::FunctionNotRecognized("sinc".to_string(), Span::new(10, 14)),
+            ParseError::UnexpectedToken("5".to_string(), Span::new(23, 24)),
+        ]
+    );
+
+    let expected = Expr::new_add(
+        Expr::new_add(
+            Expr::new_add(Expr::new_add(1.0, Expr::new_mul(Expr::Error, 2.0)), Expr::Error),
+            4.0,
+        ),
+        "x",
+    );
+    assert_eq!(expr, Some(expected));
+}
+
+#[test]
+fn recover_lexer_errors() {
+    let (expr, errors) = Expr::parse_recovering("2 $ 3.", &options(true, false));
+
+    assert_eq!(
+        errors,
+        vec![
+            ParseError::UnexpectedChar('$', Span::new(2, 3)),
+            ParseError::InvalidNumber("3.".to_string(), Span::new(4, 6)),
+            ParseError::UnexpectedToken("0".to_string(), Span::new(4, 6)),
+        ]
+    );
+    assert_eq!(expr, Some(Expr::Num(2.0)));
+}
+
+#[test]
+fn recover_unclosed_paren() {
+    let (expr, errors) = Expr::parse_recovering("sin(x + 1", &ParseOptions::default());
+
+    assert_eq!(errors, vec![ParseError::UnexpectedEof(Span::new(9, 9))]);
+    assert_eq!(expr, Some(Expr::new_sin(Expr::new_add("x", 1.0))));
+}
+
+#[test]
+fn recover_partial_evaluation() {
+    let (expr, errors) = Expr::parse_recovering("x * 2 + 3 / 0", &options(true, false));
+    let expr = expr.unwrap();
+
+    assert_eq!(errors, vec![ParseError::EvalError(EvalError::DivisionByZero, Span::new(8, 13))]);
+    assert_eq!(expr.eval_with_var("x", 1.0), Err(EvalError::ErrorNode));
+
+    let Expr::Add(valid, _) = expr else {
+        panic!("Expected addition, found {}", expr)
+    };
+    assert_eq!(valid.eval_with_var("x", 1.5), Ok(3.0));
+}
+
+#[test]
+fn recover_nothing() {
+    let (expr, errors) = Expr::parse_recovering(")", &ParseOptions::default());
+
+    assert_eq!(expr, None);
+    assert_eq!(errors, vec![ParseError::UnexpectedToken(")".to_string(), Span::new(0, 1))]);
+}
+
+#[test]
+fn number_literals() {
+    let cases = [
+        ("1e-6", 1e-6),
+        ("6.02E23", 6.02e23),
+        ("2.5e+3", 2500.0),
+        (".5", 0.5),
+        ("1_000_000", 1_000_000.0),
+        ("0.000_1", 0.0001),
+        ("0x1F", 31.0),
+        ("0XfF_fF", 65535.0),
+        ("0b1010", 10.0),
+    ];
+
+    for (input, expected) in cases {
+        assert_eq!(Expr::parse(input, false), Ok(Expr::Num(expected)), "{}", input);
+    }
+}
+
+#[test]
+fn exponent_needs_digits() {
+    let expr = Expr::parse("2e + e", false).unwrap_err();
+
+    // "2e" is a number followed by an identifier, not an exponent
+    assert_eq!(expr, ParseError::UnexpectedToken("e".to_string(), Span::new(1, 2)));
+}
+
+#[test]
+fn invalid_number_literals() {
+    let cases = [
+        "1.", "1.e5", "1_", "1__0", "1_.5", "0x", "0x1G", "0b102", "0x_1", "1e39",
+        "0x1_0000_0000_0000_0000_0000_0000_0000_0000",
+    ];
+
+    for input in cases {
+        let expected = ParseError::InvalidNumber(input.to_string(), Span::new(0, input.len()));
+        assert_eq!(Expr::parse(input, false), Err(expected), "{}", input);
+    }
+}
+
+/// Inputs paired with their fully parenthesized form, as printed by `Display`
+const PRECEDENCE_CORPUS: &[(&str, &str)] = &[
+    ("1 + 2 + 3", "((1 + 2) + 3)"),
+    ("1 - 2 - 3", "((1 - 2) - 3)"),
+    ("1 - 2 + 3", "((1 - 2) + 3)"),
+    ("1 / 2 / 3", "((1 / 2) / 3)"),
+    ("1 / 2 * 3", "((1 / 2) * 3)"),
+    ("1 + 2 * 3", "(1 + (2 * 3))"),
+    ("1 * 2 + 3", "((1 * 2) + 3)"),
+    ("2 ^ 3 ^ 2", "(2 ^ (3 ^ 2))"),
+    ("2 * 3 ^ 2", "(2 * (3 ^ 2))"),
+    ("2 ^ 3 * 2", "((2 ^ 3) * 2)"),
+    ("(2 ^ 3) ^ 2", "((2 ^ 3) ^ 2)"),
+    ("-x", "(-1 * x)"),
+    ("+x", "x"),
+    ("--x", "(-1 * (-1 * x))"),
+    ("-+x", "(-1 * x)"),
+    ("-x ^ 2", "(-1 * (x ^ 2))"),
+    ("-x * y", "((-1 * x) * y)"),
+    ("-x + y", "((-1 * x) + y)"),
+    ("x - -y", "(x - (-1 * y))"),
+    ("x * -y ^ 2", "(x * (-1 * (y ^ 2)))"),
+    ("2 ^ -x", "(2 ^ (-1 * x))"),
+    ("2 ^ -x ^ 2", "(2 ^ (-1 * (x ^ 2)))"),
+    ("x ^ y ^ -z", "(x ^ (y ^ (-1 * z)))"),
+    ("sin(x) ^ 2", "(sin(x) ^ 2)"),
+    ("-sin(x + 1) * 2", "((-1 * sin((x + 1))) * 2)"),
+    ("log(2, x ^ 2) ^ 3", "(log(2, (x ^ 2)) ^ 3)"),
+    ("x + 1 < y * 2", "((x + 1) < (y * 2))"),
+    ("0 < x < 1", "((0 < x) && (x < 1))"),
+    ("a == b != c", "((a == b) && (b != c))"),
+    ("(a < b) < c", "((a < b) < c)"),
+    ("a || b && c", "(a || (b && c))"),
+    ("a && b || c", "((a && b) || c)"),
+    ("!a && b", "(!(a) && b)"),
+    ("!x^2", "!((x ^ 2))"),
+    ("-x < 0 || x >= 1", "(((-1 * x) < 0) || (x >= 1))"),
+    ("a ? b : c ? d : e", "(a ? b : (c ? d : e))"),
+    ("a || b ? c + 1 : d", "((a || b) ? (c + 1) : d)"),
+    ("a ? b ? c : d : e", "(a ? (b ? c : d) : e)"),
+    ("if(x > 1, a, b)", "((x > 1) ? a : b)"),
+    ("x!", "(x)!"),
+    ("2 ^ 3!", "(2 ^ (3)!)"),
+    ("x! ^ 2", "((x)! ^ 2)"),
+    ("-x!", "(-1 * (x)!)"),
+    ("x!!", "((x)!)!"),
+    ("!x!", "!((x)!)"),
+    ("(x + 1)! / x!", "(((x + 1))! / (x)!)"),
+    ("x! < y", "((x)! < y)"),
+    ("x != y", "(x != y)"),
+    ("a % b * c", "((a % b) * c)"),
+    ("a + b // c", "(a + (b // c))"),
+    ("a // b / c", "((a // b) / c)"),
+    ("-a % b", "((-1 * a) % b)"),
+    ("a % b ^ 2", "(a % (b ^ 2))"),
+    ("floor(x / 2)", "floor((x / 2))"),
+    ("|x - 2|", "abs((x - 2))"),
+    ("|a| + |b|", "(abs(a) + abs(b))"),
+    ("||x| - 1|", "abs((abs(x) - 1))"),
+    ("||x||", "abs(abs(x))"),
+    ("|||x|||", "abs(abs(abs(x)))"),
+    ("|x - |y||", "abs((x - abs(y)))"),
+    ("|-x|^2", "(abs((-1 * x)) ^ 2)"),
+    ("|x|!", "(abs(x))!"),
+    ("|a || b|", "abs((a || b))"),
+    ("|a| || |b|", "(abs(a) || abs(b))"),
+    ("||a| || b|", "abs((abs(a) || b))"),
+    ("2×π", "(2 * pi)"),
+    ("a·b ÷ c", "((a * b) / c)"),
+    ("a⋅b", "(a * b)"),
+    ("−x", "(-1 * x)"),
+    ("x²", "(x ^ 2)"),
+    ("x⁻¹", "(x ^ -1)"),
+    ("x²³", "(x ^ 23)"),
+    ("(x + 1)²", "((x + 1) ^ 2)"),
+    ("√(x+1)", "((x + 1) ^ 0.5)"),
+    ("√x²", "((x ^ 2) ^ 0.5)"),
+    ("√x * y", "((x ^ 0.5) * y)"),
+    ("sqrt(x)", "(x ^ 0.5)"),
+    ("θ + λ", "(θ + λ)"),
+    ("sin(θ)²", "(sin(θ) ^ 2)"),
+];
+
+#[test]
+fn precedence_corpus() {
+    // The expected structure is written with all parentheses
+    for (input, expected) in PRECEDENCE_CORPUS {
+        let expr = Expr::parse(input, false).unwrap();
+        assert_eq!(Ok(expr), Expr::parse(expected, false), "{}", input);
+
+        check_syntax_tree(input, &options(false, true));
+        check_syntax_tree(expected, &options(false, true));
+    }
+}
+
+#[test]
+fn precedence_values() {
+    assert_eq!(Expr::parse("2^3^2", true), Ok(Expr::Num(512.0)));
+    assert_eq!(Expr::parse("-2^2", true), Ok(Expr::Num(-4.0)));
+    assert_eq!(Expr::parse("(-2)^2", true), Ok(Expr::Num(4.0)));
+    assert_eq!(Expr::parse("--3", true), Ok(Expr::Num(3.0)));
+    assert_eq!(Expr::parse("+3", true), Ok(Expr::Num(3.0)));
+}
+
+#[test]
+fn implicit_multiplication() {
+    let cases = [
+        ("2x", "(2 * x)"),
+        ("3(x + 1)", "(3 * (x + 1))"),
+        ("(a + b)(a - b)", "((a + b) * (a - b))"),
+        ("2 sin(x)", "(2 * sin(x))"),
+        ("x y", "(x * y)"),
+        ("2x^2", "(2 * (x ^ 2))"),
+        ("1/2x", "((1 / 2) * x)"),
+        ("-2x", "(-2 * x)"),
+        ("x(y + 1)", "(x * (y + 1))"),
+        ("sin(x)cos(x)", "(sin(x) * cos(x))"),
+        ("sinx", "sinx"),
+        ("2x²", "(2 * (x ^ 2))"),
+        ("2πr", "((2 * pi) * r)"),
+        ("πθ", "(pi * θ)"),
+        ("2e3x", "(2000 * x)"),
+    ];
+
+    for (input, expected) in cases {
+        let expr = Expr::parse_with(input, &options(false, true)).unwrap();
+        assert_eq!(Ok(expr), Expr::parse(expected, false), "{}", input);
+    }
+}
+
+#[test]
+fn implicit_multiplication_ambiguous() {
+    let options = options(false, true);
+
+    assert_eq!(
+        Expr::parse_with("sin x", &options),
+        Err(ParseError::MissingArguments("sin".to_string(), Span::new(0, 3)))
+    );
+    assert_eq!(
+        Expr::parse_with("2 3", &options),
+        Err(ParseError::UnexpectedToken("3".to_string(), Span::new(2, 3)))
+    );
+    assert_eq!(
+        Expr::parse_with("(x)2", &options),
+        Err(ParseError::UnexpectedToken("2".to_string(), Span::new(3, 4)))
+    );
+}
+
+#[test]
+fn implicit_multiplication_disabled() {
+    assert_eq!(
+        Expr::parse("2x", false),
+        Err(ParseError::UnexpectedToken("x".to_string(), Span::new(1, 2)))
+    );
+    assert!(matches!(Expr::parse("x(1)", false), Err(ParseError::FunctionNotRecognized(_, _))));
+}
+
+const LATEX_CORPUS: &[(&str, &str)] = &[
+    (r"\frac{a}{b}", "a / b"),
+    (r"\frac12 x", "(1 / 2) x"),
+    (r"x^{2} + 1", "x^2 + 1"),
+    (r"x^2y", "x^2 y"),
+    (r"e^{-x^2}", "e^(-x^2)"),
+    (r"\sqrt{x + 1}", "sqrt(x + 1)"),
+    (r"\sqrt[3]{x}", "x^(1 / 3)"),
+    (r"\sin x \cos x", "sin(x) cos(x)"),
+    (r"\sin(2x)", "sin(2x)"),
+    (r"\sin^2 x + \cos^{2}{x}", "sin(x)^2 + cos(x)^2"),
+    (r"\ln x", "ln(x)"),
+    (r"\log x", "log(x)"),
+    (r"\log_{2} 8", "log(2, 8)"),
+    (r"\log_b(x + 1)", "log(b, x + 1)"),
+    (r"\exp(x)", "e^x"),
+    (r"\left( a + b \right) \cdot c", "(a + b) * c"),
+    (r"\left[ a + b \right] \times c \div d", "(a + b) * c / d"),
+    (r"\left| x - 2 \right|", "|x - 2|"),
+    (r"2\pi r", "2 pi r"),
+    (r"\theta + \lambda", "θ + λ"),
+    (r"\frac{d}{dx} x^{2}", "D(x, x^2)"),
+    (r"\frac{d}{dx} x^2 + 1", "D(x, x^2) + 1"),
+    (r"\frac{d}{dt}\left(t^3\right) \cdot 2", "D(t, (t^3)) * 2"),
+    (r"\frac{\frac{1}{x}}{y}", "(1 / x) / y"),
+    (r"x \le 1", "x <= 1"),
+    (r"x \neq y", "x != y"),
+    (r"x = 1", "x == 1"),
+    (r"n!", "n!"),
+    (r"\mathrm{speed} \, t", "speed t"),
+    (r"1.5 \infty", "1.5 inf"),
+    (r"7 \bmod 2 + 7 \mod 3", "7 % 2 + 7 % 3"),
+    (r"\lfloor x \rfloor + \lceil x \rceil", "floor(x) + ceil(x)"),
+    (r"\lfloor \frac{7}{2} \rfloor \cdot \left\lfloor \frac{x}{2} + 1 \right\rfloor", "(7 // 2) * floor(x / 2 + 1)"),
+    (r"\binom{n}{k} + {}_{n}P_{k} + {}_nP_2", "nCr(n, k) + nPr(n, k) + nPr(n, 2)"),
+    (r"\Gamma(x) + \ln \Gamma(x) + \Gamma x", "gamma(x) + lgamma(x) + Γ x"),
+    (r"\operatorname{sgn}(x) \cdot \mathrm{sgn}(y)", "sign(x) * sign(y)"),
+    (r"x > 0 \wedge \neg (y > 0) \vee z", "x > 0 && !(y > 0) || z"),
+    (
+        r"\begin{cases} 1 & \text{if } x < 0 \\ 2 & \text{ if } x < 1 \\ 3 & \text{otherwise} \end{cases}",
+        "x < 0 ? 1 : x < 1 ? 2 : 3",
+    ),
+    (r"2\tau + \phi + \varphi", "2 tau + phi + phi"),
+    (r"\left. x^2 \right|", "(x^2)"),
+    (r"\left.\frac{d}{dt}\left(t^3\right)\right|_{t=2} + 1", "D(t, t^3, 2) + 1"),
+];
+
+#[test]
+fn latex_corpus() {
+    let options = options(false, true);
+
+    for (latex, text) in LATEX_CORPUS {
+        let expected = Expr::parse_with(text, &options).unwrap();
+        assert_eq!(Expr::parse_latex(latex, &options), Ok(expected), "{}", latex);
+    }
+}
+
+#[test]
+fn latex_errors() {
+    let options = options(false, true);
+
+    assert_eq!(
+        Expr::parse_latex(r"\foo + 1", &options),
+        Err(ParseError::UnsupportedCommand("foo".to_string(), Span::new(0, 4)))
+    );
+    assert_eq!(Expr::parse_latex(r"\frac{1}{x", &options), Err(ParseError::UnexpectedEof(Span::new(10, 10))));
+    assert_eq!(Expr::parse_latex(r"x}", &options), Err(ParseError::UnexpectedChar('}', Span::new(1, 2))));
+    assert_eq!(
+        Expr::parse_latex(r"1..2", &options),
+        Err(ParseError::InvalidNumber("1..2".to_string(), Span::new(0, 4)))
+    );
+    assert_eq!(
+        Expr::parse_latex(r"\left( x", &options),
+        Err(ParseError::UnexpectedEof(Span::new(8, 8)))
+    );
+    assert_eq!(
+        Expr::parse_latex(r"\left. x \right|_{x=1}", &options),
+        Err(ParseError::UnexpectedChar('_', Span::new(16, 17)))
+    );
+    assert_eq!(
+        Expr::parse_latex(r"\begin{matrix} x \end{matrix}", &options),
+        Err(ParseError::UnsupportedCommand("begin{matrix}".to_string(), Span::new(0, 6)))
+    );
+    // The last row of `cases` is the value otherwise
+    assert_eq!(
+        Expr::parse_latex(r"\begin{cases} 1 & \text{if } x < 0 \end{cases}", &options),
+        Err(ParseError::UnexpectedChar('\\', Span::new(35, 36)))
+    );
+    assert_eq!(
+        Expr::parse_latex(r"\begin{cases} 1 & \text{when} x < 0 \end{cases}", &options),
+        Err(ParseError::UnexpectedToken("when".to_string(), Span::new(18, 29)))
+    );
+    // Errors of the parser point into the LaTeX source
+    assert_eq!(
+        Expr::parse_latex(r"\sqrt{x} +", &options),
+        Err(ParseError::UnexpectedEof(Span::new(10, 10)))
+    );
+
+    let evaluating = ParseOptions {
+        implicit_evaluation: true,
+        ..options
+    };
+    assert_eq!(
+        Expr::parse_latex(r"\frac{1}{0}", &evaluating),
+        Err(ParseError::EvalError(EvalError::DivisionByZero, Span::new(0, 5)))
+    );
+}
+
+#[test]
+fn latex_random_input_never_panics() {
+    let fragments = [
+        r"\frac", r"\sqrt", r"\left", r"\right", r"\sin", r"\log", "_", "^", "{", "}", "(", ")", "[", "]", "|",
+        "x", "d", "dx", "1", "2.5", "+", "-", r"\cdot", r"\pi", " ", r"\", r"\,", r"\mathrm", "é",
+        r"\lfloor", r"\rfloor", r"\binom", r"\begin{cases}", r"\end{cases}", "&", r"\text{if }", r"\\", "P", ".",
+    ];
+    let mut rng = Rng(0x1a7e_c0de);
+    let options = options(true, true);
+
+    for _ in 0..2000 {
+        let len = rng.below(12);
+        let input: String = (0..len).map(|_| fragments[rng.below(fragments.len())]).collect();
+        let _ = Expr::parse_latex(&input, &options);
+    }
+}
+
+const TO_LATEX_CORPUS: &[(&str, &str)] = &[
+    ("(x + 1) / 2", r"\frac{x + 1}{2}"),
+    ("a - (b - c)", r"a - \left(b - c\right)"),
+    ("(a - b) - c", r"a - b - c"),
+    ("a - (-b)", r"a - \left(-b\right)"),
+    ("-(x + 1)", r"-\left(x + 1\right)"),
+    ("2x^2", "2x^{2}"),
+    ("2 * 3", r"2 \cdot 3"),
+    ("(x + 1)(x - 1)", r"\left(x + 1\right) \cdot \left(x - 1\right)"),
+    ("(x^2)^3", r"\left(x^{2}\right)^{3}"),
+    ("x^(y + 1)", "x^{y + 1}"),
+    ("sqrt(x + 1)", r"\sqrt{x + 1}"),
+    ("x^(1/3)", r"\sqrt[3]{x}"),
+    ("ln(x)", r"\ln\left(x\right)"),
+    ("log(x)", r"\log\left(x\right)"),
+    ("log(2, x)", r"\log_{2}\left(x\right)"),
+    ("2 sin(x)", r"2\sin\left(x\right)"),
+    ("|x - 1|", r"\left|x - 1\right|"),
+    ("pi * theta", r"\pi \cdot \mathrm{theta}"),
+    ("D(x, x^2)", r"\frac{d}{dx}\left(x^{2}\right)"),
+    ("D(x, x^2, 3)", r"\left.\frac{d}{dx}\left(x^{2}\right)\right|_{x=3}"),
+    ("x % 3", r"x \bmod 3"),
+    ("x // 2", r"\left\lfloor \frac{x}{2} \right\rfloor"),
+    ("cos(x) + tan(x) + cot(x)", r"\cos\left(x\right) + \tan\left(x\right) + \cot\left(x\right)"),
+    ("floor(x) + ceil(x)", r"\left\lfloor x \right\rfloor + \left\lceil x \right\rceil"),
+    ("round(x) + trunc(x)", r"\operatorname{round}\left(x\right) + \operatorname{trunc}\left(x\right)"),
+    ("frac(x) + sign(x)", r"\operatorname{frac}\left(x\right) + \operatorname{sgn}\left(x\right)"),
+    ("gamma(x) + lgamma(x)", r"\Gamma\left(x\right) + \ln\Gamma\left(x\right)"),
+    ("x! + nCr(x, 2) + nPr(x, 2)", r"x! + \binom{x}{2} + {}_{x}P_{2}"),
+    ("x < 1 && x <= 2", r"x < 1 \land x \le 2"),
+    ("x == 3 || x != 4", r"x = 3 \lor x \ne 4"),
+    ("!(x > 5) && x >= 6", r"\lnot \left(x > 5\right) \land x \ge 6"),
+    (
+        "x > 0 ? x : x < 0 ? -x : 0",
+        r"\begin{cases} x & \text{if } x > 0 \\ \begin{cases} -x & \text{if } x < 0 \\ 0 & \text{otherwise} \end{cases} & \text{otherwise} \end{cases}",
+    ),
+    ("e * tau * phi + inf", r"e \cdot \tau \cdot \varphi + \infty"),
+    ("τ * φ * Γ", r"τ \cdot φ \cdot \Gamma"),
+    ("f(x, 2) + g()", r"\operatorname{f}\left(x, 2\right) + \operatorname{g}\left(\right)"),
+];
+
+#[test]
+fn to_latex() {
+    let mut options = options(false, true);
+    options.functions.register(Function::new("f", Arity::Fixed(2), |args| Ok(args[0] - args[1])));
+    options.functions.register(Function::new("g", Arity::Fixed(0), |_| Ok(1.0)));
+
+    for (text, latex) in TO_LATEX_CORPUS {
+        let expr = Expr::parse_with(text, &options).unwrap();
+        assert_eq!(expr.to_latex(), *latex, "{}", text);
+        assert_eq!(Expr::parse_latex(latex, &options), Ok(expr), "{}", latex);
+    }
+
+    let cases = Expr::parse("if(x < 0, -x, x)", false).unwrap();
+    assert_eq!(
+        cases.to_latex(),
+        r"\begin{cases} -x & \text{if } x < 0 \\ x & \text{otherwise} \end{cases}"
+    );
+    assert_eq!(Expr::Error.to_latex(), r"\text{error}");
+}
+
+const MATHML_ROUND_TRIP: &[&str] = &[
+    "x + 1 - y",
+    "-x * 2 / (y - 3)",
+    "x % 3 + x // 2",
+    "x^(y + 1) + sqrt(x) + x^(1 / 3)",
+    "ln(x) + log(x) + log(2, x)",
+    "sin(x) + cos(x) + tan(x) + cot(x)",
+    "abs(x) + floor(x) + ceil(x) + round(x) + trunc(x) + frac(x) + sign(x)",
+    "x! + gamma(x) + lgamma(x) + nCr(x, 2) + nPr(x, 2)",
+    "x < 1 && x <= 2 || x == 3 && x != 4 || !(x > 5) && x >= 6",
+    "x > 0 ? x : -x",
+    "pi * e * tau * phi + inf",
+    "D(x, x^2 + y)",
+    "f(x, 2) + g()",
+];
+
+#[test]
+fn content_mathml_round_trip() {
+    let mut options = options(false, false);
+    options.functions.register(Function::new("f", Arity::Fixed(2), |args| Ok(args[0] - args[1])));
+    options.functions.register(Function::new("g", Arity::Fixed(0), |_| Ok(1.0)));
+
+    for text in MATHML_ROUND_TRIP {
+        let expr = Expr::parse_with(text, &options).unwrap();
+        let mathml = expr.to_content_mathml();
+        assert_eq!(Expr::parse_content_mathml(&mathml, &options), Ok(expr), "{}", mathml);
+    }
+
+    let mut derivative = Expr::parse("D(x, x^2)", false).unwrap();
+    derivative.substitute("x", Expr::Num(3.0));
+    let mathml = derivative.to_content_mathml();
+    assert!(mathml.contains("<apply><apply><diff/><lambda><bvar><ci>x</ci></bvar>"), "{}", mathml);
+    assert_eq!(Expr::parse_content_mathml(&mathml, &options), Ok(derivative));
+}
+
+#[test]
+fn content_mathml_input() {
+    let options = options(false, false);
+    let parse = |input: &str| Expr::parse_content_mathml(input, &options);
+    let text = |input: &str| Expr::parse(input, false);
+
+    assert_eq!(parse("<apply><plus/><ci>a</ci><ci>b</ci><cn>1</cn></apply>"), text("a + b + 1"));
+    assert_eq!(parse("<apply><lt/><cn>0</cn><ci>x</ci><cn>1</cn></apply>"), text("0 < x < 1"));
+    assert_eq!(parse("<apply><minus/><ci>x</ci></apply>"), text("-x"));
+    assert_eq!(
+        parse(
+            r#"<?xml version="1.0"?>
+            <m:math xmlns:m="http://www.w3.org/1998/Math/MathML">
+              <!-- x squared -->
+              <m:apply><m:power/><m:ci> x </m:ci><m:cn type="integer">2</m:cn></m:apply>
+            </m:math>"#
+        ),
+        text("x^2")
+    );
+    assert_eq!(
+        parse("<semantics><apply><ci>&#x3B8;</ci></apply><annotation>x</annotation></semantics>"),
+        Err(ParseError::FunctionNotRecognized("θ".to_string(), Span::new(18, 34)))
+    );
+    assert_eq!(
+        parse(
+            "<piecewise><piece><cn>1</cn><apply><lt/><ci>x</ci><cn>0</cn></apply></piece>\
+             <piece><cn>2</cn><apply><eq/><ci>x</ci><cn>0</cn></apply></piece>\
+             <otherwise><cn>3</cn></otherwise></piecewise>"
+        ),
+        text("x < 0 ? 1 : x == 0 ? 2 : 3")
+    );
+
+    let evaluating = self::options(true, false);
+    assert_eq!(
+        Expr::parse_content_mathml("<apply><times/><cn>2</cn><pi/></apply>", &evaluating),
+        Ok(Expr::Num(2.0 * std::f32::consts::PI))
+    );
+}
+
+#[test]
+fn content_mathml_errors() {
+    let options = options(true, false);
+    let parse = |input: &str| Expr::parse_content_mathml(input, &options);
+
+    assert_eq!(parse("<apply><plus/>"), Err(ParseError::UnexpectedEof(Span::new(14, 14))));
+    assert_eq!(parse("<cn>1</ci>"), Err(ParseError::MismatchedTag("ci".to_string(), Span::new(5, 10))));
+    assert_eq!(parse("<mi>x</mi>"), Err(ParseError::InvalidElement("mi".to_string(), Span::new(0, 10))));
+    assert_eq!(parse("<cn>1.2.3</cn>"), Err(ParseError::InvalidNumber("1.2.3".to_string(), Span::new(0, 14))));
+    assert_eq!(parse("<cn>inf</cn>"), Err(ParseError::InvalidNumber("inf".to_string(), Span::new(0, 12))));
+    assert_eq!(parse("<cn>NaN</cn>"), Err(ParseError::InvalidNumber("NaN".to_string(), Span::new(0, 12))));
+    assert_eq!(parse("<cn>1e39</cn>"), Err(ParseError::InvalidNumber("1e39".to_string(), Span::new(0, 13))));
+    assert_eq!(parse("<cn> -1e38 </cn>"), Ok(Expr::Num(-1e38)));
+    assert_eq!(parse("<cn>1</cn> x"), Err(ParseError::UnexpectedChar('x', Span::new(11, 12))));
+    assert_eq!(parse("<ci>&foo;</ci>"), Err(ParseError::UnexpectedChar('&', Span::new(4, 5))));
+    assert_eq!(
+        parse("<apply><power/><cn>2</cn></apply>"),
+        Err(ParseError::WrongNumberOfArgs("power".to_string(), 1, Span::new(0, 33)))
+    );
+    assert_eq!(
+        parse("<apply><divide/><cn>1</cn><cn>0</cn></apply>"),
+        Err(ParseError::EvalError(EvalError::DivisionByZero, Span::new(0, 44)))
+    );
+    assert_eq!(
+        parse("<apply><diff/><bvar><cn>1</cn></bvar><ci>x</ci></apply>"),
+        Err(ParseError::DerivativeNotVariable("1".to_string(), Span::new(14, 37)))
+    );
+    assert_eq!(
+        parse("<piecewise><piece><cn>1</cn><ci>x</ci></piece></piecewise>"),
+        Err(ParseError::InvalidElement("piecewise".to_string(), Span::new(0, 58)))
+    );
+}
+
+#[test]
+fn presentation_mathml() {
+    let options = options(false, true);
+    let render = |input: &str| {
+        let mathml = Expr::parse_with(input, &options).unwrap().to_presentation_mathml();
+        let inner = mathml.strip_prefix(r#"<math xmlns="http://www.w3.org/1998/Math/MathML">"#).unwrap();
+        inner.strip_suffix("</math>").unwrap().to_string()
+    };
+
+    assert_eq!(render("2x^2"), "<mrow><mn>2</mn><mo>&#x2062;</mo><msup><mi>x</mi><mn>2</mn></msup></mrow>");
+    assert_eq!(
+        render("a - (b - c)"),
+        "<mrow><mi>a</mi><mo>-</mo><mrow><mo>(</mo><mrow><mi>b</mi><mo>-</mo><mi>c</mi></mrow><mo>)</mo></mrow></mrow>"
+    );
+    assert_eq!(render("x^(1/3)"), "<mroot><mi>x</mi><mn>3</mn></mroot>");
+    assert_eq!(
+        render("log(2, x)"),
+        "<mrow><msub><mi>log</mi><mn>2</mn></msub><mo>&#x2061;</mo><mrow><mo>(</mo><mi>x</mi><mo>)</mo></mrow></mrow>"
+    );
+    assert_eq!(render("|x| < pi"), "<mrow><mrow><mo>|</mo><mi>x</mi><mo>|</mo></mrow><mo>&lt;</mo><mi>π</mi></mrow>");
+    assert_eq!(
+        render("(x + 1)!"),
+        "<mrow><mrow><mo>(</mo><mrow><mi>x</mi><mo>+</mo><mn>1</mn></mrow><mo>)</mo></mrow><mo>!</mo></mrow>"
+    );
+
+    let mut derivative = Expr::parse("D(x, x^2)", false).unwrap();
+    derivative.substitute("x", Expr::Num(3.0));
+    let mathml = derivative.to_presentation_mathml();
+    assert!(mathml.contains("<mo>|</mo></mrow><mrow><mi>x</mi><mo>=</mo><mn>3</mn></mrow></msub>"), "{}", mathml);
+}
+
+#[test]
+fn content_mathml_random_input_never_panics() {
+    let fragments = [
+        "<apply>", "</apply>", "<plus/>", "<ci>", "</ci>", "<cn>", "</cn>", "x", "1", "<", ">", "/", "&", "&#x3B8;",
+        ";", "<!--", "-->", "<![CDATA[", "]]>", "\"", "=", " ", "<bvar>", "<diff/>", "<lambda>", "é",
+    ];
+    let mut rng = Rng(0x3a7_4d1);
+    let options = options(true, false);
+
+    for _ in 0..2000 {
+        let len = rng.below(12);
+        let input: String = (0..len).map(|_| fragments[rng.below(fragments.len())]).collect();
+        let _ = Expr::parse_content_mathml(&input, &options);
+    }
+}
+
+#[test]
+fn deep_nesting_never_overflows() {
+    let options = ParseOptions::new().with_max_depth(200);
+    let n = 100_000;
+
+    let inputs = [
+        format!("{}x{}", "(".repeat(n), ")".repeat(n)),
+        format!("{}x", "-".repeat(n)),
+        format!("{}2", "2^".repeat(n)),
+        format!("{}1", "1+".repeat(n)),
+        format!("x{}", "!".repeat(n)),
+        format!("{}x{}", "sin(".repeat(n), ")".repeat(n)),
+        format!("{}x{}", "|".repeat(n), "|".repeat(n)),
+    ];
+
+    for input in &inputs {
+        assert!(matches!(Expr::parse_with(input, &options), Err(ParseError::TooDeep(200, _))));
+
+        let (expr, errors) = Expr::parse_recovering(input, &options);
+        assert!(expr.is_none());
+        assert!(errors.iter().any(|err| matches!(err, ParseError::TooDeep(200, _))));
+    }
+
+    let latex = format!("{}x{}", r"\sqrt{".repeat(n), "}".repeat(n));
+    assert!(matches!(Expr::parse_latex(&latex, &options), Err(ParseError::TooDeep(200, _))));
+
+    let mathml = format!("{}<ci>x</ci>{}", "<apply><abs/>".repeat(n), "</apply>".repeat(n));
+    assert!(matches!(Expr::parse_content_mathml(&mathml, &options), Err(ParseError::TooDeep(200, _))));
+}
+
+#[test]
+fn latex_commands_never_overflow() {
+    let options = ParseOptions::default();
+    let n = 100_000;
+
+    let inputs = [
+        r"\frac1".repeat(2000),
+        format!("x{}", "^".repeat(10_000)),
+        r"\sqrt ".repeat(n),
+        r"\sin ".repeat(n),
+        r"\lfloor ".repeat(n),
+        r"\binom{".repeat(n),
+        r"\begin{cases} ".repeat(n),
+        r"{}_{".repeat(n),
+    ];
+
+    for input in &inputs {
+        assert!(matches!(Expr::parse_latex(input, &options), Err(ParseError::TooDeep(128, _))));
+    }
+
+    let latex = format!("{}x", r"\sqrt ".repeat(127));
+    assert!(Expr::parse_latex(&latex, &options).is_ok());
+
+    let options = ParseOptions::new().with_max_depth(50);
+
+    for input in &inputs {
+        assert!(matches!(Expr::parse_latex(input, &options), Err(ParseError::TooDeep(50, _))));
+    }
+}
+
+fn random_expr(rng: &mut Rng, depth: usize) -> Expr {
+    let leaves = 4;
+    let kind = if depth == 0 { rng.below(leaves) } else { rng.below(leaves + 20) };
+    let sub = |rng: &mut Rng| random_expr(rng, depth.saturating_sub(1));
+
+    match kind {
+        0 => Expr::Num([0.0, 1.0, 2.5, 0.1, 1e-7, 123456.0, -3.0, -0.5, 0.5][rng.below(9)]),
+        1 => Expr::Var(["x", "y", "θ", "abc"][rng.below(4)].to_string()),
+        2 => {
+            let (name, value) = Constants::BUILTIN[rng.below(4)];
+            Expr::new_const(name, value)
+        }
+        3 => Expr::new_mul(Expr::Num(-1.0), sub(rng)),
+        4 => Expr::new_add(sub(rng), sub(rng)),
+        5 => Expr::new_sub(sub(rng), sub(rng)),
+        6 => Expr::new_mul(sub(rng), sub(rng)),
+        7 => Expr::new_div(sub(rng), sub(rng)),
+        8 => [Expr::new_mod, Expr::new_floor_div][rng.below(2)](sub(rng), sub(rng)),
+        9 => Expr::new_pow(sub(rng), sub(rng)),
+        10 => Expr::new_log(sub(rng), sub(rng)),
+        11 => [Expr::new_sin, Expr::new_cos, Expr::new_abs, Expr::new_floor, Expr::new_gamma][rng.below(5)](sub(rng)),
+        12 => Expr::new_factorial(sub(rng)),
+        13 => Expr::new_not(sub(rng)),
+        14 => [Expr::new_lt, Expr::new_le, Expr::new_eq, Expr::new_ne][rng.below(4)](sub(rng), sub(rng)),
+        15 => [Expr::new_and, Expr::new_or][rng.below(2)](sub(rng), sub(rng)),
+        16 => Expr::new_cond(sub(rng), sub(rng), sub(rng)),
+        17 => [Expr::new_comb, Expr::new_perm][rng.below(2)](sub(rng), sub(rng)),
+        18 => Expr::new_derivative("x", sub(rng)),
+        19 => Expr::Derivative(Box::new(sub(rng)), "y".to_string(), Some(Box::new(sub(rng)))),
+        20 => Expr::new_pow(sub(rng), Expr::Num(0.5)),
+        21 => Expr::new_log(Expr::new_const("e", std::f32::consts::E), sub(rng)),
+        22 => Expr::new_mul(Expr::Num(2.0), sub(rng)),
+        _ => Expr::new_mul(sub(rng), Expr::Var("x".to_string())),
+    }
+}
+
+#[test]
+fn random_trees_round_trip() {
+    let mut rng = Rng(0x5eed_1234_abcd);
+
+    for _ in 0..5_000 {
+        let expr = random_expr(&mut rng, 5);
+        check_round_trip(&expr);
+        check_implicit_round_trip(&expr);
+        check_syntax_tree(&expr.to_string(), &options(false, true));
+    }
+}
+
+/// Inputs paired with their canonical form
+const FORMAT_CORPUS: &[(&str, &str)] = &[
+    ("((1 + 2) + 3)", "1 + 2 + 3"),
+    ("1 - (2 - 3)", "1 - (2 - 3)"),
+    ("(2 ^ (3 ^ 2))", "2^3^2"),
+    ("((2^3)^2)", "(2^3)^2"),
+    ("-(x)", "-x"),
+    ("-(2)", "-(2)"),
+    ("- - x", "--x"),
+    ("a*(-b)", "a * -b"),
+    ("(x!)!", "x!!"),
+    ("!(a)", "!a"),
+    ("2 (x+1)", "2(x + 1)"),
+    ("2 x", "2x"),
+    ("x y", "x y"),
+    ("(x)(y)", "(x)(y)"),
+    ("(a+b)(a-b)", "(a + b)(a - b)"),
+    ("((0 < x) && (x < 1))", "0 < x && x < 1"),
+    ("((a < b) < c)", "(a < b) < c"),
+    ("(a ? b : (c ? d : e))", "a ? b : c ? d : e"),
+    ("((a ? b : c) ? d : e)", "(a ? b : c) ? d : e"),
+    ("|| x | - 1 |", "||x| - 1|"),
+    ("x²", "x²"),
+    ("x⁻¹", "x⁻¹"),
+    ("√(x+1)", "√(x + 1)"),
+    ("sin( x )^2", "sin(x)^2"),
+    ("log(2,x)", "log(2, x)"),
+];
+
+#[test]
+fn format() {
+    for (input, expected) in FORMAT_CORPUS {
+        let tree = SyntaxTree::parse(input, &options(false, true)).unwrap();
+        assert_eq!(tree.format(), *expected, "{}", input);
+    }
+}
+
+#[test]
+fn format_keeps_comments() {
+    let input = "# area\n pi * ( r^2 ) /* r in meters */ # end";
+    let tree = SyntaxTree::parse(input, &ParseOptions::default()).unwrap();
+
+    assert_eq!(tree.to_string(), input);
+    assert_eq!(tree.format(), "# area\npi * r^2 /* r in meters */ # end");
+}
+
+#[test]
+fn syntax_tree_spans() {
+    let input = "sin(x) + 2";
+    let tree = SyntaxTree::parse(input, &ParseOptions::default()).unwrap();
+    let texts: Vec<_> = tree.tokens().iter().map(|token| token.text).collect();
+
+    assert_eq!(texts, ["sin", "(", "x", ")", "+", "2", ""]);
+    assert_eq!(tree.root.span(), Span::new(0, 10));
+    assert!(matches!(SyntaxTree::parse("sin(x", &ParseOptions::default()), Err(ParseError::UnexpectedEof(_))));
+}
+
+#[test]
+fn semantic_tokens() {
+    use SemanticKind::*;
+
+    let options = ParseOptions::default();
+    let input = "f(x) = x^2 + e; a = f(2) * pi\n|a - ((y))| + sin";
+    let document = Document::new(input, &options);
+    let tokens: Vec<_> = document
+        .semantic_tokens()
+        .into_iter()
+        .map(|token| (&input[token.span.start..token.span.end], token.kind))
+        .collect();
+
+    assert_eq!(tokens[..5], [("f", Function), ("(", Paren { depth: 0 }), ("x", Variable), (")", Paren { depth: 0 }), ("=", Operator)]);
+    assert_eq!(tokens[9..11], [("e", Constant), (";", Operator)]);
+    assert_eq!(tokens[13..17], [("f", Function), ("(", Paren { depth: 0 }), ("2", Number), (")", Paren { depth: 0 })]);
+    assert_eq!(tokens[22..27], [("(", Paren { depth: 0 }), ("(", Paren { depth: 1 }), ("y", Variable), (")", Paren { depth: 1 }), (")", Paren { depth: 0 })]);
+    // Without parentheses a function name is a variable, unless it is implicit multiplication
+    assert_eq!(tokens.last(), Some(&("sin", Variable)));
+
+    let options = options.with_implicit_multiplication(true);
+    let document = Document::new("2sin", &options);
+    assert_eq!(document.semantic_tokens()[1].kind, Function);
+
+    // Unreadable input is skipped
+    let options = ParseOptions::default();
+    let document = Document::new("x $ 1.", &options);
+    assert_eq!(document.semantic_tokens(), [SemanticToken { span: Span::new(0, 1), kind: Variable }]);
+}
+
+#[test]
+fn completions() {
+    let labels = |document: &Document, offset| -> Vec<String> {
+        document.completions(offset).into_iter().map(|completion| completion.label).collect()
+    };
+
+    let mut options = ParseOptions::new().with_allowed_variables(["t"]);
+    options.functions.register(Function::new("clamp", Arity::Fixed(3), |args| Ok(args[0].clamp(args[1], args[2]))));
+    options.define("sq(v) = v^2").unwrap();
+
+    let document = Document::new("c", &options);
+    assert_eq!(labels(&document, 1), ["ceil", "clamp", "cos", "cot"]);
+
+    let clamp = &document.completions(1)[1];
+    assert_eq!(clamp.kind, CompletionKind::Function(Arity::Fixed(3)));
+    assert_eq!(clamp.detail, "clamp(x1, x2, x3)");
+    assert_eq!(clamp.span, Span::new(0, 1));
+
+    let document = Document::new("s", &options);
+    assert_eq!(labels(&document, 1), ["sign", "sin", "sq", "sqrt"]);
+    assert_eq!(document.completions(1)[2].detail, "sq(v)");
+
+    let document = Document::new("ph + t", &options);
+    assert_eq!(document.completions(2)[0].kind, CompletionKind::Constant(1.618_034));
+    assert_eq!(labels(&document, 6), ["t", "tan", "tau", "trunc"]);
+    assert!(!labels(&document, 4).contains(&"ph".to_string()));
+
+    // Parameters only in their definition, bindings only after their statement
+    let input = "g(u, w) = u * w; b = g(1, 2); b + ";
+    let document = Document::new(input, &options);
+    assert!(labels(&document, 12).contains(&"w".to_string()));
+    assert!(!labels(&document, 12).contains(&"b".to_string()));
+    assert!(!labels(&document, input.len()).contains(&"w".to_string()));
+    assert!(labels(&document, input.len()).contains(&"b".to_string()));
+    assert_eq!(document.completions(input.len()).iter().find(|c| c.label == "g").map(|c| c.kind), Some(CompletionKind::Function(Arity::Fixed(2))));
+
+    let options = ParseOptions::new().with_allowed_functions(["sin"]).case_insensitive();
+    let document = Document::new("SI", &options);
+    assert_eq!(labels(&document, 2), ["sin"]);
+}
+
+#[test]
+fn hover() {
+    let mut options = ParseOptions::default();
+    options.functions.register(Function::new("mean", Arity::Variadic { min: 1 }, |args| {
+        Ok(args.iter().sum::<f32>() / args.len() as f32)
+    }));
+
+    let input = "sqrt(4) + 2 * (3 + x) + pi + mean(1, 3)";
+    let document = Document::new(input, &options);
+
+    let hover = document.hover(0).unwrap();
+    assert_eq!(hover.signature.as_deref(), Some("sqrt(x)"));
+    assert_eq!((hover.span, hover.value), (Span::new(0, 7), Some(2.0)));
+
+    // The operator of a constant expression, the number in one that is not constant
+    assert_eq!(document.hover(17), None);
+    assert_eq!(document.hover(15).map(|hover| (hover.span, hover.value)), Some((Span::new(15, 16), Some(3.0))));
+    assert_eq!(document.hover(12), None);
+    assert_eq!(document.hover(25).and_then(|hover| hover.value), Some(std::f32::consts::PI));
+    assert_eq!(document.hover(30).and_then(|hover| hover.signature), Some("mean(x, ...)".to_string()));
+    // Registered functions are not called, they can be slow or impure
+    assert_eq!(document.hover(30).map(|hover| (hover.span, hover.value)), Some((Span::new(29, 33), None)));
+    assert_eq!(document.hover(8), None);
+
+    // Values of operators are known only in an expression, numbers and constants everywhere
+    let document = Document::new("f(x) = 2 + 3; f(e)", &options);
+    assert_eq!(document.hover(9), None);
+    assert_eq!(document.hover(11).and_then(|hover| hover.value), Some(3.0));
+    assert_eq!(document.hover(14).and_then(|hover| hover.signature), Some("f(x)".to_string()));
+    assert_eq!(document.hover(16).and_then(|hover| hover.value), Some(std::f32::consts::E));
+
+    // A range with the bounds swapped accepts no count of arguments, it doesn't panic
+    options.functions.register(Function::new("swapped", Arity::Range { min: 3, max: 1 }, |_| Ok(0.0)));
+    let document = Document::new("swapped(1)", &options);
+    assert_eq!(document.hover(0).map(|hover| hover.value), Some(None));
+}
+
+#[test]
+fn editor_queries_never_panic() {
+    let mut rng = Rng(0xED17_0A5E_1234_5678);
+    let options = options(false, true);
+
+    for _ in 0..2_000 {
+        let input = random_fragments(&mut rng);
+        let document = Document::new(&input, &options);
+        let _ = document.semantic_tokens();
+
+        for offset in 0..=input.len() + 1 {
+            let _ = document.completions(offset);
+            let _ = document.hover(offset);
+        }
+    }
+}
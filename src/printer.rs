@@ -0,0 +1,222 @@
+use crate::expr::{Expr, PREFIX_PRECEDENCE};
+
+/// Options that control how the expression is printed, see `Expr::to_string_with`
+///
+/// All options are disabled by default, which is the form printed by `Display`.
+#[derive(Debug, Clone, Default)]
+pub struct PrintOptions {
+    /// Leave out the spaces around operators and after commas, `2*x+1`
+    pub compact: bool,
+
+    /// Leave out `*` where the implicit multiplication parses the product back, `2x`, `(a + b)(a - b)`
+    pub implicit_multiplication: bool,
+
+    /// Print constants as their values instead of names
+    pub constant_values: bool,
+
+    /// Print `abs(x)` as `|x|`, this is the alternate form of `Display`
+    pub abs_bars: bool,
+}
+
+impl Expr {
+    /// Prints the expression with only the parentheses the precedence requires
+    ///
+    /// ```
+    /// use pemel::prelude::*;
+    ///
+    /// let expr = Expr::parse("(2 * x) + (y ^ 2)", false).unwrap();
+    /// assert_eq!(expr.to_string(), "2 * x + y^2");
+    ///
+    /// let options = PrintOptions {
+    ///     compact: true,
+    ///     implicit_multiplication: true,
+    ///     ..Default::default()
+    /// };
+    /// assert_eq!(expr.to_string_with(&options), "2x+y^2");
+    /// ```
+    ///
+    /// With the default options, the printed expression is parsed back by `Expr::parse(&expr.to_string(), false)`
+    /// to the same tree, if the tree is one the parser can produce.
+    /// With `implicit_multiplication` the parser needs the same option enabled.
+    /// Trees the parser can't produce, like `Expr::Error` or infinite numbers, are printed in a readable form only.
+    pub fn to_string_with(&self, options: &PrintOptions) -> String {
+        Printer { options }.print(self)
+    }
+}
+
+struct Printer<'o> {
+    options: &'o PrintOptions,
+}
+
+impl Printer<'_> {
+    fn print(&self, expr: &Expr) -> String {
+        match expr {
+            Expr::Num(n) => n.to_string(),
+            Expr::Var(name) => name.clone(),
+            Expr::Const(_, value) if self.options.constant_values => value.to_string(),
+            Expr::Const(name, _) => name.clone(),
+            Expr::Error => "<error>".to_string(),
+
+            Expr::Add(lhs, rhs) => self.binop(lhs, "+", rhs, 9),
+            Expr::Sub(lhs, rhs) => self.binop(lhs, "-", rhs, 9),
+            // `-2` is the literal, so negated number is printed as the product
+            Expr::Mul(lhs, rhs) if **lhs == Expr::Num(-1.0) && !matches!(**rhs, Expr::Num(_)) => {
+                format!("-{}", self.operand(rhs, PREFIX_PRECEDENCE + 1))
+            }
+            Expr::Mul(lhs, rhs) => self.product(lhs, rhs),
+            Expr::Div(lhs, rhs) => self.binop(lhs, "/", rhs, 11),
+            Expr::Mod(lhs, rhs) => self.binop(lhs, "%", rhs, 11),
+            Expr::FloorDiv(lhs, rhs) => self.binop(lhs, "//", rhs, 11),
+
+            Expr::Pow(base, exp) if **exp == Expr::Num(0.5) => self.function("sqrt", &[base]),
+            // Right associative, so a power in the base needs parentheses and in the exponent doesn't
+            Expr::Pow(base, exp) => format!("{}^{}", self.operand(base, 16), self.operand(exp, 15)),
+
+            Expr::Log(base, arg) => match base.as_ref() {
+                Expr::Const(name, _) if name == "e" => self.function("ln", &[arg]),
+                Expr::Num(n) if *n == 10.0 => self.function("log", &[arg]),
+                base => self.function("log", &[base, arg]),
+            },
+
+            Expr::Abs(inner) if self.options.abs_bars => format!("|{}|", self.print(inner)),
+            Expr::Sin(inner) => self.function("sin", &[inner]),
+            Expr::Cos(inner) => self.function("cos", &[inner]),
+            Expr::Tan(inner) => self.function("tan", &[inner]),
+            // The angle is written in degrees, like in the input
+            Expr::Degrees(inner) => self.print(inner),
+            Expr::Cot(inner) => self.function("cot", &[inner]),
+            Expr::Abs(inner) => self.function("abs", &[inner]),
+            Expr::Floor(inner) => self.function("floor", &[inner]),
+            Expr::Ceil(inner) => self.function("ceil", &[inner]),
+            Expr::Round(inner) => self.function("round", &[inner]),
+            Expr::Trunc(inner) => self.function("trunc", &[inner]),
+            Expr::Frac(inner) => self.function("frac", &[inner]),
+            Expr::Sign(inner) => self.function("sign", &[inner]),
+            Expr::Gamma(inner) => self.function("gamma", &[inner]),
+            Expr::LnGamma(inner) => self.function("lgamma", &[inner]),
+            Expr::Factorial(inner) => format!("{}!", self.operand(inner, 18)),
+            Expr::Comb(n, k) => self.function("nCr", &[n, k]),
+            Expr::Perm(n, k) => self.function("nPr", &[n, k]),
+
+            // Comparisons are chained by the parser, so a comparison operand always needs parentheses
+            Expr::Lt(lhs, rhs) => self.binop(lhs, "<", rhs, 8),
+            Expr::Le(lhs, rhs) => self.binop(lhs, "<=", rhs, 8),
+            Expr::Eq(lhs, rhs) => self.binop(lhs, "==", rhs, 8),
+            Expr::Ne(lhs, rhs) => self.binop(lhs, "!=", rhs, 8),
+            Expr::Gt(lhs, rhs) => self.binop(lhs, ">", rhs, 8),
+            Expr::Ge(lhs, rhs) => self.binop(lhs, ">=", rhs, 8),
+            Expr::And(lhs, rhs) => self.binop(lhs, "&&", rhs, 5),
+            Expr::Or(lhs, rhs) => self.binop(lhs, "||", rhs, 3),
+            Expr::Not(inner) => format!("!{}", self.operand(inner, PREFIX_PRECEDENCE + 1)),
+
+            // Right associative, nested conditional in the middle branch is in parentheses only for readability
+            Expr::Cond(cond, then, otherwise) => {
+                let (question, colon) = if self.options.compact { ("?", ":") } else { (" ? ", " : ") };
+                let cond = self.operand(cond, 2);
+                let then = self.operand(then, 2);
+                format!("{}{}{}{}{}", cond, question, then, colon, self.print(otherwise))
+            }
+
+            Expr::Derivative(expr, var, sub) => {
+                let var = Expr::Var(var.clone());
+                match sub {
+                    None => self.function("D", &[&var, expr]),
+                    Some(sub) => self.function("D", &[&var, expr, sub]),
+                }
+            }
+
+            Expr::Call(func, args) => {
+                let args: Vec<&Expr> = args.iter().collect();
+                self.function(func.name(), &args)
+            }
+        }
+    }
+
+    /// Precedence of the printed form, constant printed as a negative value binds like a negative number
+    fn precedence(&self, expr: &Expr) -> u8 {
+        match expr {
+            Expr::Const(_, value) if self.options.constant_values => Expr::Num(*value).precedence(),
+            Expr::Degrees(inner) => self.precedence(inner),
+            expr => expr.precedence(),
+        }
+    }
+
+    /// Returns true for negation and negative numbers
+    fn is_negative(&self, expr: &Expr) -> bool {
+        self.precedence(expr) == PREFIX_PRECEDENCE && !matches!(expr, Expr::Not(_))
+    }
+
+    /// Left associative binary operator, `precedence` is the level of the operator
+    ///
+    /// The right operand on the same level needs parentheses, so `a - (b - c)` keeps them.
+    /// Negative right operand is in parentheses for readability, `a - (-b)` instead of `a - -b`.
+    fn binop(&self, lhs: &Expr, op: &str, rhs: &Expr, precedence: u8) -> String {
+        let lhs = self.operand(lhs, precedence);
+        let rhs = if self.is_negative(rhs) {
+            format!("({})", self.print(rhs))
+        } else {
+            self.operand(rhs, precedence + 1)
+        };
+
+        if !self.options.compact {
+            format!("{} {} {}", lhs, op, rhs)
+        } else if lhs.ends_with('!') && op.starts_with('=') {
+            // `x!==y` would be read as `x != = y`
+            format!("{} {}{}", lhs, op, rhs)
+        } else {
+            format!("{}{}{}", lhs, op, rhs)
+        }
+    }
+
+    /// Prints the product, without `*` if the implicit multiplication reads it back the same
+    fn product(&self, lhs: &Expr, rhs: &Expr) -> String {
+        // `1/2x` would be read correctly as `(1/2) * x`, but it looks like `1 / (2x)`
+        let is_lhs_juxtaposable = !matches!(lhs, Expr::Div(_, _) | Expr::Mod(_, _) | Expr::FloorDiv(_, _));
+
+        if !(self.options.implicit_multiplication && is_lhs_juxtaposable) || matches!(rhs, Expr::Num(_)) {
+            return self.binop(lhs, "*", rhs, 11);
+        }
+
+        let lhs_str = self.operand(lhs, 11);
+        let rhs_str = if self.is_negative(rhs) {
+            format!("({})", self.print(rhs))
+        } else {
+            self.operand(rhs, 12)
+        };
+
+        // The implicit multiplication is inserted only before an identifier or `(`
+        let Some(first) = rhs_str.chars().next() else {
+            return self.binop(lhs, "*", rhs, 11);
+        };
+
+        if first == '(' {
+            format!("{}{}", lhs_str, rhs_str)
+        } else if !first.is_alphabetic() {
+            self.binop(lhs, "*", rhs, 11)
+        } else if matches!(lhs, Expr::Num(_)) && !continues_number(&lhs_str, first) {
+            format!("{}{}", lhs_str, rhs_str)
+        } else {
+            format!("{} {}", lhs_str, rhs_str)
+        }
+    }
+
+    /// Prints the operand, in parentheses if it binds looser than `min_precedence`
+    fn operand(&self, expr: &Expr, min_precedence: u8) -> String {
+        if self.precedence(expr) < min_precedence {
+            format!("({})", self.print(expr))
+        } else {
+            self.print(expr)
+        }
+    }
+
+    fn function(&self, name: &str, args: &[&Expr]) -> String {
+        let separator = if self.options.compact { "," } else { ", " };
+        let args: Vec<String> = args.iter().map(|arg| self.print(arg)).collect();
+        format!("{}({})", name, args.join(separator))
+    }
+}
+
+/// Returns true if the letter would be read as a part of the number literal, like in `2e3` or `0x1`
+fn continues_number(number: &str, letter: char) -> bool {
+    matches!(letter, 'e' | 'E') || (number == "0" && matches!(letter, 'x' | 'X' | 'b' | 'B'))
+}
@@ -0,0 +1,93 @@
+use std::fmt::Display;
+
+use crate::eval_error::EvalError;
+use crate::expr::Expr;
+use crate::parser::{self, ParseError, ParseOptions};
+
+/// Script of statements separated by `;` or new lines, like `a = 2; b = a * x; b^2 + a`
+///
+/// Every statement but the last one is a binding `name = expr` or a function definition `f(x) = expr`,
+/// the last statement is the expression whose value is the result of the program.
+///
+/// Later statements see the earlier bindings, a name can be bound again, so `x = x + 1; x^2` is `(x + 1)^2`.
+/// Functions defined in the program can be used only in it.
+///
+/// ```
+/// use pemel::prelude::*;
+///
+/// let program = Program::parse("a = 2; b = a * x; b^2 + a", false).unwrap();
+/// assert_eq!(program.eval_with(&[("x", 3.0)]), Ok(38.0));
+///
+/// let expr = program.inline();
+/// assert_eq!(expr.eval_with(&[("x", 3.0)]), Ok(38.0));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    /// Bindings in the order they are written
+    pub bindings: Vec<(String, Expr)>,
+    pub result: Expr,
+}
+
+impl Program {
+    /// Parse the program from a string
+    ///
+    /// Like `Expr::parse`, it can overflow the stack on deeply nested input, see `Program::parse_with`
+    /// with `ParseOptions::max_depth` for untrusted input.
+    pub fn parse(input: &str, implicit_evaluation: bool) -> Result<Program, ParseError> {
+        let options = ParseOptions {
+            implicit_evaluation,
+            ..Default::default()
+        };
+
+        Self::parse_with(input, &options)
+    }
+
+    /// Parse the program from a string with the given options
+    pub fn parse_with(input: &str, options: &ParseOptions) -> Result<Program, ParseError> {
+        options.check_length(input)?;
+        let tokens = options.lexer(input).collect::<Result<_, _>>()?;
+        parser::parse_program(tokens, options)
+    }
+
+    /// Evaluate the program with the given values of the variables, see `Expr::eval_with`
+    ///
+    /// Each binding is evaluated once, bound names take precedence over the given values.
+    pub fn eval_with(&self, values: &[(&str, f32)]) -> Result<f32, EvalError> {
+        self.eval_with_budget(values, usize::MAX)
+    }
+
+    /// Evaluate the program like `eval_with`, the bindings and the result share the budget of `Expr::eval_with_budget`
+    pub fn eval_with_budget(&self, values: &[(&str, f32)], budget: usize) -> Result<f32, EvalError> {
+        let mut values = values.to_vec();
+        let mut steps = budget;
+
+        for (name, expr) in &self.bindings {
+            let value = expr.eval_steps(&values, &mut steps)?;
+            // The first matching value is used, so the latest binding goes to the front
+            values.insert(0, (name, value));
+        }
+
+        self.result.eval_steps(&values, &mut steps)
+    }
+
+    /// Returns the result with all bindings substituted, a single expression equivalent to the program
+    pub fn inline(&self) -> Expr {
+        let mut expr = self.result.clone();
+
+        for (name, value) in self.bindings.iter().rev() {
+            expr.substitute(name, value.clone());
+        }
+
+        expr
+    }
+}
+
+impl Display for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, expr) in &self.bindings {
+            write!(f, "{} = {}; ", name, expr)?;
+        }
+
+        write!(f, "{}", self.result)
+    }
+}
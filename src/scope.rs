@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+
+use crate::expr::Expr;
+use crate::names::CaseIndex;
+use crate::parser::{self, ParseError, ParseOptions};
+
+/// Function defined in the expression language, like `f(x) = x^2 + 1`
+///
+/// Calls of the function are replaced by its body during parsing,
+/// with the parameters substituted by the arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definition {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Expr,
+}
+
+impl Definition {
+    /// Parse the definition in the form `name(param, ...) = body`
+    ///
+    /// The body can call functions already defined in `options.scope`, but not the function itself.
+    pub fn parse(input: &str, options: &ParseOptions) -> Result<Definition, ParseError> {
+        options.check_length(input)?;
+        parser::parse_definition(options.lexer(input), options)
+    }
+
+    /// Returns the body with parameters replaced by the arguments
+    ///
+    /// All parameters are replaced at once, so `f(x, y) = x - y` called as `f(y, x)` is `y - x`.
+    ///
+    /// The number of arguments must be the same as the number of parameters.
+    pub fn apply(&self, args: Vec<Expr>) -> Expr {
+        let mut body = self.body.clone();
+
+        // Parameters are first renamed to names that can't be parsed,
+        // so they don't collide with variables used in the arguments
+        for (i, param) in self.params.iter().enumerate() {
+            body.substitute(param, Expr::Var(placeholder(i)));
+        }
+
+        for (i, arg) in args.into_iter().enumerate() {
+            body.substitute(&placeholder(i), arg);
+        }
+
+        body
+    }
+}
+
+fn placeholder(index: usize) -> String {
+    format!("#{}", index)
+}
+
+/// Functions defined in the expression language, see `Definition`
+///
+/// ```
+/// use pemel::prelude::*;
+///
+/// let mut options = ParseOptions::default();
+/// options.define("f(x) = x^2 + 1").unwrap();
+/// options.define("g(x, y) = f(x) * y").unwrap();
+///
+/// let expr = Expr::parse_with("g(2, y)", &options).unwrap();
+/// assert_eq!(expr.eval_with_var("y", 3.0), Ok(15.0));
+/// ```
+///
+/// Names of the built-in functions can't be redefined, they take precedence over the definitions.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Scope {
+    definitions: HashMap<String, Definition>,
+    names: CaseIndex,
+}
+
+impl Scope {
+    /// Inserts the definition, replacing the previous one with the same name
+    ///
+    /// Functions that were defined using the replaced one keep its old body.
+    pub fn insert(&mut self, definition: Definition) {
+        self.names.insert(&definition.name);
+        self.definitions.insert(definition.name.clone(), definition);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Definition> {
+        self.names.remove(name);
+        self.definitions.remove(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Definition> {
+        self.definitions.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Definition> {
+        self.definitions.values()
+    }
+
+    /// Returns the name of the definition with the given lowercase name, for the case insensitive parsing
+    pub(crate) fn get_ignore_case(&self, lowercase: &str) -> Option<&str> {
+        self.names.get(lowercase)
+    }
+}
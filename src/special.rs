@@ -0,0 +1,156 @@
+//! Gamma function and combinatorics used by `Expr::Factorial`, `Expr::Gamma` and friends
+//!
+//! The computations are done in `f64` and rounded to `f32` at the end.
+
+use std::f64::consts::PI;
+
+use crate::eval_error::EvalError;
+
+/// Coefficients of the Lanczos approximation with `g = 7`
+const LANCZOS_G: f64 = 7.0;
+const LANCZOS: [f64; 9] = [
+    0.999_999_999_999_809_9,
+    676.520_368_121_885_1,
+    -1_259.139_216_722_402_8,
+    771.323_428_777_653_1,
+    -176.615_029_162_140_6,
+    12.507_343_278_686_905,
+    -0.138_571_095_265_720_12,
+    9.984_369_578_019_572e-6,
+    1.505_632_735_149_311_6e-7,
+];
+
+/// Largest `n` whose factorial fits into `f32`
+const MAX_EXACT_FACTORIAL: f64 = 34.0;
+
+fn is_non_positive_integer(x: f64) -> bool {
+    x <= 0.0 && x.fract() == 0.0
+}
+
+/// Gamma function, `gamma(n + 1) = n!`
+///
+/// Non-positive integers are poles of the function and return `EvalError::InvalidFactorial`.
+pub(crate) fn gamma(x: f32) -> Result<f32, EvalError> {
+    let x = x as f64;
+
+    if is_non_positive_integer(x) {
+        return Err(EvalError::InvalidFactorial);
+    }
+
+    // Small positive integers are computed exactly
+    if x.fract() == 0.0 && x - 1.0 <= MAX_EXACT_FACTORIAL {
+        return Ok((1..x as u32).map(f64::from).product::<f64>() as f32);
+    }
+
+    Ok(gamma_f64(x) as f32)
+}
+
+/// Natural logarithm of the absolute value of the gamma function
+///
+/// Unlike `gamma`, it doesn't overflow for large arguments.
+pub(crate) fn ln_gamma(x: f32) -> Result<f32, EvalError> {
+    let x = x as f64;
+
+    if is_non_positive_integer(x) {
+        return Err(EvalError::InvalidFactorial);
+    }
+
+    Ok(ln_gamma_f64(x) as f32)
+}
+
+/// Factorial extended to the reals, `x! = gamma(x + 1)`
+pub(crate) fn factorial(x: f32) -> Result<f32, EvalError> {
+    gamma(x + 1.0)
+}
+
+/// Number of ways to choose `k` items out of `n` without order, `n! / (k! (n - k)!)`
+///
+/// Both arguments must be non-negative integers, it is `0` if `k > n`.
+pub(crate) fn combinations(n: f32, k: f32) -> Result<f32, EvalError> {
+    let (n, k) = combinatorics_args(n, k)?;
+
+    if k > n {
+        return Ok(0.0);
+    }
+
+    // The product is shorter and more precise with the smaller of `k` and `n - k`
+    let k = k.min(n - k);
+    let mut result = 1.0;
+
+    // Every partial product is a binomial coefficient too and they only grow up to `k = n / 2`,
+    // so once one is too large for `f32` the loop stops, even for `n` like `1e12`
+    for i in 0..k as u64 {
+        result = result * (n - i as f64) / (i as f64 + 1.0);
+
+        if result > f32::MAX as f64 {
+            return Ok(f32::INFINITY);
+        }
+    }
+
+    Ok(result.round() as f32)
+}
+
+/// Number of ways to choose `k` items out of `n` with order, `n! / (n - k)!`
+///
+/// Both arguments must be non-negative integers, it is `0` if `k > n`.
+pub(crate) fn permutations(n: f32, k: f32) -> Result<f32, EvalError> {
+    let (n, k) = combinatorics_args(n, k)?;
+
+    if k > n {
+        return Ok(0.0);
+    }
+
+    let mut result = 1.0;
+
+    // Every factor is at least `1`, the loop stops once the product is too large for `f32`
+    for i in 0..k as u64 {
+        result *= n - i as f64;
+
+        if result > f32::MAX as f64 {
+            return Ok(f32::INFINITY);
+        }
+    }
+
+    Ok(result as f32)
+}
+
+fn combinatorics_args(n: f32, k: f32) -> Result<(f64, f64), EvalError> {
+    let is_valid = |x: f32| x >= 0.0 && x.fract() == 0.0;
+
+    if is_valid(n) && is_valid(k) {
+        Ok((n as f64, k as f64))
+    } else {
+        Err(EvalError::InvalidCombination)
+    }
+}
+
+fn gamma_f64(x: f64) -> f64 {
+    // Reflection formula, the approximation is valid only for `x >= 0.5`
+    if x < 0.5 {
+        return PI / ((PI * x).sin() * gamma_f64(1.0 - x));
+    }
+
+    let (t, sum) = lanczos(x);
+    (2.0 * PI).sqrt() * t.powf(x - 0.5) * (-t).exp() * sum
+}
+
+fn ln_gamma_f64(x: f64) -> f64 {
+    if x < 0.5 {
+        return (PI / (PI * x).sin().abs()).ln() - ln_gamma_f64(1.0 - x);
+    }
+
+    let (t, sum) = lanczos(x);
+    0.5 * (2.0 * PI).ln() + (x - 0.5) * t.ln() - t + sum.ln()
+}
+
+/// Returns the base of the power and the series of the Lanczos approximation for `gamma(x)`
+fn lanczos(x: f64) -> (f64, f64) {
+    let x = x - 1.0;
+    let t = x + LANCZOS_G + 0.5;
+    let sum = LANCZOS[1..]
+        .iter()
+        .enumerate()
+        .fold(LANCZOS[0], |acc, (i, c)| acc + c / (x + i as f64 + 1.0));
+
+    (t, sum)
+}